@@ -0,0 +1,37 @@
+use minijinja::value::{Value, ValueKind};
+use minijinja::{context, escape_formatter, Environment, Error, Output, State};
+
+/// Formats floats with two digits after the decimal point.
+///
+/// All other values are handed to the default formatter so that auto
+/// escaping keeps working as usual.
+fn float_formatter(out: &mut Output, state: &State, value: &Value) -> Result<(), Error> {
+    if value.kind() == ValueKind::Number && i64::try_from(value.clone()).is_err() {
+        if let Ok(f) = f64::try_from(value.clone()) {
+            return escape_formatter(out, state, &Value::from(format!("{f:.2}")));
+        }
+    }
+    escape_formatter(out, state, value)
+}
+
+fn main() {
+    let mut env = Environment::new();
+    env.set_formatter(float_formatter);
+    env.add_template(
+        "report.html",
+        "<p>{{ name }}: {{ total }} items for {{ price }} each ({{ ratio }})</p>",
+    )
+    .unwrap();
+    let template = env.get_template("report.html").unwrap();
+    println!(
+        "{}",
+        template
+            .render(context! {
+                name => "Tom & Jerry",
+                total => 42,
+                price => 1.0 / 3.0,
+                ratio => 0.1 + 0.2,
+            })
+            .unwrap()
+    );
+}
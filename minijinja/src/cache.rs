@@ -0,0 +1,218 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use crate::compiler::parser::ParserConfig;
+use crate::error::Error;
+use crate::source::{load_template, LoadedTemplate};
+use crate::template::CompiledTemplate;
+
+/// A compiled template stored in a [`CompiledCache`].
+///
+/// The template owns its name and source.  Cloning it is cheap as the
+/// compiled template is reference counted.
+#[derive(Clone)]
+#[cfg_attr(docsrs, doc(cfg(feature = "source")))]
+pub struct CachedTemplate {
+    loaded: Arc<LoadedTemplate>,
+}
+
+impl fmt::Debug for CachedTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedTemplate")
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+impl CachedTemplate {
+    /// Returns the name of the template.
+    pub fn name(&self) -> &str {
+        &self.loaded.borrow_owner().0
+    }
+
+    /// Returns the source of the template.
+    pub fn source(&self) -> &str {
+        &self.loaded.borrow_owner().1
+    }
+
+    pub(crate) fn compiled(&self) -> &CompiledTemplate<'_> {
+        self.loaded.borrow_dependent()
+    }
+
+    pub(crate) fn matches(&self, name: &str, source: &str) -> bool {
+        self.name() == name && self.source() == source
+    }
+}
+
+/// A cache for compiled templates that can be shared between environments.
+///
+/// The cache is consulted by
+/// [`Environment::template_from_named_str`](crate::Environment::template_from_named_str)
+/// and [`Environment::render_named_str`](crate::Environment::render_named_str)
+/// (as well as their unnamed counterparts) once it was set with
+/// [`Environment::set_compiled_cache`](crate::Environment::set_compiled_cache).
+/// Entries are keyed by a hash of the template name, the source and the
+/// syntax settings of the environment.  The engine verifies that the name and
+/// source of a returned template match, so hash collisions are harmless.
+///
+/// [`LruCompiledCache`] is the default implementation.
+#[cfg_attr(docsrs, doc(cfg(feature = "source")))]
+pub trait CompiledCache: Send + Sync {
+    /// Returns the template stored for the given key.
+    fn get(&self, key: u64) -> Option<CachedTemplate>;
+
+    /// Stores a template under the given key.
+    fn insert(&self, key: u64, template: CachedTemplate);
+}
+
+/// An in-memory [`CompiledCache`] that evicts the least recently used entry.
+///
+/// ```
+/// # use std::sync::Arc;
+/// # use minijinja::{Environment, LruCompiledCache, context};
+/// let cache = Arc::new(LruCompiledCache::new(64));
+/// for _ in 0..3 {
+///     let mut env = Environment::new();
+///     env.set_compiled_cache(cache.clone());
+///     let rv = env.render_str("Hello {{ name }}!", context!(name => "World"));
+///     assert_eq!(rv.unwrap(), "Hello World!");
+/// }
+/// assert_eq!(cache.len(), 1);
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "source")))]
+pub struct LruCompiledCache {
+    capacity: usize,
+    state: Mutex<LruState>,
+}
+
+#[derive(Default)]
+struct LruState {
+    entries: HashMap<u64, (u64, CachedTemplate)>,
+    tick: u64,
+}
+
+impl fmt::Debug for LruCompiledCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LruCompiledCache")
+            .field("capacity", &self.capacity)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl LruCompiledCache {
+    /// Creates a cache that holds up to `capacity` templates.
+    pub fn new(capacity: usize) -> LruCompiledCache {
+        LruCompiledCache {
+            capacity,
+            state: Mutex::new(LruState::default()),
+        }
+    }
+
+    /// Returns the number of cached templates.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    /// Returns `true` if no templates are cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all templates from the cache.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().entries.clear();
+    }
+}
+
+impl CompiledCache for LruCompiledCache {
+    fn get(&self, key: u64) -> Option<CachedTemplate> {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        state.entries.get_mut(&key).map(|entry| {
+            entry.0 = tick;
+            entry.1.clone()
+        })
+    }
+
+    fn insert(&self, key: u64, template: CachedTemplate) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.entries.len() >= self.capacity && !state.entries.contains_key(&key) {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.0)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.entries.insert(key, (tick, template));
+    }
+}
+
+/// Returns the cache key for a template.
+fn cache_key(name: &str, source: &str, parser_config: ParserConfig) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    source.hash(&mut hasher);
+    parser_config.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Looks up a template in the cache and compiles it on a miss.
+pub(crate) fn get_or_compile(
+    cache: &dyn CompiledCache,
+    name: &str,
+    source: &str,
+    parser_config: ParserConfig,
+) -> Result<CachedTemplate, Error> {
+    let key = cache_key(name, source, parser_config);
+    if let Some(cached) = cache.get(key) {
+        if cached.matches(name, source) {
+            return Ok(cached);
+        }
+    }
+    let cached = CachedTemplate {
+        loaded: ok!(load_template(
+            name.to_string(),
+            source.to_string(),
+            parser_config
+        )),
+    };
+    cache.insert(key, cached.clone());
+    Ok(cached)
+}
+
+#[test]
+fn test_environment_does_not_retain_cached_templates() {
+    let cache = Arc::new(LruCompiledCache::new(4));
+    let mut env = crate::Environment::new();
+    env.set_compiled_cache(cache.clone());
+    for idx in 0..100 {
+        let source = format!("{{{{ x }}}}-{idx}");
+        let rv = env.render_str(&source, crate::context!(x => idx)).unwrap();
+        assert_eq!(rv, format!("{idx}-{idx}"));
+        let tmpl = env.template_from_str(&source).unwrap();
+        assert_eq!(
+            tmpl.render(crate::context!(x => 0)).unwrap(),
+            format!("0-{idx}")
+        );
+    }
+
+    // only the cache holds on to the remaining compiled templates
+    let state = cache.state.lock().unwrap();
+    assert_eq!(state.entries.len(), 4);
+    for (_, cached) in state.entries.values() {
+        assert_eq!(Arc::strong_count(&cached.loaded), 1);
+    }
+}
@@ -261,6 +261,7 @@ pub struct Extends<'a> {
 pub struct Include<'a> {
     pub name: Expr<'a>,
     pub ignore_missing: bool,
+    pub with_context: bool,
 }
 
 /// An auto escape control block.
@@ -2,7 +2,8 @@ use std::collections::BTreeMap;
 
 use crate::compiler::ast;
 use crate::compiler::instructions::{
-    Instruction, Instructions, LocalId, LOOP_FLAG_RECURSIVE, LOOP_FLAG_WITH_LOOP_VAR, MAX_LOCALS,
+    Instruction, Instructions, LocalId, LOOP_FLAG_RECURSIVE, LOOP_FLAG_UNPACK_PAIRS,
+    LOOP_FLAG_WITH_LOOP_VAR, MAX_LOCALS,
 };
 use crate::compiler::tokens::Span;
 use crate::output::CaptureMode;
@@ -50,6 +51,8 @@ pub struct CodeGenerator<'source> {
     raw_template_bytes: usize,
     #[cfg(feature = "multi_template")]
     has_extends: bool,
+    #[cfg(feature = "multi_template")]
+    static_extends: Option<String>,
 }
 
 impl<'source> CodeGenerator<'source> {
@@ -66,6 +69,8 @@ impl<'source> CodeGenerator<'source> {
             raw_template_bytes: 0,
             #[cfg(feature = "multi_template")]
             has_extends: false,
+            #[cfg(feature = "multi_template")]
+            static_extends: None,
         }
     }
 
@@ -129,14 +134,9 @@ impl<'source> CodeGenerator<'source> {
     }
 
     /// Starts a for loop
-    pub fn start_for_loop(&mut self, with_loop_var: bool, recursive: bool) {
-        let mut flags = 0;
-        if with_loop_var {
-            flags |= LOOP_FLAG_WITH_LOOP_VAR;
-        }
-        if recursive {
-            flags |= LOOP_FLAG_RECURSIVE;
-        }
+    ///
+    /// The flags are a combination of the `LOOP_FLAG_*` constants.
+    pub fn start_for_loop(&mut self, flags: u8) {
         self.add(Instruction::PushLoop(flags));
         let iter_instr = self.add(Instruction::Iterate(!0));
         self.pending_block.push(PendingBlock::Loop(iter_instr));
@@ -239,6 +239,18 @@ impl<'source> CodeGenerator<'source> {
                 }
                 #[cfg(feature = "multi_template")]
                 {
+                    // only an extends tag outside of other tags always runs
+                    self.static_extends = t
+                        .children
+                        .iter()
+                        .find_map(|node| match node {
+                            ast::Stmt::Extends(extends) => Some(extends),
+                            _ => None,
+                        })
+                        .and_then(|extends| match extends.name {
+                            ast::Expr::Const(ref c) => c.value.as_str().map(|x| x.to_string()),
+                            _ => None,
+                        });
                     if self.has_extends {
                         self.add(Instruction::RenderParent);
                     }
@@ -315,7 +327,7 @@ impl<'source> CodeGenerator<'source> {
                 self.add(Instruction::BeginCapture(CaptureMode::Discard));
                 self.add(Instruction::PushWith);
                 self.compile_expr(&import.expr);
-                self.add_with_span(Instruction::Include(false), import.span());
+                self.add_with_span(Instruction::Include(false, true), import.span());
                 self.add(Instruction::ExportLocals);
                 self.add(Instruction::PopFrame);
                 self.compile_assignment(&import.name);
@@ -326,7 +338,7 @@ impl<'source> CodeGenerator<'source> {
                 self.add(Instruction::BeginCapture(CaptureMode::Discard));
                 self.add(Instruction::PushWith);
                 self.compile_expr(&from_import.expr);
-                self.add_with_span(Instruction::Include(false), from_import.span());
+                self.add_with_span(Instruction::Include(false, true), from_import.span());
                 for (name, _) in &from_import.names {
                     self.compile_expr(name);
                 }
@@ -347,7 +359,10 @@ impl<'source> CodeGenerator<'source> {
             ast::Stmt::Include(include) => {
                 self.set_line_from_span(include.span());
                 self.compile_expr(&include.name);
-                self.add_with_span(Instruction::Include(include.ignore_missing), include.span());
+                self.add_with_span(
+                    Instruction::Include(include.ignore_missing, include.with_context),
+                    include.span(),
+                );
             }
             #[cfg(feature = "macros")]
             ast::Stmt::Macro(macro_decl) => {
@@ -492,13 +507,18 @@ impl<'source> CodeGenerator<'source> {
 
     fn compile_for_loop(&mut self, for_loop: &ast::Spanned<ast::ForLoop<'source>>) {
         self.set_line_from_span(for_loop.span());
+        // `for key, value in map` iterates over the items of a map.
+        let pair_flags = match for_loop.target {
+            ast::Expr::List(ref list) if list.items.len() == 2 => LOOP_FLAG_UNPACK_PAIRS,
+            _ => 0,
+        };
         if let Some(ref filter_expr) = for_loop.filter_expr {
             // filter expressions work like a nested for loop without
             // the special loop variable that append into a new list
             // just outside of the loop.
             self.add(Instruction::BuildList(0));
             self.compile_expr(&for_loop.iter);
-            self.start_for_loop(false, false);
+            self.start_for_loop(pair_flags);
             self.add(Instruction::DupTop);
             self.compile_assignment(&for_loop.target);
             self.compile_expr(filter_expr);
@@ -511,7 +531,11 @@ impl<'source> CodeGenerator<'source> {
         } else {
             self.compile_expr(&for_loop.iter);
         }
-        self.start_for_loop(true, for_loop.recursive);
+        let mut flags = LOOP_FLAG_WITH_LOOP_VAR | pair_flags;
+        if for_loop.recursive {
+            flags |= LOOP_FLAG_RECURSIVE;
+        }
+        self.start_for_loop(flags);
         self.compile_assignment(&for_loop.target);
         for node in &for_loop.body {
             self.compile_stmt(node);
@@ -809,6 +833,15 @@ impl<'source> CodeGenerator<'source> {
         (self.raw_template_bytes * 2).next_power_of_two()
     }
 
+    /// Returns the name of the template that is always extended.
+    ///
+    /// This is only known for an `{% extends %}` tag with a constant name that
+    /// is not nested in another tag.
+    #[cfg(feature = "multi_template")]
+    pub fn static_extends(&self) -> Option<&str> {
+        self.static_extends.as_deref()
+    }
+
     /// Converts the compiler into the instructions.
     pub fn finish(
         self,
@@ -11,6 +11,9 @@ pub const LOOP_FLAG_WITH_LOOP_VAR: u8 = 1;
 /// This loop is recursive.
 pub const LOOP_FLAG_RECURSIVE: u8 = 2;
 
+/// This loop unpacks into two targets and iterates over map items.
+pub const LOOP_FLAG_UNPACK_PAIRS: u8 = 4;
+
 /// This macro is self referential.
 #[cfg(feature = "macros")]
 pub const MACRO_SELF_REFERENTIAL: u8 = 1;
@@ -212,8 +215,10 @@ pub enum Instruction<'source> {
     RenderParent,
 
     /// Includes another template.
+    ///
+    /// The flags are `ignore missing` and if the context is passed on.
     #[cfg(feature = "multi_template")]
-    Include(bool),
+    Include(bool, bool),
 
     /// Builds a module
     #[cfg(feature = "multi_template")]
@@ -17,6 +17,7 @@ struct TokenizerState<'s> {
     failed: bool,
     current_line: u32,
     current_col: u32,
+    current_offset: u32,
 }
 
 fn find_marker(a: &str) -> Option<(usize, bool)> {
@@ -71,6 +72,37 @@ fn lex_identifier(s: &str) -> usize {
         .count()
 }
 
+/// Finds the end of a comment.
+///
+/// The input is expected to start with the opening `{#`.  The returned
+/// offset points to the `#}` that closes the comment.  If `nested` is
+/// enabled, `{#` within the comment opens a nested comment which needs to
+/// be closed separately.
+fn find_comment_end(a: &str, nested: bool) -> Option<usize> {
+    let bytes = a.as_bytes();
+    if !nested {
+        return memstr(bytes, b"#}");
+    }
+    let mut depth = 0usize;
+    let mut offset = 2;
+    while let Some(idx) = memchr(&bytes[offset..], b'#') {
+        let pos = offset + idx;
+        if bytes.get(pos + 1) == Some(&b'}') {
+            if depth == 0 {
+                return Some(pos);
+            }
+            depth -= 1;
+            offset = pos + 2;
+        } else {
+            if bytes[pos - 1] == b'{' {
+                depth += 1;
+            }
+            offset = pos + 1;
+        }
+    }
+    None
+}
+
 fn skip_basic_tag(block_str: &str, name: &str) -> Option<(usize, bool)> {
     let mut ptr = block_str;
     let mut trim = false;
@@ -114,22 +146,25 @@ impl<'s> TokenizerState<'s> {
                 _ => self.current_col += 1,
             }
         }
+        self.current_offset += bytes as u32;
         self.rest = new_rest;
         skipped
     }
 
     #[inline(always)]
-    fn loc(&self) -> (u32, u32) {
-        (self.current_line, self.current_col)
+    fn loc(&self) -> (u32, u32, u32) {
+        (self.current_line, self.current_col, self.current_offset)
     }
 
-    fn span(&self, start: (u32, u32)) -> Span {
-        let (start_line, start_col) = start;
+    fn span(&self, start: (u32, u32, u32)) -> Span {
+        let (start_line, start_col, start_offset) = start;
         Span {
             start_line,
             start_col,
             end_line: self.current_line,
             end_col: self.current_col,
+            start_offset,
+            end_offset: self.current_offset,
         }
     }
 
@@ -246,9 +281,19 @@ impl<'s> TokenizerState<'s> {
 }
 
 /// Tokenizes the source.
+#[cfg(any(test, feature = "unstable_machinery"))]
 pub fn tokenize(
     input: &str,
     in_expr: bool,
+) -> impl Iterator<Item = Result<(Token<'_>, Span), Error>> {
+    tokenize_with_options(input, in_expr, false)
+}
+
+/// Tokenizes the source optionally allowing nested comments.
+pub(crate) fn tokenize_with_options(
+    input: &str,
+    in_expr: bool,
+    nested_comments: bool,
 ) -> impl Iterator<Item = Result<(Token<'_>, Span), Error>> {
     let mut state = TokenizerState {
         rest: input,
@@ -260,6 +305,7 @@ pub fn tokenize(
         failed: false,
         current_line: 1,
         current_col: 0,
+        current_offset: 0,
     };
     let mut trim_leading_whitespace = false;
 
@@ -284,15 +330,23 @@ pub fn tokenize(
                     Some("{%") => {
                         // raw blocks require some special handling.  If we are at the beginning of a raw
                         // block we want to skip everything until {% endraw %} completely ignoring iterior
-                        // syntax and emit the entire raw block as TemplateData.
-                        if let Some((mut ptr, _)) = skip_basic_tag(&state.rest[2..], "raw") {
-                            ptr += 2;
+                        // syntax and emit the contents of the raw block as TemplateData.
+                        if let Some((raw, trim_start)) = skip_basic_tag(&state.rest[2..], "raw") {
+                            let body_start = raw + 2;
+                            let mut ptr = body_start;
                             while let Some(block) = memstr(&state.rest.as_bytes()[ptr..], b"{%") {
-                                ptr += block + 2;
+                                let body_end = ptr + block;
+                                ptr = body_end + 2;
                                 if let Some((endraw, trim)) =
                                     skip_basic_tag(&state.rest[ptr..], "endraw")
                                 {
-                                    let result = &state.rest[..ptr + endraw];
+                                    let mut result = &state.rest[body_start..body_end];
+                                    if trim_start {
+                                        result = result.trim_start();
+                                    }
+                                    if state.rest.as_bytes().get(ptr) == Some(&b'-') {
+                                        result = result.trim_end();
+                                    }
                                     state.advance(ptr + endraw);
                                     trim_leading_whitespace = trim;
                                     return Some(Ok((
@@ -314,7 +368,7 @@ pub fn tokenize(
                         return Some(Ok((Token::BlockStart, state.span(old_loc))));
                     }
                     Some("{#") => {
-                        if let Some(comment_end) = memstr(state.rest.as_bytes(), b"#}") {
+                        if let Some(comment_end) = find_comment_end(state.rest, nested_comments) {
                             if state
                                 .rest
                                 .as_bytes()
@@ -469,6 +523,15 @@ fn test_find_marker() {
     assert_eq!(find_marker("foo {{-"), Some((4, true)));
 }
 
+#[test]
+fn test_find_comment_end() {
+    assert_eq!(find_comment_end("{# {% x %} {{ #}", false), Some(14));
+    assert_eq!(find_comment_end("{# {# x #} #}", false), Some(8));
+    assert_eq!(find_comment_end("{# {# x #} #}", true), Some(11));
+    assert_eq!(find_comment_end("{# {# x #}", true), None);
+    assert_eq!(find_comment_end("{##}", true), Some(2));
+}
+
 #[test]
 fn test_is_basic_tag() {
     assert_eq!(skip_basic_tag(" raw %}", "raw"), Some((7, false)));
@@ -4,10 +4,21 @@ use crate::compiler::ast;
 
 struct AssignmentTracker<'a> {
     out: HashSet<&'a str>,
+    nested_out: Option<HashSet<String>>,
     assigned: Vec<HashSet<&'a str>>,
+    visit_all: bool,
 }
 
 impl<'a> AssignmentTracker<'a> {
+    fn new(nested: bool, visit_all: bool) -> AssignmentTracker<'a> {
+        AssignmentTracker {
+            out: HashSet::new(),
+            nested_out: if nested { Some(HashSet::new()) } else { None },
+            assigned: vec![Default::default()],
+            visit_all,
+        }
+    }
+
     fn is_assigned(&self, name: &str) -> bool {
         self.assigned.iter().any(|x| x.contains(name))
     }
@@ -16,6 +27,14 @@ impl<'a> AssignmentTracker<'a> {
         self.assigned.last_mut().unwrap().insert(name);
     }
 
+    fn assign_nested(&mut self, expr: &ast::Expr<'a>) {
+        match expr {
+            ast::Expr::Var(var) => self.assign(var.id),
+            ast::Expr::List(list) => list.items.iter().for_each(|x| self.assign_nested(x)),
+            _ => {}
+        }
+    }
+
     fn push(&mut self) {
         self.assigned.push(Default::default());
     }
@@ -26,166 +45,246 @@ impl<'a> AssignmentTracker<'a> {
 }
 
 /// Finds all variables that need to be captured as closure for a macro.
+#[cfg(feature = "macros")]
 pub fn find_macro_closure<'a>(m: &ast::Macro<'a>) -> HashSet<&'a str> {
-    fn visit_expr_opt<'a>(expr: &Option<ast::Expr<'a>>, state: &mut AssignmentTracker<'a>) {
-        if let Some(expr) = expr {
-            visit_expr(expr, state);
-        }
+    let mut state = AssignmentTracker::new(false, false);
+    m.args.iter().for_each(|arg| state.assign_nested(arg));
+    m.body.iter().for_each(|node| walk(node, &mut state));
+    state.out
+}
+
+/// Finds all variables that a template looks up from the context.
+///
+/// If `nested` is enabled attribute lookups on such variables are reported
+/// as dotted paths (`foo.bar`) instead of just the variable name.
+pub fn find_undeclared(t: &ast::Stmt<'_>, nested: bool) -> HashSet<String> {
+    let mut state = AssignmentTracker::new(nested, true);
+    walk(t, &mut state);
+    match state.nested_out {
+        Some(rv) => rv,
+        None => state.out.into_iter().map(|x| x.to_string()).collect(),
     }
+}
 
-    fn visit_expr<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
-        match expr {
-            ast::Expr::Var(var) => {
-                if !state.is_assigned(var.id) {
-                    state.out.insert(var.id);
-                    state.assign(var.id);
+/// Returns the dotted path of an attribute lookup if it starts at a variable.
+fn attr_path<'a>(expr: &ast::Expr<'a>) -> Option<(String, &'a str)> {
+    match expr {
+        ast::Expr::Var(var) => Some((var.id.to_string(), var.id)),
+        ast::Expr::GetAttr(attr) => attr_path(&attr.expr).map(|(mut path, root)| {
+            path.push('.');
+            path.push_str(attr.name);
+            (path, root)
+        }),
+        _ => None,
+    }
+}
+
+fn visit_expr_opt<'a>(expr: &Option<ast::Expr<'a>>, state: &mut AssignmentTracker<'a>) {
+    if let Some(expr) = expr {
+        visit_expr(expr, state);
+    }
+}
+
+fn visit_expr<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
+    match expr {
+        ast::Expr::Var(var) => {
+            if !state.is_assigned(var.id) {
+                state.out.insert(var.id);
+                if let Some(ref mut nested_out) = state.nested_out {
+                    nested_out.insert(var.id.to_string());
                 }
             }
-            ast::Expr::Const(_) => {}
-            ast::Expr::UnaryOp(expr) => visit_expr(&expr.expr, state),
-            ast::Expr::BinOp(expr) => {
-                visit_expr(&expr.left, state);
-                visit_expr(&expr.right, state);
-            }
-            ast::Expr::IfExpr(expr) => {
-                visit_expr(&expr.test_expr, state);
-                visit_expr(&expr.true_expr, state);
-                visit_expr_opt(&expr.false_expr, state);
-            }
-            ast::Expr::Filter(expr) => {
-                visit_expr_opt(&expr.expr, state);
-                expr.args.iter().for_each(|x| visit_expr(x, state));
-            }
-            ast::Expr::Test(expr) => {
-                visit_expr(&expr.expr, state);
-                expr.args.iter().for_each(|x| visit_expr(x, state));
-            }
-            ast::Expr::GetAttr(expr) => visit_expr(&expr.expr, state),
-            ast::Expr::GetItem(expr) => {
-                visit_expr(&expr.expr, state);
-                visit_expr(&expr.subscript_expr, state);
-            }
-            ast::Expr::Slice(slice) => {
-                visit_expr_opt(&slice.start, state);
-                visit_expr_opt(&slice.stop, state);
-                visit_expr_opt(&slice.step, state);
-            }
-            ast::Expr::Call(expr) => {
-                visit_expr(&expr.expr, state);
-                expr.args.iter().for_each(|x| visit_expr(x, state));
+        }
+        ast::Expr::Const(_) => {}
+        ast::Expr::UnaryOp(expr) => visit_expr(&expr.expr, state),
+        ast::Expr::BinOp(expr) => {
+            visit_expr(&expr.left, state);
+            visit_expr(&expr.right, state);
+        }
+        ast::Expr::IfExpr(expr) => {
+            visit_expr(&expr.test_expr, state);
+            visit_expr(&expr.true_expr, state);
+            visit_expr_opt(&expr.false_expr, state);
+        }
+        ast::Expr::Filter(expr) => {
+            visit_expr_opt(&expr.expr, state);
+            expr.args.iter().for_each(|x| visit_expr(x, state));
+        }
+        ast::Expr::Test(expr) => {
+            visit_expr(&expr.expr, state);
+            expr.args.iter().for_each(|x| visit_expr(x, state));
+        }
+        ast::Expr::GetAttr(attr) => {
+            if state.nested_out.is_some() {
+                if let Some((path, root)) = attr_path(expr) {
+                    if !state.is_assigned(root) {
+                        state.out.insert(root);
+                        if let Some(ref mut nested_out) = state.nested_out {
+                            nested_out.insert(path);
+                        }
+                    }
+                    return;
+                }
             }
-            ast::Expr::List(expr) => expr.items.iter().for_each(|x| visit_expr(x, state)),
-            ast::Expr::Map(expr) => expr.keys.iter().zip(expr.values.iter()).for_each(|(k, v)| {
-                visit_expr(k, state);
-                visit_expr(v, state);
-            }),
-            ast::Expr::Kwargs(expr) => expr.pairs.iter().for_each(|(_, v)| visit_expr(v, state)),
+            visit_expr(&attr.expr, state)
+        }
+        ast::Expr::GetItem(expr) => {
+            visit_expr(&expr.expr, state);
+            visit_expr(&expr.subscript_expr, state);
+        }
+        ast::Expr::Slice(slice) => {
+            visit_expr_opt(&slice.start, state);
+            visit_expr_opt(&slice.stop, state);
+            visit_expr_opt(&slice.step, state);
         }
+        ast::Expr::Call(expr) => {
+            visit_expr(&expr.expr, state);
+            expr.args.iter().for_each(|x| visit_expr(x, state));
+        }
+        ast::Expr::List(expr) => expr.items.iter().for_each(|x| visit_expr(x, state)),
+        ast::Expr::Map(expr) => expr.keys.iter().zip(expr.values.iter()).for_each(|(k, v)| {
+            visit_expr(k, state);
+            visit_expr(v, state);
+        }),
+        ast::Expr::Kwargs(expr) => expr.pairs.iter().for_each(|(_, v)| visit_expr(v, state)),
     }
+}
 
-    fn assign_nested<'a>(expr: &ast::Expr<'a>, state: &mut AssignmentTracker<'a>) {
-        match expr {
-            ast::Expr::Var(var) => state.assign(var.id),
-            ast::Expr::List(list) => list.items.iter().for_each(|x| assign_nested(x, state)),
-            _ => {}
-        }
+#[cfg(feature = "macros")]
+fn visit_macro<'a>(m: &ast::Macro<'a>, state: &mut AssignmentTracker<'a>) {
+    m.defaults.iter().for_each(|x| visit_expr(x, state));
+    state.push();
+    state.assign("caller");
+    m.args.iter().for_each(|arg| state.assign_nested(arg));
+    m.body.iter().for_each(|node| walk(node, state));
+    state.pop();
+}
+
+/// Assigns a target and visits the expression assigned to it.
+///
+/// Macro closures assign the target first, so the expression never captures
+/// a variable of the same name.
+fn assign_and_visit<'a>(
+    target: &ast::Expr<'a>,
+    expr: &ast::Expr<'a>,
+    state: &mut AssignmentTracker<'a>,
+) {
+    if state.visit_all {
+        visit_expr(expr, state);
+        state.assign_nested(target);
+    } else {
+        state.assign_nested(target);
+        visit_expr(expr, state);
     }
+}
 
-    fn walk<'a>(node: &ast::Stmt<'a>, state: &mut AssignmentTracker<'a>) {
-        match node {
-            ast::Stmt::Template(stmt) => {
-                state.assign("self");
-                stmt.children.iter().for_each(|x| walk(x, state));
-            }
-            ast::Stmt::EmitExpr(expr) => visit_expr(&expr.expr, state),
-            ast::Stmt::EmitRaw(_) => {}
-            ast::Stmt::ForLoop(stmt) => {
-                state.push();
-                state.assign("loop");
-                visit_expr(&stmt.iter, state);
-                assign_nested(&stmt.target, state);
-                visit_expr_opt(&stmt.filter_expr, state);
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-                state.push();
-                stmt.else_body.iter().for_each(|x| walk(x, state));
-                state.pop();
+fn walk<'a>(node: &ast::Stmt<'a>, state: &mut AssignmentTracker<'a>) {
+    match node {
+        ast::Stmt::Template(stmt) => {
+            state.assign("self");
+            stmt.children.iter().for_each(|x| walk(x, state));
+        }
+        ast::Stmt::EmitExpr(expr) => visit_expr(&expr.expr, state),
+        ast::Stmt::EmitRaw(_) => {}
+        ast::Stmt::ForLoop(stmt) => {
+            state.push();
+            state.assign("loop");
+            visit_expr(&stmt.iter, state);
+            state.assign_nested(&stmt.target);
+            visit_expr_opt(&stmt.filter_expr, state);
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+            state.push();
+            stmt.else_body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::IfCond(stmt) => {
+            visit_expr(&stmt.expr, state);
+            state.push();
+            stmt.true_body.iter().for_each(|x| walk(x, state));
+            state.pop();
+            state.push();
+            stmt.false_body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::WithBlock(stmt) => {
+            state.push();
+            for (target, expr) in &stmt.assignments {
+                assign_and_visit(target, expr, state);
             }
-            ast::Stmt::IfCond(stmt) => {
-                visit_expr(&stmt.expr, state);
-                state.push();
-                stmt.true_body.iter().for_each(|x| walk(x, state));
-                state.pop();
-                state.push();
-                stmt.false_body.iter().for_each(|x| walk(x, state));
-                state.pop();
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::Set(stmt) => assign_and_visit(&stmt.target, &stmt.expr, state),
+        ast::Stmt::AutoEscape(stmt) => {
+            state.push();
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::FilterBlock(stmt) => {
+            state.push();
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        ast::Stmt::SetBlock(stmt) => {
+            state.assign_nested(&stmt.target);
+            state.push();
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        #[cfg(feature = "multi_template")]
+        ast::Stmt::Block(stmt) => {
+            state.push();
+            state.assign("super");
+            stmt.body.iter().for_each(|x| walk(x, state));
+            state.pop();
+        }
+        #[cfg(feature = "multi_template")]
+        ast::Stmt::Extends(stmt) => {
+            if state.visit_all {
+                visit_expr(&stmt.name, state);
             }
-            ast::Stmt::WithBlock(stmt) => {
-                state.push();
-                for (target, expr) in &stmt.assignments {
-                    assign_nested(target, state);
-                    visit_expr(expr, state);
-                }
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
+        }
+        #[cfg(feature = "multi_template")]
+        ast::Stmt::Include(stmt) => {
+            if state.visit_all {
+                visit_expr(&stmt.name, state);
             }
-            ast::Stmt::Set(stmt) => {
-                assign_nested(&stmt.target, state);
+        }
+        #[cfg(feature = "multi_template")]
+        ast::Stmt::Import(stmt) => {
+            if state.visit_all {
                 visit_expr(&stmt.expr, state);
             }
-            ast::Stmt::AutoEscape(stmt) => {
-                state.push();
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            ast::Stmt::FilterBlock(stmt) => {
-                state.push();
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            ast::Stmt::SetBlock(stmt) => {
-                assign_nested(&stmt.target, state);
-                state.push();
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            #[cfg(feature = "multi_template")]
-            ast::Stmt::Block(stmt) => {
-                state.push();
-                state.assign("super");
-                stmt.body.iter().for_each(|x| walk(x, state));
-                state.pop();
-            }
-            #[cfg(feature = "multi_template")]
-            ast::Stmt::Extends(_) | ast::Stmt::Include(_) => {}
-            #[cfg(feature = "multi_template")]
-            ast::Stmt::Import(stmt) => {
-                assign_nested(&stmt.name, state);
+            state.assign_nested(&stmt.name);
+        }
+        #[cfg(feature = "multi_template")]
+        ast::Stmt::FromImport(stmt) => {
+            if state.visit_all {
+                visit_expr(&stmt.expr, state);
             }
-            #[cfg(feature = "multi_template")]
-            ast::Stmt::FromImport(stmt) => stmt.names.iter().for_each(|(arg, alias)| {
-                assign_nested(alias.as_ref().unwrap_or(arg), state);
-            }),
-            #[cfg(feature = "macros")]
-            ast::Stmt::Macro(stmt) => {
-                state.assign(stmt.name);
+            stmt.names.iter().for_each(|(arg, alias)| {
+                state.assign_nested(alias.as_ref().unwrap_or(arg));
+            })
+        }
+        #[cfg(feature = "macros")]
+        ast::Stmt::Macro(stmt) => {
+            state.assign(stmt.name);
+            if state.visit_all {
+                visit_macro(stmt, state);
             }
-            #[cfg(feature = "macros")]
-            ast::Stmt::CallBlock(_) => {}
-            ast::Stmt::Do(stmt) => {
+        }
+        #[cfg(feature = "macros")]
+        ast::Stmt::CallBlock(stmt) => {
+            if state.visit_all {
                 visit_expr(&stmt.call.expr, state);
                 stmt.call.args.iter().for_each(|x| visit_expr(x, state));
+                visit_macro(&stmt.macro_decl, state);
             }
         }
+        ast::Stmt::Do(stmt) => {
+            visit_expr(&stmt.call.expr, state);
+            stmt.call.args.iter().for_each(|x| visit_expr(x, state));
+        }
     }
-
-    let mut state = AssignmentTracker {
-        out: HashSet::new(),
-        assigned: vec![Default::default()],
-    };
-
-    m.args.iter().for_each(|arg| assign_nested(arg, &mut state));
-    m.body.iter().for_each(|node| walk(node, &mut state));
-
-    state.out
 }
@@ -4,7 +4,6 @@ pub mod ast;
 pub mod codegen;
 pub mod instructions;
 pub mod lexer;
-#[cfg(feature = "macros")]
 pub mod meta;
 pub mod parser;
 pub mod tokens;
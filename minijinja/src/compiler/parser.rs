@@ -2,7 +2,7 @@ use std::collections::BTreeSet;
 use std::fmt;
 
 use crate::compiler::ast::{self, Spanned};
-use crate::compiler::lexer::tokenize;
+use crate::compiler::lexer::tokenize_with_options;
 use crate::compiler::tokens::{Span, Token};
 use crate::error::{Error, ErrorKind};
 use crate::value::Value;
@@ -11,6 +11,7 @@ const MAX_RECURSION: usize = 150;
 const RESERVED_NAMES: [&str; 8] = [
     "true", "True", "false", "False", "none", "None", "loop", "self",
 ];
+const TEST_ARG_STOP_WORDS: [&str; 8] = ["and", "or", "else", "if", "is", "not", "in", "recursive"];
 
 macro_rules! syntax_error {
     ($msg:expr) => {{
@@ -91,17 +92,23 @@ struct TokenStream<'a> {
     iter: Box<dyn Iterator<Item = Result<(Token<'a>, Span), Error>> + 'a>,
     current: Option<Result<(Token<'a>, Span), Error>>,
     last_span: Span,
+    last_ended_tag: bool,
 }
 
 impl<'a> TokenStream<'a> {
     /// Tokenize a template
-    pub fn new(source: &'a str, in_expr: bool) -> TokenStream<'a> {
-        let mut iter = Box::new(tokenize(source, in_expr)) as Box<dyn Iterator<Item = _>>;
+    pub fn new(source: &'a str, in_expr: bool, config: ParserConfig) -> TokenStream<'a> {
+        let mut iter = Box::new(tokenize_with_options(
+            source,
+            in_expr,
+            config.nested_comments,
+        )) as Box<dyn Iterator<Item = _>>;
         let current = iter.next();
         TokenStream {
             iter,
             current,
             last_span: Span::default(),
+            last_ended_tag: false,
         }
     }
 
@@ -109,8 +116,9 @@ impl<'a> TokenStream<'a> {
     pub fn next(&mut self) -> Result<Option<(Token<'a>, Span)>, Error> {
         let rv = self.current.take();
         self.current = self.iter.next();
-        if let Some(Ok((_, span))) = rv {
+        if let Some(Ok((ref tok, span))) = rv {
             self.last_span = span;
+            self.last_ended_tag = matches!(tok, Token::BlockEnd | Token::VariableEnd);
         }
         rv.transpose()
     }
@@ -129,6 +137,7 @@ impl<'a> TokenStream<'a> {
     pub fn expand_span(&self, mut span: Span) -> Span {
         span.end_line = self.last_span.end_line;
         span.end_col = self.last_span.end_col;
+        span.end_offset = self.last_span.end_offset;
         span
     }
 
@@ -156,6 +165,7 @@ struct Parser<'a> {
     #[allow(unused)]
     blocks: BTreeSet<&'a str>,
     depth: usize,
+    errors: Option<Vec<Error>>,
 }
 
 macro_rules! binop {
@@ -216,12 +226,13 @@ macro_rules! with_recursion_guard {
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(source: &'a str, in_expr: bool) -> Parser<'a> {
+    pub fn new(source: &'a str, in_expr: bool, config: ParserConfig) -> Parser<'a> {
         Parser {
-            stream: TokenStream::new(source, in_expr),
+            stream: TokenStream::new(source, in_expr, config),
             in_macro: false,
             blocks: BTreeSet::new(),
             depth: 0,
+            errors: None,
         }
     }
 
@@ -441,6 +452,23 @@ impl<'a> Parser<'a> {
                         expect_token!(self, Token::Ident(name) => name, "identifier");
                     let args = if matches_token!(self, Token::ParenOpen) {
                         ok!(self.parse_args())
+                    } else if matches_token!(
+                        self,
+                        Token::Str(_)
+                            | Token::String(_)
+                            | Token::Int(_)
+                            | Token::Float(_)
+                            | Token::BracketOpen
+                            | Token::BraceOpen
+                    ) || matches!(
+                        ok!(self.stream.current()),
+                        Some((Token::Ident(ident), _)) if !TEST_ARG_STOP_WORDS.contains(ident)
+                    ) {
+                        // a single argument can be passed without parentheses
+                        // (eg: `x is divisibleby 3`)
+                        let arg_span = self.stream.current_span();
+                        let arg = ok!(self.parse_primary());
+                        vec![ok!(self.parse_postfix(arg, arg_span))]
                     } else {
                         Vec::new()
                     };
@@ -689,7 +717,11 @@ impl<'a> Parser<'a> {
             }
             if matches_token!(
                 self,
-                Token::ParenClose | Token::VariableEnd | Token::BlockEnd | Token::Ident("in")
+                Token::ParenClose
+                    | Token::VariableEnd
+                    | Token::BlockEnd
+                    | Token::Assign
+                    | Token::Ident("in")
             ) {
                 break;
             }
@@ -803,7 +835,11 @@ impl<'a> Parser<'a> {
             expect_token!(self, Token::ParenClose, "`)`");
             (assign, true)
         } else {
-            (ok!(self.parse_assign_name()), false)
+            let target = ok!(self.parse_assignment());
+            // unparenthesized tuples (`a, b = ...`) can only be used with
+            // an assignment, not with set blocks.
+            let is_tuple = matches!(target, ast::Expr::List(_));
+            (target, is_tuple)
         };
 
         if !in_paren && matches_token!(self, Token::BlockEnd | Token::Pipe) {
@@ -911,9 +947,19 @@ impl<'a> Parser<'a> {
         } else {
             false
         };
+        let with_context = if skip_token!(self, Token::Ident("without")) {
+            expect_token!(self, Token::Ident("context"), "context keyword");
+            false
+        } else {
+            if skip_token!(self, Token::Ident("with")) {
+                expect_token!(self, Token::Ident("context"), "context keyword");
+            }
+            true
+        };
         Ok(ast::Include {
             name,
             ignore_missing,
+            with_context,
         })
     }
 
@@ -1054,14 +1100,10 @@ impl<'a> Parser<'a> {
                 Token::TemplateData(raw) => {
                     rv.push(ast::Stmt::EmitRaw(Spanned::new(ast::EmitRaw { raw }, span)))
                 }
-                Token::VariableStart => {
-                    let expr = ok!(self.parse_expr());
-                    rv.push(ast::Stmt::EmitExpr(Spanned::new(
-                        ast::EmitExpr { expr },
-                        self.stream.expand_span(span),
-                    )));
-                    expect_token!(self, Token::VariableEnd, "end of variable block");
-                }
+                Token::VariableStart => match self.parse_emit_expr(span) {
+                    Ok(stmt) => rv.push(stmt),
+                    Err(err) => ok!(self.recover(err)),
+                },
                 Token::BlockStart => {
                     let (tok, _span) = match ok!(self.stream.current()) {
                         Some(rv) => rv,
@@ -1070,8 +1112,10 @@ impl<'a> Parser<'a> {
                     if end_check(tok) {
                         return Ok(rv);
                     }
-                    rv.push(ok!(self.parse_stmt()));
-                    expect_token!(self, Token::BlockEnd, "end of block");
+                    match self.parse_stmt_and_end() {
+                        Ok(stmt) => rv.push(stmt),
+                        Err(err) => ok!(self.recover(err)),
+                    }
                 }
                 _ => unreachable!("lexer produced garbage"),
             }
@@ -1079,6 +1123,49 @@ impl<'a> Parser<'a> {
         Ok(rv)
     }
 
+    fn parse_emit_expr(&mut self, span: Span) -> Result<ast::Stmt<'a>, Error> {
+        let expr = ok!(self.parse_expr());
+        let rv = ast::Stmt::EmitExpr(Spanned::new(
+            ast::EmitExpr { expr },
+            self.stream.expand_span(span),
+        ));
+        expect_token!(self, Token::VariableEnd, "end of variable block");
+        Ok(rv)
+    }
+
+    fn parse_stmt_and_end(&mut self) -> Result<ast::Stmt<'a>, Error> {
+        let rv = ok!(self.parse_stmt());
+        expect_token!(self, Token::BlockEnd, "end of block");
+        Ok(rv)
+    }
+
+    /// Records an error and skips to the end of the current tag.
+    ///
+    /// This only recovers if errors are collected, otherwise the error is
+    /// passed through.  If the end of the input is reached while skipping,
+    /// the error is returned as well as there is nothing left to recover.
+    fn recover(&mut self, mut err: Error) -> Result<(), Error> {
+        if self.errors.is_none() {
+            return Err(err);
+        }
+        if err.span().is_none() {
+            err.set_span(self.stream.last_span());
+        }
+        while !self.stream.last_ended_tag {
+            match self.stream.next() {
+                Ok(Some((Token::BlockEnd | Token::VariableEnd, _))) => break,
+                Ok(Some(_)) => {}
+                Ok(None) => return Err(err),
+                Err(lexer_err) => {
+                    self.errors.as_mut().unwrap().push(err);
+                    return Err(lexer_err);
+                }
+            }
+        }
+        self.errors.as_mut().unwrap().push(err);
+        Ok(())
+    }
+
     pub fn parse(&mut self) -> Result<ast::Stmt<'a>, Error> {
         let span = self.stream.last_span();
         Ok(ast::Stmt::Template(Spanned::new(
@@ -1090,21 +1177,28 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// Settings that influence how templates are tokenized and parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub(crate) struct ParserConfig {
+    /// Preserve a single trailing newline at the end of the template.
+    pub keep_trailing_newline: bool,
+    /// Allow comments to nest.
+    pub nested_comments: bool,
+}
+
 /// Parses a template
+#[cfg(feature = "unstable_machinery")]
 pub fn parse<'source>(source: &'source str, filename: &str) -> Result<ast::Stmt<'source>, Error> {
-    // we want to chop off a single newline at the end.  This means that a template
-    // by default does not end in a newline which is a useful property to allow
-    // inline templates to work.  If someone wants a trailing newline the expectation
-    // is that the user adds it themselves for achieve consistency.
-    let mut source = source;
-    if source.ends_with('\n') {
-        source = &source[..source.len() - 1];
-    }
-    if source.ends_with('\r') {
-        source = &source[..source.len() - 1];
-    }
+    parse_template(source, filename, ParserConfig::default())
+}
 
-    let mut parser = Parser::new(source, false);
+/// Parses a template with the given parser config.
+pub(crate) fn parse_template<'source>(
+    source: &'source str,
+    filename: &str,
+    config: ParserConfig,
+) -> Result<ast::Stmt<'source>, Error> {
+    let mut parser = Parser::new(chop_trailing_newline(source, config), false, config);
     parser.parse().map_err(|mut err| {
         if err.line().is_none() {
             err.set_filename_and_span(filename, parser.stream.last_span())
@@ -1113,9 +1207,56 @@ pub fn parse<'source>(source: &'source str, filename: &str) -> Result<ast::Stmt<
     })
 }
 
+/// Parses a template and collects all syntax errors.
+///
+/// Unlike [`parse_template`] this does not stop at the first syntax error
+/// but skips to the end of the offending tag and continues parsing.
+pub(crate) fn parse_template_all_errors<'source>(
+    source: &'source str,
+    filename: &str,
+    config: ParserConfig,
+) -> Result<ast::Stmt<'source>, Vec<Error>> {
+    let mut parser = Parser::new(chop_trailing_newline(source, config), false, config);
+    parser.errors = Some(Vec::new());
+    let rv = parser.parse();
+    let mut errors = parser.errors.take().unwrap_or_default();
+    match rv {
+        Ok(rv) if errors.is_empty() => return Ok(rv),
+        Ok(_) => {}
+        Err(err) => errors.push(err),
+    }
+    let last_span = parser.stream.last_span();
+    Err(errors
+        .into_iter()
+        .map(|mut err| {
+            if err.name().is_none() {
+                let span = err.span().unwrap_or(last_span);
+                err.set_filename_and_span(filename, span);
+            }
+            err
+        })
+        .collect())
+}
+
+fn chop_trailing_newline(mut source: &str, config: ParserConfig) -> &str {
+    // we want to chop off a single newline at the end.  This means that a template
+    // by default does not end in a newline which is a useful property to allow
+    // inline templates to work.  If someone wants a trailing newline the expectation
+    // is that the user adds it themselves for achieve consistency.
+    if !config.keep_trailing_newline {
+        if source.ends_with('\n') {
+            source = &source[..source.len() - 1];
+        }
+        if source.ends_with('\r') {
+            source = &source[..source.len() - 1];
+        }
+    }
+    source
+}
+
 /// Parses an expression
 pub fn parse_expr(source: &str) -> Result<ast::Expr<'_>, Error> {
-    let mut parser = Parser::new(source, true);
+    let mut parser = Parser::new(source, true, ParserConfig::default());
     parser
         .parse_expr()
         .and_then(|result| {
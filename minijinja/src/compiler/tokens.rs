@@ -132,6 +132,8 @@ pub struct Span {
     pub start_col: u32,
     pub end_line: u32,
     pub end_col: u32,
+    pub start_offset: u32,
+    pub end_offset: u32,
 }
 
 impl fmt::Debug for Span {
@@ -5,8 +5,8 @@ use crate::error::Error;
 use crate::filters::{self, BoxedFilter};
 use crate::output::Output;
 use crate::tests::{self, BoxedTest};
-use crate::utils::{write_escaped, AutoEscape};
-use crate::value::Value;
+use crate::utils::{json_options, write_env_float, write_escaped, AutoEscape};
+use crate::value::{Value, ValueRepr};
 use crate::vm::State;
 
 pub(crate) fn no_auto_escape(_: &str) -> AutoEscape {
@@ -44,8 +44,37 @@ pub fn default_auto_escape_callback(name: &str) -> AutoEscape {
 )]
 /// * [`None`](AutoEscape::None): no escaping
 /// * [`Custom(..)`](AutoEscape::Custom): results in an error
+///
+/// Floats are printed according to the environment's
+/// [`float_format`](crate::Environment::set_float_format) and
+/// [`non_finite_format`](crate::Environment::set_non_finite_format) unless
+/// they are serialized to JSON.
+///
+/// Custom formatters can delegate to this function for all values they do
+/// not want to handle themselves.  This keeps the auto escaping behavior of
+/// the state intact:
+///
+/// ```
+/// # use minijinja::{Environment, escape_formatter};
+/// # use minijinja::value::Value;
+/// let mut env = Environment::new();
+/// env.set_formatter(|out, state, value| {
+///     if value.is_true() {
+///         escape_formatter(out, state, value)
+///     } else {
+///         escape_formatter(out, state, &Value::from("<empty>"))
+///     }
+/// });
+/// let rv = env.render_named_str("x.html", "{{ a }}|{{ b }}", minijinja::context!(a => "<a>", b => ""));
+/// assert_eq!(rv.unwrap(), "&lt;a&gt;|&lt;empty&gt;");
+/// ```
 pub fn escape_formatter(out: &mut Output, state: &State, value: &Value) -> Result<(), Error> {
-    write_escaped(out, state.auto_escape(), value)
+    if let ValueRepr::F64(val) = value.0 {
+        if matches!(state.auto_escape(), AutoEscape::None | AutoEscape::Html) {
+            return write_env_float(out, val, state.env());
+        }
+    }
+    write_escaped(out, state.auto_escape(), value, json_options(state))
 }
 
 pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::BoxedFilter> {
@@ -56,6 +85,10 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
     #[cfg(feature = "builtins")]
     {
         rv.insert("lower".into(), BoxedFilter::new(filters::lower));
+        rv.insert("casefold".into(), BoxedFilter::new(filters::casefold));
+        rv.insert("string".into(), BoxedFilter::new(filters::string));
+        rv.insert("escape_once".into(), BoxedFilter::new(filters::escape_once));
+        rv.insert("forceescape".into(), BoxedFilter::new(filters::forceescape));
         rv.insert("upper".into(), BoxedFilter::new(filters::upper));
         rv.insert("title".into(), BoxedFilter::new(filters::title));
         rv.insert("capitalize".into(), BoxedFilter::new(filters::capitalize));
@@ -64,11 +97,25 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
         rv.insert("count".into(), BoxedFilter::new(filters::length));
         rv.insert("dictsort".into(), BoxedFilter::new(filters::dictsort));
         rv.insert("items".into(), BoxedFilter::new(filters::items));
+        rv.insert("combine".into(), BoxedFilter::new(filters::combine));
+        rv.insert("dict2items".into(), BoxedFilter::new(filters::dict2items));
         rv.insert("reverse".into(), BoxedFilter::new(filters::reverse));
         rv.insert("trim".into(), BoxedFilter::new(filters::trim));
+        rv.insert("lstrip".into(), BoxedFilter::new(filters::lstrip));
+        rv.insert("rstrip".into(), BoxedFilter::new(filters::rstrip));
+        rv.insert(
+            "normalize_space".into(),
+            BoxedFilter::new(filters::normalize_space),
+        );
         rv.insert("join".into(), BoxedFilter::new(filters::join));
         rv.insert("default".into(), BoxedFilter::new(filters::default));
+        rv.insert(
+            "default_if_none".into(),
+            BoxedFilter::new(filters::default_if_none),
+        );
         rv.insert("round".into(), BoxedFilter::new(filters::round));
+        rv.insert("int".into(), BoxedFilter::new(filters::int));
+        rv.insert("float".into(), BoxedFilter::new(filters::float));
         rv.insert("abs".into(), BoxedFilter::new(filters::abs));
         rv.insert("attr".into(), BoxedFilter::new(filters::attr));
         rv.insert("first".into(), BoxedFilter::new(filters::first));
@@ -76,8 +123,11 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
         rv.insert("min".into(), BoxedFilter::new(filters::min));
         rv.insert("max".into(), BoxedFilter::new(filters::max));
         rv.insert("sort".into(), BoxedFilter::new(filters::sort));
+        rv.insert("groupby".into(), BoxedFilter::new(filters::groupby));
+        rv.insert("flatten".into(), BoxedFilter::new(filters::flatten));
         rv.insert("d".into(), BoxedFilter::new(filters::default));
         rv.insert("list".into(), BoxedFilter::new(filters::list));
+        rv.insert("pprint".into(), BoxedFilter::new(filters::pprint));
         rv.insert("bool".into(), BoxedFilter::new(filters::bool));
         rv.insert("batch".into(), BoxedFilter::new(filters::batch));
         rv.insert("slice".into(), BoxedFilter::new(filters::slice));
@@ -87,14 +137,53 @@ pub(crate) fn get_builtin_filters() -> BTreeMap<Cow<'static, str>, filters::Boxe
         rv.insert("selectattr".into(), BoxedFilter::new(filters::selectattr));
         rv.insert("rejectattr".into(), BoxedFilter::new(filters::rejectattr));
         rv.insert("map".into(), BoxedFilter::new(filters::map));
+        rv.insert("mapvalues".into(), BoxedFilter::new(filters::mapvalues));
 
         #[cfg(feature = "json")]
         {
             rv.insert("tojson".into(), BoxedFilter::new(filters::tojson));
+            rv.insert("json".into(), BoxedFilter::new(filters::tojson));
+            rv.insert("fromjson".into(), BoxedFilter::new(filters::fromjson));
+        }
+        #[cfg(feature = "toml")]
+        {
+            rv.insert("tomlencode".into(), BoxedFilter::new(filters::tomlencode));
         }
         #[cfg(feature = "urlencode")]
         {
             rv.insert("urlencode".into(), BoxedFilter::new(filters::urlencode));
+            rv.insert("urldecode".into(), BoxedFilter::new(filters::urldecode));
+        }
+        #[cfg(feature = "base64")]
+        {
+            rv.insert("b64encode".into(), BoxedFilter::new(filters::b64encode));
+            rv.insert("b64decode".into(), BoxedFilter::new(filters::b64decode));
+        }
+        #[cfg(feature = "regex")]
+        {
+            rv.insert(
+                "regex_replace".into(),
+                BoxedFilter::new(filters::regex_replace),
+            );
+            rv.insert(
+                "regex_search".into(),
+                BoxedFilter::new(filters::regex_search),
+            );
+            rv.insert(
+                "regex_findall".into(),
+                BoxedFilter::new(filters::regex_findall),
+            );
+        }
+        #[cfg(feature = "number_format")]
+        {
+            rv.insert(
+                "numberformat".into(),
+                BoxedFilter::new(filters::numberformat),
+            );
+            rv.insert(
+                "currencyformat".into(),
+                BoxedFilter::new(filters::currencyformat),
+            );
         }
     }
 
@@ -112,15 +201,19 @@ pub(crate) fn get_builtin_tests() -> BTreeMap<Cow<'static, str>, BoxedTest> {
     {
         rv.insert("odd".into(), BoxedTest::new(tests::is_odd));
         rv.insert("even".into(), BoxedTest::new(tests::is_even));
+        rv.insert("divisibleby".into(), BoxedTest::new(tests::is_divisibleby));
         rv.insert("number".into(), BoxedTest::new(tests::is_number));
         rv.insert("string".into(), BoxedTest::new(tests::is_string));
         rv.insert("sequence".into(), BoxedTest::new(tests::is_sequence));
         rv.insert("mapping".into(), BoxedTest::new(tests::is_mapping));
+        rv.insert("iterable".into(), BoxedTest::new(tests::is_iterable));
         rv.insert(
             "startingwith".into(),
             BoxedTest::new(tests::is_startingwith),
         );
         rv.insert("endingwith".into(), BoxedTest::new(tests::is_endingwith));
+        #[cfg(feature = "regex")]
+        rv.insert("matching".into(), BoxedTest::new(tests::is_matching));
 
         // operators
         rv.insert("eq".into(), BoxedTest::new(tests::is_eq));
@@ -161,6 +254,10 @@ pub(crate) fn get_globals() -> BTreeMap<Cow<'static, str>, Value> {
             "debug".into(),
             BoxedFunction::new(functions::debug).to_value(),
         );
+        rv.insert(
+            "coalesce".into(),
+            BoxedFunction::new(functions::coalesce).to_value(),
+        );
     }
 
     rv
@@ -6,17 +6,35 @@ use std::sync::Arc;
 use serde::Serialize;
 
 use crate::compiler::codegen::CodeGenerator;
-use crate::compiler::parser::parse_expr;
-use crate::error::{attach_basic_debug_info, Error};
+use crate::compiler::parser::{parse_expr, parse_template_all_errors, ParserConfig};
+use crate::error::{attach_basic_debug_info, Error, ErrorKind};
 use crate::expression::Expression;
 use crate::output::Output;
-use crate::template::{CompiledTemplate, Template};
-use crate::utils::{AutoEscape, BTreeMapKeysDebug};
+use crate::template::{CompiledTemplate, CompiledTemplateRef, OwnedTemplate, Template};
+use crate::utils::{AutoEscape, BTreeMapKeysDebug, FloatFormat, NonFiniteFormat};
 use crate::value::{FunctionArgs, FunctionResult, Value};
-use crate::vm::{State, Vm};
+use crate::vm::{State, TraceEvent};
 use crate::{defaults, filters, functions, tests};
 
-type TemplateMap<'source> = BTreeMap<&'source str, Arc<CompiledTemplate<'source>>>;
+type TemplateMap<'source> = BTreeMap<Cow<'source, str>, StoredTemplate<'source>>;
+
+/// A template held by the environment, either borrowing or owning its source.
+#[derive(Clone)]
+enum StoredTemplate<'source> {
+    Borrowed(Arc<CompiledTemplate<'source>>),
+    #[cfg(feature = "source")]
+    Owned(Arc<crate::source::LoadedTemplate>),
+}
+
+impl<'source> StoredTemplate<'source> {
+    fn compiled(&self) -> &CompiledTemplate<'_> {
+        match self {
+            StoredTemplate::Borrowed(compiled) => compiled,
+            #[cfg(feature = "source")]
+            StoredTemplate::Owned(loaded) => loaded.borrow_dependent(),
+        }
+    }
+}
 
 #[derive(Clone)]
 enum Source<'source> {
@@ -37,6 +55,11 @@ impl<'source> fmt::Debug for Source<'source> {
 
 type AutoEscapeFunc = dyn Fn(&str) -> AutoEscape + Sync + Send;
 type FormatterFunc = dyn Fn(&mut Output, &State, &Value) -> Result<(), Error> + Sync + Send;
+type TraceFunc = dyn Fn(&TraceEvent) + Sync + Send;
+type UnknownMethodFunc =
+    dyn Fn(&State, &Value, &str, &[Value]) -> Result<Value, Error> + Sync + Send;
+#[cfg(feature = "multi_template")]
+type PathJoinFunc = dyn Fn(&str, &str) -> String + Sync + Send;
 
 /// An abstraction that holds the engine configuration.
 ///
@@ -60,6 +83,37 @@ environment it's recommended to turn on the `source` feature and to use the
 ///   defaults.  It will contain all built-in filters, tests and globals as well
 ///   as a callback for auto escaping based on file extension.
 /// * [`Environment::empty`] creates a completely blank environment.
+///
+/// # Sharing
+///
+/// The environment is `Send` and `Sync` and rendering only ever requires a
+/// shared reference.  Once it has been configured it can be wrapped in an
+/// [`Arc`] and handed to as many threads as needed.  Templates that are loaded
+/// on first use through a `Source` are cached in a thread-safe way.  Cloning
+/// the environment is also cheap as callbacks, filters and the like are
+/// reference counted, but a clone is an independent copy which can be
+/// configured separately.
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::thread;
+/// # use minijinja::{Environment, context};
+///
+/// let mut env = Environment::new();
+/// env.add_template("hello", "Hello {{ name }}!").unwrap();
+/// let env = Arc::new(env);
+///
+/// let handles = (0..4).map(|idx| {
+///     let env = env.clone();
+///     thread::spawn(move || {
+///         let tmpl = env.get_template("hello").unwrap();
+///         tmpl.render(context!(name => idx)).unwrap()
+///     })
+/// }).collect::<Vec<_>>();
+/// for handle in handles {
+///     handle.join().unwrap();
+/// }
+/// ```
 #[derive(Clone)]
 pub struct Environment<'source> {
     templates: Source<'source>,
@@ -68,10 +122,25 @@ pub struct Environment<'source> {
     pub(crate) globals: BTreeMap<Cow<'source, str>, Value>,
     default_auto_escape: Arc<AutoEscapeFunc>,
     formatter: Arc<FormatterFunc>,
+    pub(crate) trace_callback: Option<Arc<TraceFunc>>,
+    pub(crate) unknown_method_callback: Option<Arc<UnknownMethodFunc>>,
+    #[cfg(feature = "multi_template")]
+    path_join_callback: Option<Arc<PathJoinFunc>>,
+    parser_config: ParserConfig,
+    #[cfg(feature = "source")]
+    compiled_cache: Option<Arc<dyn crate::cache::CompiledCache>>,
+    float_format: FloatFormat,
+    non_finite_format: NonFiniteFormat,
     #[cfg(feature = "debug")]
     debug: bool,
     #[cfg(feature = "fuel")]
     fuel: Option<u64>,
+    #[cfg(feature = "json")]
+    json_sort_keys: bool,
+    #[cfg(feature = "json")]
+    json_pretty: bool,
+    #[cfg(feature = "json")]
+    json_strict_floats: bool,
 }
 
 impl<'source> Default for Environment<'source> {
@@ -106,10 +175,25 @@ impl<'source> Environment<'source> {
             globals: defaults::get_globals(),
             default_auto_escape: Arc::new(defaults::default_auto_escape_callback),
             formatter: Arc::new(defaults::escape_formatter),
+            trace_callback: None,
+            unknown_method_callback: None,
+            #[cfg(feature = "multi_template")]
+            path_join_callback: None,
+            parser_config: ParserConfig::default(),
+            #[cfg(feature = "source")]
+            compiled_cache: None,
+            float_format: FloatFormat::AlwaysDecimal,
+            non_finite_format: NonFiniteFormat::default(),
             #[cfg(feature = "debug")]
             debug: cfg!(debug_assertions),
             #[cfg(feature = "fuel")]
             fuel: None,
+            #[cfg(feature = "json")]
+            json_sort_keys: false,
+            #[cfg(feature = "json")]
+            json_pretty: false,
+            #[cfg(feature = "json")]
+            json_strict_floats: false,
         }
     }
 
@@ -125,10 +209,25 @@ impl<'source> Environment<'source> {
             globals: Default::default(),
             default_auto_escape: Arc::new(defaults::no_auto_escape),
             formatter: Arc::new(defaults::escape_formatter),
+            trace_callback: None,
+            unknown_method_callback: None,
+            #[cfg(feature = "multi_template")]
+            path_join_callback: None,
+            parser_config: ParserConfig::default(),
+            #[cfg(feature = "source")]
+            compiled_cache: None,
+            float_format: FloatFormat::AlwaysDecimal,
+            non_finite_format: NonFiniteFormat::default(),
             #[cfg(feature = "debug")]
             debug: cfg!(debug_assertions),
             #[cfg(feature = "fuel")]
             fuel: None,
+            #[cfg(feature = "json")]
+            json_sort_keys: false,
+            #[cfg(feature = "json")]
+            json_pretty: false,
+            #[cfg(feature = "json")]
+            json_strict_floats: false,
         }
     }
 
@@ -138,18 +237,28 @@ impl<'source> Environment<'source> {
     /// it.  To look up a loaded template use the [`get_template`](Self::get_template)
     /// method.
     ///
-    /// Note that there are situations where the interface of this method is
-    /// too restrictive.  For instance the environment itself does not permit
-    /// any form of sensible dynamic template loading.
+    /// The name and source are borrowed for the `'source` lifetime of the
+    /// environment and not copied.  This is ideal for templates embedded into
+    /// the binary (eg: with `include_str!`) which are `'static`.  For templates
+    /// that are created at runtime the borrow usually becomes inconvenient.
     #[cfg_attr(
         feature = "source",
-        doc = "To address this restriction use [`set_source`](Self::set_source)."
+        doc = "In that case use [`add_template_owned`](Self::add_template_owned) instead."
     )]
     pub fn add_template(&mut self, name: &'source str, source: &'source str) -> Result<(), Error> {
+        self._add_template(name, source)
+            .map_err(|err| self.finalize_error(err))
+    }
+
+    fn _add_template(&mut self, name: &'source str, source: &'source str) -> Result<(), Error> {
         match self.templates {
             Source::Borrowed(ref mut map) => {
-                let compiled_template = ok!(CompiledTemplate::from_name_and_source(name, source));
-                map.insert(name, Arc::new(compiled_template));
+                let compiled_template =
+                    ok!(CompiledTemplate::new(name, source, self.parser_config));
+                map.insert(
+                    Cow::Borrowed(name),
+                    StoredTemplate::Borrowed(Arc::new(compiled_template)),
+                );
                 Ok(())
             }
             #[cfg(feature = "source")]
@@ -157,6 +266,50 @@ impl<'source> Environment<'source> {
         }
     }
 
+    /// Loads a template from a string, taking ownership of name and source.
+    ///
+    /// This works like [`add_template`](Self::add_template) but the name and
+    /// source are moved into the environment, so they do not need to outlive
+    /// it.  This is useful for templates that are loaded at runtime:
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// fn make_env() -> Environment<'static> {
+    ///     let mut env = Environment::new();
+    ///     let source = format!("Hello {{{{ name }}}} from {}!", "runtime");
+    ///     env.add_template_owned("hello.txt", source).unwrap();
+    ///     env
+    /// }
+    /// ```
+    ///
+    /// Borrowed and owned templates can be mixed freely in one environment.
+    #[cfg(feature = "source")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "source")))]
+    pub fn add_template_owned<N, S>(&mut self, name: N, source: S) -> Result<(), Error>
+    where
+        N: Into<String>,
+        S: Into<String>,
+    {
+        self._add_template_owned(name.into(), source.into())
+            .map_err(|err| self.finalize_error(err))
+    }
+
+    #[cfg(feature = "source")]
+    fn _add_template_owned(&mut self, name: String, source: String) -> Result<(), Error> {
+        match self.templates {
+            Source::Borrowed(ref mut map) => {
+                let loaded = ok!(crate::source::load_template(
+                    name.clone(),
+                    source,
+                    self.parser_config
+                ));
+                map.insert(Cow::Owned(name), StoredTemplate::Owned(loaded));
+                Ok(())
+            }
+            Source::Owned(ref mut src) => src.add_template(name, source),
+        }
+    }
+
     /// Removes a template by name.
     pub fn remove_template(&mut self, name: &str) {
         match self.templates {
@@ -170,6 +323,82 @@ impl<'source> Environment<'source> {
         }
     }
 
+    /// Removes all templates from the environment.
+    pub fn clear_templates(&mut self) {
+        match self.templates {
+            Source::Borrowed(ref mut map) => map.clear(),
+            #[cfg(feature = "source")]
+            Source::Owned(ref mut source) => source.clear_templates(),
+        }
+    }
+
+    /// Returns the names of all currently loaded templates.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.add_template("a.txt", "A").unwrap();
+    /// env.add_template("b.txt", "B").unwrap();
+    /// let names = env.template_names().collect::<Vec<_>>();
+    /// assert_eq!(names, vec!["a.txt", "b.txt"]);
+    /// ```
+    pub fn template_names(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        match self.templates {
+            Source::Borrowed(ref map) => Box::new(map.keys().map(|x| x.as_ref())),
+            #[cfg(feature = "source")]
+            Source::Owned(ref source) => source.template_names(),
+        }
+    }
+
+    /// Compiles all templates and reports all errors at once.
+    ///
+    /// Templates added with [`add_template`](Self::add_template) are compiled
+    /// immediately, so this is mostly useful with a loader based source where
+    /// templates are otherwise only compiled on first use.  This can be used
+    /// to fail early at startup if any template has a syntax error.
+    #[cfg_attr(
+        feature = "source",
+        doc = "For details see [`Source::compile_all`](crate::Source::compile_all)."
+    )]
+    pub fn compile_all(&self) -> Result<(), Vec<(String, Error)>> {
+        match self.templates {
+            Source::Borrowed(_) => Ok(()),
+            #[cfg(feature = "source")]
+            Source::Owned(ref source) => source.compile_all(),
+        }
+    }
+
+    /// Parses a template from a string and collects all syntax errors.
+    ///
+    /// Normally parsing stops at the first syntax error.  This method instead
+    /// skips to the end of the tag with the error and continues parsing, so
+    /// that all syntax errors of a template can be reported at once.  Each
+    /// error carries its own line and span information.  Note that an error
+    /// in the opening tag of a block such as `{% for %}` can cause follow-up
+    /// errors for the matching end tag.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let env = Environment::new();
+    /// let errors = env.parse_all_errors("{{ 1 + }}\n{{ x }}\n{{ y y }}").unwrap_err();
+    /// let lines = errors.iter().map(|x| x.line()).collect::<Vec<_>>();
+    /// assert_eq!(lines, [Some(1), Some(3)]);
+    /// ```
+    pub fn parse_all_errors(&self, source: &str) -> Result<(), Vec<Error>> {
+        parse_template_all_errors(source, "<string>", self.parser_config)
+            .map(|_| ())
+            .map_err(|errors| {
+                errors
+                    .into_iter()
+                    .map(|err| {
+                        self.finalize_error(
+                            attach_basic_debug_info(Err::<(), _>(err), source).unwrap_err(),
+                        )
+                    })
+                    .collect()
+            })
+    }
+
     /// Fetches a template by name.
     ///
     /// This requires that the template has been loaded with
@@ -184,13 +413,9 @@ impl<'source> Environment<'source> {
     /// println!("{}", tmpl.render(context!{ name => "World" }).unwrap());
     /// ```
     pub fn get_template(&self, name: &str) -> Result<Template<'_>, Error> {
-        let compiled = match &self.templates {
-            Source::Borrowed(ref map) => {
-                ok!(map.get(name).ok_or_else(|| Error::new_not_found(name)))
-            }
-            #[cfg(feature = "source")]
-            Source::Owned(source) => ok!(source.get_compiled_template(name)),
-        };
+        let compiled = ok!(self
+            .get_compiled_template(name)
+            .map_err(|err| self.finalize_error(err)));
         Ok(Template::new(
             self,
             compiled,
@@ -198,6 +423,97 @@ impl<'source> Environment<'source> {
         ))
     }
 
+    /// Compiles a template without adding it to the environment.
+    ///
+    /// Unlike [`add_template`](Self::add_template) this does not require the
+    /// source to outlive the environment, it only needs to outlive the returned
+    /// template.  The source is borrowed and not copied.  The template can
+    /// include or extend templates from the environment, but other templates
+    /// cannot refer to it.  As the compiled template is owned by the returned
+    /// [`OwnedTemplate`] rather than the environment, a [`Template`] handle
+    /// can be borrowed from it with [`OwnedTemplate::as_template`].
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// let env = Environment::new();
+    /// let source = String::from("Hello {{ name }}!");
+    /// let tmpl = env.template_from_named_str("hello.txt", &source).unwrap();
+    /// println!("{}", tmpl.render(context!{ name => "World" }).unwrap());
+    /// ```
+    pub fn template_from_named_str<'a>(
+        &'a self,
+        name: &'a str,
+        source: &'a str,
+    ) -> Result<OwnedTemplate<'a>, Error> {
+        #[cfg(feature = "source")]
+        {
+            if let Some(ref cache) = self.compiled_cache {
+                let cached =
+                    ok!(
+                        crate::cache::get_or_compile(&**cache, name, source, self.parser_config)
+                            .map_err(|err| self.finalize_error(err))
+                    );
+                return Ok(OwnedTemplate::new(
+                    self,
+                    CompiledTemplateRef::Cached(cached),
+                    self.get_initial_auto_escape(name),
+                ));
+            }
+        }
+        let compiled = ok!(CompiledTemplate::new(name, source, self.parser_config)
+            .map_err(|err| self.finalize_error(err)));
+        Ok(OwnedTemplate::new(
+            self,
+            CompiledTemplateRef::Owned(Arc::new(compiled)),
+            self.get_initial_auto_escape(name),
+        ))
+    }
+
+    /// Compiles a template from a string without adding it to the environment.
+    ///
+    /// This works like [`template_from_named_str`](Self::template_from_named_str)
+    /// but the template is named `<string>`.
+    pub fn template_from_str<'a>(&'a self, source: &'a str) -> Result<OwnedTemplate<'a>, Error> {
+        self.template_from_named_str("<string>", source)
+    }
+
+    /// Sets a cache for templates compiled from strings.
+    ///
+    /// Templates created with [`template_from_named_str`](Self::template_from_named_str),
+    /// [`render_named_str`](Self::render_named_str) and their unnamed
+    /// counterparts are normally compiled on every call.  With a cache set, a
+    /// template that was already compiled with the same name, source and syntax
+    /// settings is reused instead.  As the cache is reference counted it can be
+    /// shared by many short lived environments.  A default implementation is
+    /// provided by [`LruCompiledCache`](crate::LruCompiledCache).
+    ///
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use minijinja::{Environment, LruCompiledCache};
+    /// let cache = Arc::new(LruCompiledCache::new(100));
+    /// let mut env = Environment::new();
+    /// env.set_compiled_cache(cache.clone());
+    /// assert_eq!(env.render_str("{{ 1 + 1 }}", ()).unwrap(), "2");
+    /// assert_eq!(cache.len(), 1);
+    /// ```
+    #[cfg(feature = "source")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "source")))]
+    pub fn set_compiled_cache(&mut self, cache: Arc<dyn crate::cache::CompiledCache>) {
+        self.compiled_cache = Some(cache);
+    }
+
+    /// Looks up a compiled template in the environment.
+    pub(crate) fn get_compiled_template(&self, name: &str) -> Result<&CompiledTemplate<'_>, Error> {
+        match &self.templates {
+            Source::Borrowed(ref map) => map
+                .get(name)
+                .map(|x| x.compiled())
+                .ok_or_else(|| Error::new_not_found(name)),
+            #[cfg(feature = "source")]
+            Source::Owned(source) => source.get_compiled_template(name),
+        }
+    }
+
     /// Parses and renders a template from a string in one go.
     ///
     /// In some cases you really only need a template to be rendered once from
@@ -248,17 +564,10 @@ impl<'source> Environment<'source> {
     }
 
     fn _render_str(&self, name: &str, source: &str, root: Value) -> Result<String, Error> {
-        let compiled = ok!(CompiledTemplate::from_name_and_source(name, source));
-        let mut rv = String::with_capacity(compiled.buffer_size_hint);
-        Vm::new(self)
-            .eval(
-                &compiled.instructions,
-                root,
-                &compiled.blocks,
-                &mut Output::with_string(&mut rv),
-                self.get_initial_auto_escape(name),
-            )
-            .map(|_| rv)
+        // the owned template keeps a cached entry alive only while rendering
+        ok!(self.template_from_named_str(name, source))
+            .as_template()
+            ._render(root)
     }
 
     /// Sets a new function to select the default auto escaping.
@@ -288,6 +597,42 @@ impl<'source> Environment<'source> {
         self.default_auto_escape = Arc::new(f);
     }
 
+    /// Selects the default auto escaping from a list of file extensions.
+    ///
+    /// This is a shortcut for [`set_auto_escape_callback`](Self::set_auto_escape_callback)
+    /// for the common case where the auto escaping only depends on the file
+    /// extension of the template name.  Extensions are given without the
+    /// leading dot.  Templates with an extension that is not in the list do
+    /// not get auto escaping.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, AutoEscape};
+    /// # let mut env = Environment::new();
+    /// env.set_default_auto_escape_extensions(&[
+    ///     ("html", AutoEscape::Html),
+    ///     ("svg", AutoEscape::Html),
+    ///     ("txt", AutoEscape::None),
+    /// ]);
+    /// # assert_eq!(env.render_named_str("x.svg", "{{ '<' }}", ()).unwrap(), "&lt;");
+    /// # assert_eq!(env.render_named_str("x.htm", "{{ '<' }}", ()).unwrap(), "<");
+    /// ```
+    pub fn set_default_auto_escape_extensions(&mut self, extensions: &[(&str, AutoEscape)]) {
+        let extensions = extensions
+            .iter()
+            .map(|(ext, auto_escape)| (ext.to_string(), *auto_escape))
+            .collect::<Vec<_>>();
+        self.set_auto_escape_callback(move |name| {
+            let ext = match name.rsplit_once('.') {
+                Some((_, ext)) => ext,
+                None => return AutoEscape::None,
+            };
+            extensions
+                .iter()
+                .find(|(x, _)| x == ext)
+                .map_or(AutoEscape::None, |(_, auto_escape)| *auto_escape)
+        });
+    }
+
     /// Sets a different formatter function.
     ///
     /// The formatter is invoked to format the given value into the provided
@@ -303,6 +648,13 @@ impl<'source> Environment<'source> {
     /// The current value of the auto escape flag can be retrieved directly
     /// from the [`State`].
     ///
+    /// Because the formatter sees every value before it's written it can also
+    /// be used to change how a certain kind of value is printed.  For instance
+    /// a formatter can intercept [`ValueKind::Number`](crate::value::ValueKind::Number)
+    /// values to render floats with a fixed precision and hand everything else
+    /// to [`escape_formatter`](defaults::escape_formatter).  See the
+    /// `custom-formatter` example for how this can be done.
+    ///
     /// ```
     /// # use minijinja::Environment;
     /// # let mut env = Environment::new();
@@ -329,6 +681,164 @@ impl<'source> Environment<'source> {
         self.formatter = Arc::new(f);
     }
 
+    /// Sets a callback that is invoked for significant events during rendering.
+    ///
+    /// The callback is invoked with a [`TraceEvent`] when a loop is entered or
+    /// left, a filter or function is called or a block is rendered.  Every
+    /// event carries the name of the template and the line that triggered it
+    /// which makes it possible to build profiles of template execution.  If
+    /// an operation fails no exit event is emitted for it.
+    ///
+    /// When no callback is set (the default) tracing has no effect.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, TracePhase};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let mut env = Environment::new();
+    /// let calls = Arc::new(AtomicUsize::new(0));
+    /// let counter = calls.clone();
+    /// env.set_trace_callback(move |event| {
+    ///     if event.phase() == TracePhase::Enter {
+    ///         counter.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// });
+    /// env.render_str("{{ 'foo'|upper|lower }}", ()).unwrap();
+    /// assert_eq!(calls.load(Ordering::Relaxed), 2);
+    /// ```
+    pub fn set_trace_callback<F>(&mut self, f: F)
+    where
+        F: Fn(&TraceEvent) + 'static + Sync + Send,
+    {
+        self.trace_callback = Some(Arc::new(f));
+    }
+
+    /// Sets a callback to join template paths for includes and extends.
+    ///
+    /// The callback is invoked with the name of the template that performs
+    /// the `include`, `extends` or `import` and the name of the referenced
+    /// template.  It returns the name of the template to load.  This allows
+    /// resolving relative template names against the current template.  By
+    /// default names are used as they are.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_path_join_callback(|parent, name| match name.strip_prefix("./") {
+    ///     Some(rest) => match parent.rsplit_once('/') {
+    ///         Some((dir, _)) => format!("{dir}/{rest}"),
+    ///         None => rest.to_string(),
+    ///     },
+    ///     None => name.to_string(),
+    /// });
+    /// env.add_template("pages/index.html", "{% include './item.html' %}").unwrap();
+    /// env.add_template("pages/item.html", "item").unwrap();
+    /// let tmpl = env.get_template("pages/index.html").unwrap();
+    /// assert_eq!(tmpl.render(()).unwrap(), "item");
+    /// ```
+    #[cfg(feature = "multi_template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi_template")))]
+    pub fn set_path_join_callback<F>(&mut self, f: F)
+    where
+        F: Fn(&str, &str) -> String + 'static + Sync + Send,
+    {
+        self.path_join_callback = Some(Arc::new(f));
+    }
+
+    /// Sets a callback that is invoked for unknown methods.
+    ///
+    /// When a method is called on a value (`value.method(...)`) that does not
+    /// resolve on the value itself, this callback is invoked with the state,
+    /// the value, the name of the method and the arguments instead of failing
+    /// with an [`UnknownMethod`](crate::ErrorKind::UnknownMethod) error.  The
+    /// callback can return that error itself for methods it does not know
+    /// either.  This allows catch-all method dispatch for objects that cannot
+    /// enumerate their methods ahead of time.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, Error, ErrorKind};
+    /// # use minijinja::value::Value;
+    /// let mut env = Environment::new();
+    /// env.set_unknown_method_callback(|_state, value, name, _args| {
+    ///     match (value.as_str(), name) {
+    ///         (Some(s), "shout") => Ok(Value::from(s.to_uppercase() + "!")),
+    ///         _ => Err(Error::new(ErrorKind::UnknownMethod, format!("no method {name}"))),
+    ///     }
+    /// });
+    /// assert_eq!(env.render_str("{{ 'hello'.shout() }}", ()).unwrap(), "HELLO!");
+    /// ```
+    pub fn set_unknown_method_callback<F>(&mut self, f: F)
+    where
+        F: Fn(&State, &Value, &str, &[Value]) -> Result<Value, Error> + Sync + Send + 'static,
+    {
+        self.unknown_method_callback = Some(Arc::new(f));
+    }
+
+    /// Preserve the trailing newline when rendering templates.
+    ///
+    /// The default is `false`, which causes a single newline, if present, to be
+    /// stripped from the end of the template.  This setting only affects
+    /// templates that are compiled after it was changed, so it should be set
+    /// before templates are added.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// assert_eq!(env.render_str("foo\n", ()).unwrap(), "foo");
+    /// env.set_keep_trailing_newline(true);
+    /// assert_eq!(env.render_str("foo\n", ()).unwrap(), "foo\n");
+    /// ```
+    pub fn set_keep_trailing_newline(&mut self, yes: bool) {
+        self.parser_config.keep_trailing_newline = yes;
+        self.sync_parser_config();
+    }
+
+    /// Returns the value of the trailing newline preservation flag.
+    pub fn keep_trailing_newline(&self) -> bool {
+        self.parser_config.keep_trailing_newline
+    }
+
+    /// Enables or disables nested comments.
+    ///
+    /// By default a comment ends at the first `#}` even if it contains
+    /// another `{#`.  With nested comments enabled the lexer tracks the
+    /// nesting depth so that `{# outer {# inner #} still comment #}` is
+    /// treated as a single comment.  This makes it easy to comment out
+    /// sections of a template that already contain comments.  Like
+    /// [`set_keep_trailing_newline`](Self::set_keep_trailing_newline) this
+    /// only affects templates compiled after it was changed.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_nested_comments(true);
+    /// let rv = env.render_str("a{# {# inner #} {{ x #}b", ()).unwrap();
+    /// assert_eq!(rv, "ab");
+    /// ```
+    pub fn set_nested_comments(&mut self, yes: bool) {
+        self.parser_config.nested_comments = yes;
+        self.sync_parser_config();
+    }
+
+    /// Returns the value of the nested comments flag.
+    pub fn nested_comments(&self) -> bool {
+        self.parser_config.nested_comments
+    }
+
+    pub(crate) fn parser_config(&self) -> ParserConfig {
+        self.parser_config
+    }
+
+    fn sync_parser_config(&mut self) {
+        #[cfg(feature = "source")]
+        {
+            if let Source::Owned(ref mut source) = self.templates {
+                source.set_parser_config(self.parser_config);
+            }
+        }
+    }
+
     /// Enable or disable the debug mode.
     ///
     /// When the debug mode is enabled the engine will dump out some of the
@@ -337,7 +847,22 @@ impl<'source> Environment<'source> {
     /// high as the data including the template source is cloned.
     ///
     /// When this is enabled templates will print debug information with source
-    /// context when the error is printed.
+    /// context when the error is printed.  When it's disabled errors returned
+    /// by the environment never carry source snippets or a dump of the
+    /// render state, even for syntax errors.  This makes it possible to
+    /// compile the `debug` feature in but only turn the output on for some
+    /// deployments:
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.set_debug(false);
+    /// let err = env.add_template("hello.txt", "{{ foo bar }}").unwrap_err();
+    /// assert_eq!(
+    ///     format!("{:#}", err),
+    ///     "syntax error: unexpected identifier, expected end of variable block (in hello.txt:1)"
+    /// );
+    /// ```
     ///
     /// This requires the `debug` feature.  This is enabled by default if
     /// debug assertions are enabled and false otherwise.
@@ -353,6 +878,75 @@ impl<'source> Environment<'source> {
         self.debug
     }
 
+    /// Removes the debug info from an error unless debug mode is enabled.
+    fn finalize_error(&self, err: Error) -> Error {
+        #[cfg(feature = "debug")]
+        {
+            if !self.debug {
+                let mut err = err;
+                err.discard_debug_info();
+                return err;
+            }
+        }
+        err
+    }
+
+    /// Sets the format used for printing floats.
+    ///
+    /// By default floats are always printed with a decimal point so `5.0`
+    /// renders as `5.0` ([`FloatFormat::AlwaysDecimal`]).  With
+    /// [`FloatFormat::Minimal`] integral floats render as `5` instead and
+    /// [`FloatFormat::Fixed`] emits a fixed number of digits after the
+    /// decimal point.
+    ///
+    /// This affects floats printed by the default formatter and the
+    /// [`string`](crate::filters::string) filter.  Floats nested in lists or
+    /// maps as well as JSON output are not affected.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, FloatFormat};
+    /// let mut env = Environment::new();
+    /// env.set_float_format(FloatFormat::Fixed(2));
+    /// assert_eq!(env.render_str("{{ 1 / 3 }}", ()).unwrap(), "0.33");
+    /// ```
+    pub fn set_float_format(&mut self, format: FloatFormat) {
+        self.float_format = format;
+    }
+
+    /// Returns the format used for printing floats.
+    pub fn float_format(&self) -> FloatFormat {
+        self.float_format
+    }
+
+    /// Sets how `NaN` and infinite floats are printed.
+    ///
+    /// Such floats are typically the result of bad input data or a division
+    /// of a float by zero.  By default they render as `NaN`, `inf` and `-inf`.
+    /// [`NonFiniteFormat::Tokens`] replaces these tokens and with
+    /// [`NonFiniteFormat::Error`] printing such a float fails rendering with
+    /// an [`InvalidOperation`](crate::ErrorKind::InvalidOperation) error.
+    ///
+    /// Like the [`float_format`](Self::set_float_format) this affects floats
+    /// printed by the default formatter and the
+    /// [`string`](crate::filters::string) filter.  JSON output is controlled
+    /// separately by `set_json_strict_floats`.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, NonFiniteFormat};
+    /// let mut env = Environment::new();
+    /// env.set_non_finite_format(NonFiniteFormat::Tokens { nan: "n/a", inf: "∞" });
+    /// let rv = env.render_str("{{ x }}|{{ -x * x }}", minijinja::context!(x => f64::INFINITY));
+    /// assert_eq!(rv.unwrap(), "∞|-∞");
+    /// ```
+    pub fn set_non_finite_format(&mut self, format: NonFiniteFormat) {
+        self.non_finite_format = format;
+    }
+
+    /// Returns how `NaN` and infinite floats are printed.
+    pub fn non_finite_format(&self) -> NonFiniteFormat {
+        self.non_finite_format
+    }
+
     /// Sets the optional fuel of the engine.
     ///
     /// When MiniJinja is compiled with the `fuel` feature then every
@@ -375,6 +969,66 @@ impl<'source> Environment<'source> {
         self.fuel
     }
 
+    /// Sorts the keys of maps when serializing to JSON.
+    ///
+    /// This affects the [`tojson`](crate::filters::tojson) filter (unless it's
+    /// overridden with the `sort_keys` argument) as well as values printed with
+    /// [`AutoEscape::Json`].  By default keys are emitted in the iteration order
+    /// of the map.  Sorted keys make the output deterministic which is useful
+    /// for diffing or caching rendered output.
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    pub fn set_json_sort_keys(&mut self, yes: bool) {
+        self.json_sort_keys = yes;
+    }
+
+    /// Returns `true` if map keys are sorted when serializing to JSON.
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    pub fn json_sort_keys(&self) -> bool {
+        self.json_sort_keys
+    }
+
+    /// Pretty prints JSON output by default.
+    ///
+    /// When enabled the [`tojson`](crate::filters::tojson) filter indents
+    /// its output by two spaces unless an explicit `indent` argument is given
+    /// (`indent=false` forces compact output).  Values printed with
+    /// [`AutoEscape::Json`] are pretty printed as well.  This makes it possible
+    /// to switch between readable and compact output (for instance for debug
+    /// and release builds) without changing templates.
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    pub fn set_json_pretty(&mut self, yes: bool) {
+        self.json_pretty = yes;
+    }
+
+    /// Returns `true` if JSON output is pretty printed by default.
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    pub fn json_pretty(&self) -> bool {
+        self.json_pretty
+    }
+
+    /// Fails on non-finite floats when serializing to JSON.
+    ///
+    /// JSON cannot represent `NaN` and infinite floats.  By default they are
+    /// emitted as `null` by the [`tojson`](crate::filters::tojson) filter and
+    /// for values printed with [`AutoEscape::Json`].  When this is enabled
+    /// serializing such a float fails with an error instead.
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    pub fn set_json_strict_floats(&mut self, yes: bool) {
+        self.json_strict_floats = yes;
+    }
+
+    /// Returns `true` if non-finite floats fail to serialize to JSON.
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    pub fn json_strict_floats(&self) -> bool {
+        self.json_strict_floats
+    }
+
     /// Sets the template source for the environment.
     ///
     /// This helps when working with dynamically loaded templates.  The
@@ -389,7 +1043,8 @@ impl<'source> Environment<'source> {
     /// For more information see [`Source`](crate::source::Source).
     #[cfg(feature = "source")]
     #[cfg_attr(docsrs, doc(cfg(feature = "source")))]
-    pub fn set_source(&mut self, source: crate::source::Source) {
+    pub fn set_source(&mut self, mut source: crate::source::Source) {
+        source.set_parser_config(self.parser_config);
         self.templates = Source::Owned(source);
     }
 
@@ -421,6 +1076,7 @@ impl<'source> Environment<'source> {
     /// example see [`Expression`].
     pub fn compile_expression(&self, expr: &'source str) -> Result<Expression<'_, 'source>, Error> {
         attach_basic_debug_info(self._compile_expression(expr), expr)
+            .map_err(|err| self.finalize_error(err))
     }
 
     fn _compile_expression(&self, expr: &'source str) -> Result<Expression<'_, 'source>, Error> {
@@ -449,6 +1105,47 @@ impl<'source> Environment<'source> {
             .insert(name.into(), filters::BoxedFilter::new(f));
     }
 
+    /// Adds a new filter function unless one with that name exists already.
+    ///
+    /// This works like [`add_filter`](Self::add_filter) but instead of
+    /// replacing an existing filter (including built-in ones) an
+    /// [`InvalidOperation`](crate::ErrorKind::InvalidOperation) error is
+    /// returned and the environment is left unchanged.  This is useful to
+    /// catch name collisions when filters are registered from multiple places.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::new();
+    /// env.try_add_filter("slugify", |value: String| value.to_lowercase()).unwrap();
+    /// assert!(env.try_add_filter("slugify", |value: String| value).is_err());
+    /// ```
+    pub fn try_add_filter<N, F, Rv, Args>(&mut self, name: N, f: F) -> Result<(), Error>
+    where
+        N: Into<Cow<'source, str>>,
+        // the crazy bounds here exist to enable borrowing in closures
+        F: filters::Filter<Rv, Args>
+            + for<'a> filters::Filter<Rv, <Args as FunctionArgs<'a>>::Output>,
+        Rv: FunctionResult,
+        Args: for<'a> FunctionArgs<'a>,
+    {
+        let name = name.into();
+        if self.has_filter(&name) {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("filter {name:?} is already registered"),
+            ));
+        }
+        self.filters.insert(name, filters::BoxedFilter::new(f));
+        Ok(())
+    }
+
+    /// Returns `true` if a filter with the given name is registered.
+    ///
+    /// This includes the built-in filters.
+    pub fn has_filter(&self, name: &str) -> bool {
+        self.filters.contains_key(name)
+    }
+
     /// Removes a filter by name.
     pub fn remove_filter(&mut self, name: &str) {
         self.filters.remove(name);
@@ -507,6 +1204,45 @@ impl<'source> Environment<'source> {
         self.globals.remove(name);
     }
 
+    /// Returns the names of all registered filters in sorted order.
+    ///
+    /// This includes the built-in filters as well as filters added with
+    /// [`add_filter`](Self::add_filter).
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// let mut env = Environment::empty();
+    /// env.add_filter("slugify", |value: String| value.to_lowercase());
+    /// assert_eq!(env.filters().collect::<Vec<_>>(), ["slugify"]);
+    /// ```
+    pub fn filters(&self) -> impl Iterator<Item = &str> + '_ {
+        self.filters.keys().map(|x| x.as_ref())
+    }
+
+    /// Returns the names of all registered tests in sorted order.
+    ///
+    /// This includes the built-in tests as well as tests added with
+    /// [`add_test`](Self::add_test).
+    pub fn tests(&self) -> impl Iterator<Item = &str> + '_ {
+        self.tests.keys().map(|x| x.as_ref())
+    }
+
+    /// Returns the names of all registered global functions in sorted order.
+    ///
+    /// These are the built-in functions and the ones added with
+    /// [`add_function`](Self::add_function).  Other global variables are not
+    /// included, even if they happen to be callable objects.
+    pub fn functions(&self) -> impl Iterator<Item = &str> + '_ {
+        self.globals
+            .iter()
+            .filter(|(_, value)| {
+                value.as_object().map_or(false, |x| {
+                    x.downcast_ref::<functions::BoxedFunction>().is_some()
+                })
+            })
+            .map(|(name, _)| name.as_ref())
+    }
+
     /// Looks up a function.
     pub(crate) fn get_global(&self, name: &str) -> Option<Value> {
         self.globals.get(name).cloned()
@@ -522,6 +1258,15 @@ impl<'source> Environment<'source> {
         self.tests.get(name)
     }
 
+    /// Resolves the name of a template referenced from the `parent` template.
+    #[cfg(feature = "multi_template")]
+    pub(crate) fn join_template_path<'a>(&self, name: &'a str, parent: &str) -> Cow<'a, str> {
+        match self.path_join_callback {
+            Some(ref cb) => Cow::Owned(cb(parent, name)),
+            None => Cow::Borrowed(name),
+        }
+    }
+
     pub(crate) fn get_initial_auto_escape(&self, name: &str) -> AutoEscape {
         (self.default_auto_escape)(name)
     }
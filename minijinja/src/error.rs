@@ -1,5 +1,6 @@
 use std::borrow::Cow;
 use std::fmt;
+use std::ops::Range;
 
 use crate::compiler::tokens::Span;
 
@@ -126,6 +127,8 @@ pub enum ErrorKind {
     UndefinedError,
     /// Not able to serialize this value.
     BadSerialization,
+    /// Not able to deserialize this value.
+    CannotDeserialize,
     /// An error happened in an include.
     BadInclude,
     /// An error happened in a super block.
@@ -156,6 +159,7 @@ impl ErrorKind {
             ErrorKind::BadEscape => "bad string escape",
             ErrorKind::UndefinedError => "undefined value",
             ErrorKind::BadSerialization => "could not serialize to internal format",
+            ErrorKind::CannotDeserialize => "cannot deserialize",
             ErrorKind::BadInclude => "could not render include",
             ErrorKind::EvalBlock => "could not render block",
             ErrorKind::CannotUnpack => "cannot unpack",
@@ -229,6 +233,11 @@ impl Error {
         self.repr.lineno = span.start_line as usize;
     }
 
+    pub(crate) fn set_span(&mut self, span: Span) {
+        self.repr.span = Some(span);
+        self.repr.lineno = span.start_line as usize;
+    }
+
     pub(crate) fn new_not_found(name: &str) -> Error {
         Error::new(
             ErrorKind::TemplateNotFound,
@@ -248,6 +257,14 @@ impl Error {
         self.repr.kind
     }
 
+    /// Returns the error detail
+    ///
+    /// The detail is an error message that provides further details about
+    /// the error kind.
+    pub fn detail(&self) -> Option<&str> {
+        self.repr.detail.as_deref()
+    }
+
     /// Returns the filename of the template that caused the error.
     pub fn name(&self) -> Option<&str> {
         self.repr.name.as_deref()
@@ -262,8 +279,18 @@ impl Error {
         }
     }
 
-    /// Returns the line number where the error occurred.
-    #[cfg(feature = "debug")]
+    /// Returns the byte range of where the error occurred if available.
+    ///
+    /// In combination with [`line`](Self::line) and [`name`](Self::name) this
+    /// can be used to point to the exact location of the error in the
+    /// template source.
+    pub fn range(&self) -> Option<Range<usize>> {
+        self.repr
+            .span
+            .map(|span| span.start_offset as usize..span.end_offset as usize)
+    }
+
+    /// Returns the span where the error occurred.
     pub(crate) fn span(&self) -> Option<Span> {
         self.repr.span
     }
@@ -284,6 +311,11 @@ impl Error {
     pub(crate) fn attach_debug_info(&mut self, value: crate::debug::DebugInfo) {
         self.repr.debug_info = Some(value);
     }
+
+    #[cfg(feature = "debug")]
+    pub(crate) fn discard_debug_info(&mut self) {
+        self.repr.debug_info = None;
+    }
 }
 
 impl std::error::Error for Error {
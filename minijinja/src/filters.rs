@@ -72,10 +72,16 @@
 //! this module.  Note though that these functions are not to be
 //! called from Rust code as their exact interface (arguments and return types)
 //! might change from one MiniJinja version to another.
+//!
+//! Most filters that produce strings return regular strings which are subject
+//! to auto escaping.  The [`string`], [`upper`], [`lower`], [`title`],
+//! [`capitalize`], [`trim`], [`lstrip`] and [`rstrip`] filters however keep
+//! strings marked as safe when the input was already safe, so for instance
+//! `{{ value|safe|upper }}` is not escaped again.
 use std::sync::Arc;
 
 use crate::error::Error;
-use crate::utils::{write_escaped, SealedMarker};
+use crate::utils::{json_options, write_escaped, SealedMarker};
 use crate::value::{ArgType, FunctionArgs, FunctionResult, Value};
 use crate::vm::State;
 use crate::{AutoEscape, Output};
@@ -244,7 +250,12 @@ pub fn escape(state: &State, v: Value) -> Result<Value, Error> {
         None => String::new(),
     };
     let mut out = Output::with_string(&mut rv);
-    ok!(write_escaped(&mut out, auto_escape, &v));
+    ok!(write_escaped(
+        &mut out,
+        auto_escape,
+        &v,
+        json_options(state)
+    ));
     Ok(Value::from_safe_string(rv))
 }
 
@@ -254,6 +265,7 @@ mod builtins {
 
     use crate::error::ErrorKind;
     use crate::key::Key;
+    use crate::utils::{write_env_float, HtmlEscape, PrettyPrinter};
     use crate::value::{ValueKind, ValueRepr};
     use std::borrow::Cow;
     use std::cmp::Ordering;
@@ -263,14 +275,120 @@ mod builtins {
     #[cfg(test)]
     use similar_asserts::assert_eq;
 
+    /// Applies a string transformation and retains the safe marker.
+    fn map_str<F: FnOnce(&str) -> String>(v: &Value, f: F) -> Value {
+        let rv = f(&v.to_cowstr());
+        if v.is_safe() {
+            Value::from_safe_string(rv)
+        } else {
+            Value::from(rv)
+        }
+    }
+
+    /// HTML escapes a value even if it's marked as safe.
+    ///
+    /// Unlike [`escape`] this ignores the safe marker of a string and always
+    /// applies HTML escaping to the string representation of the value.  The
+    /// result is marked as safe.
+    ///
+    /// ```jinja
+    /// {{ "<b>"|safe|forceescape }} -> &lt;b&gt;
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn forceescape(v: Value) -> Value {
+        let rv = match v.as_str() {
+            Some(s) => HtmlEscape(s).to_string(),
+            None => HtmlEscape(&v.to_string()).to_string(),
+        };
+        Value::from_safe_string(rv)
+    }
+
+    /// HTML escapes a value but leaves existing entities intact.
+    ///
+    /// This works like [`escape`] with HTML escaping, but an ampersand that
+    /// already starts an entity (like `&amp;`, `&lt;` or `&#x27;`) is not
+    /// escaped again.  This is useful for input that might already be partially
+    /// escaped.  An ampersand that is not part of an entity is escaped as usual.
+    /// Values that are already marked as safe are returned unchanged.
+    ///
+    /// ```jinja
+    /// {{ "1 &lt; 2 & 3"|escape_once }} -> 1 &lt; 2 &amp; 3
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn escape_once(v: Value) -> Value {
+        if v.is_safe() {
+            return v;
+        }
+        let s = match v.as_str() {
+            Some(s) => Cow::Borrowed(s),
+            None => Cow::Owned(v.to_string()),
+        };
+        let mut rv = String::with_capacity(s.len());
+        let mut rest = &s[..];
+        while let Some(idx) = rest.find('&') {
+            write!(rv, "{}", HtmlEscape(&rest[..idx])).ok();
+            rest = &rest[idx..];
+            let len = html_entity_len(rest.as_bytes()).unwrap_or(0);
+            if len > 0 {
+                rv.push_str(&rest[..len]);
+            } else {
+                rv.push_str("&amp;");
+            }
+            rest = &rest[len.max(1)..];
+        }
+        write!(rv, "{}", HtmlEscape(rest)).ok();
+        Value::from_safe_string(rv)
+    }
+
+    /// Returns the length of the entity at the start of `s` if there is one.
+    fn html_entity_len(s: &[u8]) -> Option<usize> {
+        let (start, is_valid): (usize, fn(&u8) -> bool) = match s {
+            [b'&', b'#', b'x' | b'X', ..] => (3, u8::is_ascii_hexdigit),
+            [b'&', b'#', ..] => (2, u8::is_ascii_digit),
+            [b'&', c, ..] if c.is_ascii_alphabetic() => (1, u8::is_ascii_alphanumeric),
+            _ => return None,
+        };
+        let len = s[start..].iter().take_while(|c| is_valid(c)).count();
+        match s.get(start + len) {
+            Some(b';') if len > 0 => Some(start + len + 1),
+            _ => None,
+        }
+    }
+
+    /// Converts a value into a string.
+    ///
+    /// Strings are returned unchanged, so strings marked as safe stay safe.
+    /// Other values are converted into regular strings.  Floats are formatted
+    /// according to the environment's
+    /// [`float_format`](crate::Environment::set_float_format) and
+    /// [`non_finite_format`](crate::Environment::set_non_finite_format).
+    ///
+    /// ```jinja
+    /// {{ 42|string ~ "!" }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn string(state: &State, v: Value) -> Result<Value, Error> {
+        if v.kind() == ValueKind::String {
+            Ok(v)
+        } else if let ValueRepr::F64(val) = v.0 {
+            let mut rv = String::new();
+            ok!(write_env_float(&mut rv, val, state.env()));
+            Ok(Value::from(rv))
+        } else {
+            Ok(Value::from(v.to_string()))
+        }
+    }
+
     /// Converts a value to uppercase.
     ///
+    /// This uses the full unicode case mapping, so `ß` becomes `SS`.
+    ///
     /// ```jinja
     /// <h1>{{ chapter.title|upper }}</h1>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn upper(v: Cow<'_, str>) -> String {
-        v.to_uppercase()
+    pub fn upper(v: Value) -> Value {
+        map_str(&v, |s| s.to_uppercase())
     }
 
     /// Converts a value to lowercase.
@@ -279,8 +397,29 @@ mod builtins {
     /// <h1>{{ chapter.title|lower }}</h1>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn lower(v: Cow<'_, str>) -> String {
-        v.to_lowercase()
+    pub fn lower(v: Value) -> Value {
+        map_str(&v, |s| s.to_lowercase())
+    }
+
+    /// Case folds a value for case-insensitive comparisons.
+    ///
+    /// This goes further than [`lower`] in that characters are mapped to
+    /// the same form regardless of their case, so for instance `ß`, `ẞ` and
+    /// `SS` all become `ss`.  The result is not meant for display but only
+    /// for comparing strings.
+    ///
+    /// ```jinja
+    /// {% if user.name|casefold == "strasse"|casefold %}...{% endif %}
+    /// ```
+    ///
+    /// Like [`upper`] and [`lower`] this uses the locale independent unicode
+    /// case mappings.  Locale specific rules such as the Turkish dotless `ı`
+    /// are not supported.
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn casefold(v: Value) -> Value {
+        // going through the uppercase form applies the special case
+        // expansions (`ß` -> `SS`) before mapping everything to lowercase.
+        map_str(&v, |s| s.to_uppercase().to_lowercase())
     }
 
     /// Converts a value to title case.
@@ -289,21 +428,23 @@ mod builtins {
     /// <h1>{{ chapter.title|title }}</h1>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn title(v: Cow<'_, str>) -> String {
-        let mut rv = String::new();
-        let mut capitalize = true;
-        for c in v.chars() {
-            if c.is_ascii_punctuation() || c.is_whitespace() {
-                rv.push(c);
-                capitalize = true;
-            } else if capitalize {
-                write!(rv, "{}", c.to_uppercase()).unwrap();
-                capitalize = false;
-            } else {
-                write!(rv, "{}", c.to_lowercase()).unwrap();
+    pub fn title(v: Value) -> Value {
+        map_str(&v, |s| {
+            let mut rv = String::new();
+            let mut capitalize = true;
+            for c in s.chars() {
+                if c.is_ascii_punctuation() || c.is_whitespace() {
+                    rv.push(c);
+                    capitalize = true;
+                } else if capitalize {
+                    write!(rv, "{}", c.to_uppercase()).unwrap();
+                    capitalize = false;
+                } else {
+                    write!(rv, "{}", c.to_lowercase()).unwrap();
+                }
             }
-        }
-        rv
+            rv
+        })
     }
 
     /// Convert the string with all its characters lowercased
@@ -313,12 +454,14 @@ mod builtins {
     /// <h1>{{ chapter.title|capitalize }}</h1>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn capitalize(text: Cow<'_, str>) -> String {
-        let mut chars = text.chars();
-        match chars.next() {
-            None => String::new(),
-            Some(f) => f.to_uppercase().collect::<String>() + &chars.as_str().to_lowercase(),
-        }
+    pub fn capitalize(text: Value) -> Value {
+        map_str(&text, |s| {
+            let mut chars = s.chars();
+            match chars.next() {
+                None => String::new(),
+                Some(f) => f.to_uppercase().collect::<String>() + &chars.as_str().to_lowercase(),
+            }
+        })
     }
 
     /// Does a string replace.
@@ -342,6 +485,10 @@ mod builtins {
     /// Returns the "length" of the value
     ///
     /// By default this filter is also registered under the alias `count`.
+    /// For maps this is the number of entries, for lists and sequence
+    /// objects the number of items and for strings the number of unicode
+    /// characters (not bytes).  Other values such as numbers or booleans
+    /// result in an error.
     ///
     /// ```jinja
     /// <p>Search results: {{ results|length }}
@@ -362,12 +509,7 @@ mod builtins {
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn dictsort(v: Value) -> Result<Value, Error> {
         if v.kind() == ValueKind::Map {
-            let mut rv = Vec::with_capacity(v.len().unwrap_or(0));
-            let iter = ok!(v.try_iter());
-            for key in iter {
-                let value = v.get_item(&key).unwrap_or(Value::UNDEFINED);
-                rv.push((key, value));
-            }
+            let mut rv = ok!(v.try_iter_pairs()).collect::<Vec<_>>();
             rv.sort_by(|a, b| {
                 Key::from_borrowed_value(&a.0)
                     .unwrap()
@@ -405,18 +547,83 @@ mod builtins {
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn items(v: Value) -> Result<Value, Error> {
+        if v.kind() == ValueKind::Map {
+            Ok(Value::from(
+                ok!(v.try_iter_pairs())
+                    .map(|(key, value)| Value::from(vec![key, value]))
+                    .collect::<Vec<_>>(),
+            ))
+        } else {
+            Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "cannot convert value into pair list",
+            ))
+        }
+    }
+
+    /// Merges mappings into a new mapping.
+    ///
+    /// Every mapping passed as argument is merged into the value from left to
+    /// right, so keys of later mappings override earlier ones.  By default
+    /// nested mappings are replaced as a whole, if `recursive` is set to `true`
+    /// they are merged as well.  This is modelled after the Ansible filter
+    /// of the same name.
+    ///
+    /// ```jinja
+    /// {% set config = defaults|combine(overrides) %}
+    /// {% set config = defaults|combine(site, page, recursive=true) %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn combine(value: Value, args: crate::value::Rest<Value>) -> Result<Value, Error> {
+        let mut args = args.0;
+        let recursive = match args.last() {
+            Some(kwargs) if kwargs.is_kwargs() => {
+                let recursive = ok!(kwargs.get_attr("recursive")).is_true();
+                args.pop();
+                recursive
+            }
+            _ => false,
+        };
+        if value.kind() != ValueKind::Map {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "combine can only be applied to maps",
+            ));
+        }
+        let mut rv = value;
+        for other in args {
+            rv = ok!(rv.merge_maps(&other, recursive));
+        }
+        Ok(rv)
+    }
+
+    /// Converts a mapping into a list of `key`/`value` maps.
+    ///
+    /// This works like [`items`] but instead of pairs every item is a map
+    /// with a `key` and a `value` attribute.  This is useful when the items
+    /// are passed on to code that looks them up by name rather than
+    /// position.  The order follows the iteration order of the map.
+    ///
+    /// ```jinja
+    /// {% for item in my_dict|dict2items %}
+    ///   {{ item.key }}: {{ item.value }}
+    /// {% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn dict2items(v: Value) -> Result<Value, Error> {
         if v.kind() == ValueKind::Map {
             let mut rv = Vec::with_capacity(v.len().unwrap_or(0));
-            let iter = ok!(v.try_iter());
-            for key in iter {
-                let value = v.get_item(&key).unwrap_or(Value::UNDEFINED);
-                rv.push(Value::from(vec![key, value]));
+            for (key, value) in ok!(v.try_iter_pairs()) {
+                let mut item = std::collections::BTreeMap::new();
+                item.insert("key", key);
+                item.insert("value", value);
+                rv.push(Value::from(item));
             }
             Ok(Value::from(rv))
         } else {
             Err(Error::new(
                 ErrorKind::InvalidOperation,
-                "cannot convert value into pair list",
+                "cannot convert value into item list",
             ))
         }
     }
@@ -444,14 +651,76 @@ mod builtins {
 
     /// Trims a value
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn trim(s: Cow<'_, str>, chars: Option<Cow<'_, str>>) -> String {
-        match chars {
+    pub fn trim(s: Value, chars: Option<Cow<'_, str>>) -> Value {
+        map_str(&s, |s| match chars {
             Some(chars) => {
                 let chars = chars.chars().collect::<Vec<_>>();
                 s.trim_matches(&chars[..]).to_string()
             }
             None => s.trim().to_string(),
-        }
+        })
+    }
+
+    /// Trims the start of a value.
+    ///
+    /// Like [`trim`] this strips whitespace by default or any of the
+    /// characters in the given string.
+    ///
+    /// ```jinja
+    /// {{ "  hello  "|lstrip }} -> "hello  "
+    /// {{ "xxhelloxx"|lstrip("x") }} -> "helloxx"
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn lstrip(s: Value, chars: Option<Cow<'_, str>>) -> Value {
+        map_str(&s, |s| match chars {
+            Some(chars) => {
+                let chars = chars.chars().collect::<Vec<_>>();
+                s.trim_start_matches(&chars[..]).to_string()
+            }
+            None => s.trim_start().to_string(),
+        })
+    }
+
+    /// Trims the end of a value.
+    ///
+    /// Like [`trim`] this strips whitespace by default or any of the
+    /// characters in the given string.
+    ///
+    /// ```jinja
+    /// {{ "  hello  "|rstrip }} -> "  hello"
+    /// {{ "xxhelloxx"|rstrip("x") }} -> "xxhello"
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn rstrip(s: Value, chars: Option<Cow<'_, str>>) -> Value {
+        map_str(&s, |s| match chars {
+            Some(chars) => {
+                let chars = chars.chars().collect::<Vec<_>>();
+                s.trim_end_matches(&chars[..]).to_string()
+            }
+            None => s.trim_end().to_string(),
+        })
+    }
+
+    /// Trims a value and collapses runs of whitespace to single spaces.
+    ///
+    /// This works like XPath's `normalize-space()`.  All unicode whitespace
+    /// (including newlines and tabs) is considered.
+    ///
+    /// ```jinja
+    /// {{ "  a\n\t b   c "|normalize_space }} -> "a b c"
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn normalize_space(s: Value) -> Value {
+        map_str(&s, |s| {
+            let mut rv = String::with_capacity(s.len());
+            for word in s.split_whitespace() {
+                if !rv.is_empty() {
+                    rv.push(' ');
+                }
+                rv.push_str(word);
+            }
+            rv
+        })
     }
 
     /// Joins a sequence by a character
@@ -508,6 +777,24 @@ mod builtins {
         }
     }
 
+    /// If the value is none it will return the passed default value,
+    /// otherwise the value of the variable.
+    ///
+    /// Unlike [`default`] this leaves undefined values as well as other falsy
+    /// values such as empty strings untouched:
+    ///
+    /// ```jinja
+    /// <td>{{ row.email|default_if_none("no email") }}</td>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn default_if_none(value: Value, other: Option<Value>) -> Value {
+        if value.is_none() {
+            other.unwrap_or_else(|| Value::from(""))
+        } else {
+            value
+        }
+    }
+
     /// Returns the absolute value of a number.
     ///
     /// ```jinja
@@ -529,16 +816,18 @@ mod builtins {
 
     /// Looks up an attribute.
     ///
-    /// In MiniJinja this is the same as the `[]` operator.  In Jinja2 there is a
-    /// small difference which is why this filter is sometimes used in Jinja2
-    /// templates.  For compatibility it's provided here as well.
+    /// This performs the same lookup as the `.` operator but the name of the
+    /// attribute can be computed at runtime.  Unlike the `[]` operator only
+    /// attributes are looked up, so the key has to be a string and it does
+    /// not index into sequences.  Missing attributes are undefined.
     ///
     /// ```jinja
-    /// {{ value['key'] == value|attr('key') }} -> true
+    /// {{ value.key == value|attr('key') }} -> true
+    /// {% for field in fields %}{{ obj|attr(field) }}{% endfor %}
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn attr(value: Value, key: &Value) -> Result<Value, Error> {
-        value.get_item(key)
+    pub fn attr(value: Value, key: &str) -> Result<Value, Error> {
+        value.get_attr(key)
     }
 
     /// Round the number to a given precision.
@@ -565,6 +854,187 @@ mod builtins {
         }
     }
 
+    /// Converts a value into an integer.
+    ///
+    /// Floats are truncated and strings are parsed.  If the value cannot be
+    /// converted the default (the first argument or the `default` keyword
+    /// argument) which is `0` is returned.  Strings that hold floats are
+    /// truncated as well.  Like [`float`] it accepts the `decimal_point` and
+    /// `thousands` keyword arguments to parse numbers with other separators.
+    ///
+    /// ```jinja
+    /// {{ "42"|int }} -> 42
+    /// {{ 42.9|int }} -> 42
+    /// {{ "foo"|int(-1) }} -> -1
+    /// {{ "1.234.567"|int(thousands=".") }} -> 1234567
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn int(
+        value: Value,
+        default: Option<Value>,
+        kwargs: Option<Value>,
+    ) -> Result<Value, Error> {
+        let opts = ok!(NumberParseOptions::from_args(
+            default,
+            kwargs,
+            Value::from(0)
+        ));
+        Ok(match value.0 {
+            ValueRepr::U64(_) | ValueRepr::I64(_) | ValueRepr::U128(_) | ValueRepr::I128(_) => {
+                value
+            }
+            ValueRepr::F64(x) => Value::from(x.trunc() as i64),
+            ValueRepr::Bool(x) => Value::from(x as i64),
+            ValueRepr::String(ref s, _) => match opts.normalize(s) {
+                Some(s) => match s.parse::<i64>() {
+                    Ok(x) => Value::from(x),
+                    Err(_) => s
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|x| x.is_finite())
+                        .map_or(opts.default, |x| Value::from(x.trunc() as i64)),
+                },
+                None => opts.default,
+            },
+            _ => opts.default,
+        })
+    }
+
+    /// Converts a value into a float.
+    ///
+    /// Integers are converted and strings are parsed.  If the value cannot be
+    /// converted the default (the first argument or the `default` keyword
+    /// argument) which is `0.0` is returned.
+    ///
+    /// The `decimal_point` and `thousands` keyword arguments change the
+    /// separators that are accepted when parsing strings.  Parsing is strict:
+    /// thousands separators have to separate groups of three digits and if a
+    /// string does not follow the configured format the default is returned.
+    ///
+    /// ```jinja
+    /// {{ "42.5"|float }} -> 42.5
+    /// {{ "foo"|float(1.0) }} -> 1.0
+    /// {{ "1.234,56"|float(thousands=".", decimal_point=",") }} -> 1234.56
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn float(
+        value: Value,
+        default: Option<Value>,
+        kwargs: Option<Value>,
+    ) -> Result<Value, Error> {
+        let opts = ok!(NumberParseOptions::from_args(
+            default,
+            kwargs,
+            Value::from(0.0)
+        ));
+        Ok(match value.0 {
+            ValueRepr::Bool(x) => Value::from(x as i64 as f64),
+            ValueRepr::String(ref s, _) => opts
+                .normalize(s)
+                .and_then(|s| s.parse::<f64>().ok())
+                .map_or(opts.default, Value::from),
+            _ => value.as_f64().map_or(opts.default, Value::from),
+        })
+    }
+
+    /// The arguments of the `int` and `float` filters.
+    struct NumberParseOptions {
+        default: Value,
+        decimal_point: Option<String>,
+        thousands: Option<String>,
+    }
+
+    impl NumberParseOptions {
+        fn from_args(
+            default: Option<Value>,
+            kwargs: Option<Value>,
+            fallback: Value,
+        ) -> Result<NumberParseOptions, Error> {
+            let (default, kwargs) = match default {
+                Some(default) if default.is_kwargs() => (None, Some(default)),
+                default => (default, kwargs),
+            };
+            let mut rv = NumberParseOptions {
+                default: default.unwrap_or(fallback),
+                decimal_point: None,
+                thousands: None,
+            };
+            if let Some(kwargs) = kwargs {
+                if !kwargs.is_kwargs() {
+                    return Err(Error::from(ErrorKind::TooManyArguments));
+                }
+                let default = ok!(kwargs.get_attr("default"));
+                if !default.is_undefined() {
+                    rv.default = default;
+                }
+                rv.decimal_point = ok!(separator_arg(&kwargs, "decimal_point"));
+                rv.thousands = ok!(separator_arg(&kwargs, "thousands"));
+            }
+            Ok(rv)
+        }
+
+        /// Converts a number in a string into the format Rust can parse.
+        ///
+        /// Returns `None` if the string does not use the configured separators
+        /// correctly.
+        fn normalize<'s>(&self, s: &'s str) -> Option<Cow<'s, str>> {
+            let s = s.trim();
+            if self.decimal_point.is_none() && self.thousands.is_none() {
+                return Some(Cow::Borrowed(s));
+            }
+            let (sign, unsigned) = match s.strip_prefix('-') {
+                Some(rest) => ("-", rest),
+                None => ("", s.strip_prefix('+').unwrap_or(s)),
+            };
+            // with `.` as thousands separator the decimal point defaults to `,`
+            let decimal_point = match (&self.decimal_point, &self.thousands) {
+                (Some(decimal_point), _) => decimal_point.as_str(),
+                (None, Some(thousands)) if thousands == "." => ",",
+                (None, _) => ".",
+            };
+            let (int_part, frac_part) = match unsigned.split_once(decimal_point) {
+                Some((int_part, frac_part)) => (int_part, Some(frac_part)),
+                None => (unsigned, None),
+            };
+            let int_part = match self.thousands {
+                Some(ref sep) if int_part.contains(sep as &str) => {
+                    let mut groups = int_part.split(sep as &str);
+                    let first = groups.next().unwrap_or("");
+                    if first.is_empty() || first.len() > 3 || !groups.all(|x| x.len() == 3) {
+                        return None;
+                    }
+                    Cow::Owned(int_part.replace(sep as &str, ""))
+                }
+                _ => Cow::Borrowed(int_part),
+            };
+            let is_digits = |x: &str| x.bytes().all(|c| c.is_ascii_digit());
+            if (int_part.is_empty() && frac_part.map_or(true, str::is_empty))
+                || !is_digits(&int_part)
+                || !frac_part.map_or(true, is_digits)
+            {
+                return None;
+            }
+            Some(Cow::Owned(match frac_part {
+                Some(frac_part) => format!("{sign}{int_part}.{frac_part}"),
+                None => format!("{sign}{int_part}"),
+            }))
+        }
+    }
+
+    fn separator_arg(kwargs: &Value, name: &str) -> Result<Option<String>, Error> {
+        let value = ok!(kwargs.get_attr(name));
+        if value.is_undefined() || value.is_none() {
+            return Ok(None);
+        }
+        match value.as_str() {
+            Some(sep) if !sep.is_empty() => Ok(Some(sep.to_string())),
+            _ => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("{name} must be a non-empty string"),
+            )),
+        }
+    }
+
     /// Returns the first item from a list.
     ///
     /// If the list is empty `undefined` is returned.
@@ -618,48 +1088,405 @@ mod builtins {
         }
     }
 
+    /// Compares two values for the ordering filters.
+    ///
+    /// Unlike the comparison operators this is a total order: values that
+    /// cannot be compared with each other are ordered by their kind, where
+    /// `none` comes before all other kinds.  Undefined values (for instance
+    /// missing attributes) go first or last depending on `missing_last`,
+    /// independent of `descending`.  `NaN` is greater than all other values
+    /// and equal to itself.
+    fn cmp_for_sort(a: &Value, b: &Value, descending: bool, missing_last: bool) -> Ordering {
+        match (a.is_undefined(), b.is_undefined()) {
+            (true, true) => Ordering::Equal,
+            (true, false) if missing_last => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, true) if missing_last => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => {
+                let rv = match (is_nan(a), is_nan(b)) {
+                    (true, true) => Ordering::Equal,
+                    (true, false) => Ordering::Greater,
+                    (false, true) => Ordering::Less,
+                    (false, false) => match a.partial_cmp(b) {
+                        Some(rv) => rv,
+                        None => a.kind().cmp(&b.kind()),
+                    },
+                };
+                if descending {
+                    rv.reverse()
+                } else {
+                    rv
+                }
+            }
+        }
+    }
+
+    fn is_nan(value: &Value) -> bool {
+        matches!(value.0, ValueRepr::F64(val) if val.is_nan())
+    }
+
+    /// Options shared by the ordering filters.
+    struct OrderingArgs {
+        attribute: Option<String>,
+        missing_last: bool,
+        reverse: Option<bool>,
+    }
+
+    impl OrderingArgs {
+        fn from_kwargs(kwargs: Option<Value>) -> Result<OrderingArgs, Error> {
+            let mut rv = OrderingArgs {
+                attribute: None,
+                missing_last: false,
+                reverse: None,
+            };
+            if let Some(kwargs) = kwargs {
+                if !kwargs.is_kwargs() {
+                    return Err(Error::from(ErrorKind::TooManyArguments));
+                }
+                let value = ok!(kwargs.get_attr("reverse"));
+                if !value.is_undefined() {
+                    rv.reverse = Some(value.is_true());
+                }
+                let value = ok!(kwargs.get_attr("attribute"));
+                if !value.is_undefined() {
+                    rv.attribute = Some(ok!(value.as_str().map(str::to_string).ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidOperation, "attribute must be a string")
+                    })));
+                }
+                let value = ok!(kwargs.get_attr("missing"));
+                if !value.is_undefined() {
+                    rv.missing_last = match value.as_str() {
+                        Some("first") => false,
+                        Some("last") => true,
+                        _ => {
+                            return Err(Error::new(
+                                ErrorKind::InvalidOperation,
+                                "missing must be \"first\" or \"last\"",
+                            ))
+                        }
+                    };
+                }
+            }
+            Ok(rv)
+        }
+
+        /// Returns the value an item is ordered by.
+        fn key(&self, item: &Value) -> Value {
+            match self.attribute {
+                Some(ref attribute) => item.get_path(attribute).unwrap_or(Value::UNDEFINED),
+                None => item.clone(),
+            }
+        }
+    }
+
     /// Returns the smallest item from the list.
+    ///
+    /// With the `attribute` keyword argument the item with the smallest value
+    /// of that attribute is returned.  Items that lack the attribute are
+    /// skipped.  Items are otherwise ordered like with the [`sort`] filter.
+    ///
+    /// ```jinja
+    /// {{ [3, 1, 2]|min }} -> 1
+    /// {{ users|min(attribute="age") }}
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn min(value: Value) -> Result<Value, Error> {
+    pub fn min(value: Value, kwargs: Option<Value>) -> Result<Value, Error> {
+        let args = ok!(OrderingArgs::from_kwargs(kwargs));
         let iter = ok!(value.try_iter().map_err(|err| {
             Error::new(ErrorKind::InvalidOperation, "cannot convert value to list").with_source(err)
         }));
         Ok(iter
-            .min_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Less))
-            .unwrap_or(Value::UNDEFINED))
+            .map(|item| (args.key(&item), item))
+            .filter(|(key, _)| args.attribute.is_none() || !key.is_undefined())
+            .min_by(|a, b| cmp_for_sort(&a.0, &b.0, false, args.missing_last))
+            .map_or(Value::UNDEFINED, |(_, item)| item))
     }
 
     /// Returns the largest item from the list.
+    ///
+    /// This accepts the same keyword arguments as [`min`].
+    ///
+    /// ```jinja
+    /// {{ [3, 1, 2]|max }} -> 3
+    /// {{ users|max(attribute="age") }}
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn max(value: Value) -> Result<Value, Error> {
+    pub fn max(value: Value, kwargs: Option<Value>) -> Result<Value, Error> {
+        let args = ok!(OrderingArgs::from_kwargs(kwargs));
         let iter = ok!(value.try_iter().map_err(|err| {
             Error::new(ErrorKind::InvalidOperation, "cannot convert value to list").with_source(err)
         }));
         Ok(iter
-            .max_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Less))
-            .unwrap_or(Value::UNDEFINED))
+            .map(|item| (args.key(&item), item))
+            .filter(|(key, _)| args.attribute.is_none() || !key.is_undefined())
+            .max_by(|a, b| cmp_for_sort(&a.0, &b.0, false, args.missing_last))
+            .map_or(Value::UNDEFINED, |(_, item)| item))
     }
 
     /// Returns the sorted version of the given list.
+    ///
+    /// The sort is stable, so items that compare equal keep their relative
+    /// order.  Passing `true` (or `reverse=true`) sorts in descending order.
+    ///
+    /// With the `attribute` keyword argument items are sorted by an attribute
+    /// instead (dotted paths like `user.name` are supported).  Multiple
+    /// attributes can be given separated by commas in which case items are
+    /// sorted by the first attribute, then by the second and so on.  Prefixing
+    /// an attribute with `-` sorts by that attribute in descending order.
+    ///
+    /// The ordering is the same for all ordering filters (`sort`, [`min`] and
+    /// [`max`]): numbers, strings etc. compare as usual, `none` sorts before
+    /// all other values and values of kinds that cannot be compared with each
+    /// other are ordered by their kind.  Undefined values, which is what a
+    /// missing attribute yields, sort before all other values independent of
+    /// the direction.  Pass `missing="last"` to put them last instead.
+    ///
+    /// ```jinja
+    /// {{ numbers|sort }}
+    /// {{ numbers|sort(reverse=true) }}
+    /// {% for row in rows|sort(attribute="dept,-salary") %}...{% endfor %}
+    /// {% for row in rows|sort(attribute="nickname", missing="last") %}...{% endfor %}
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn sort(value: Value, reverse: Option<bool>) -> Result<Value, Error> {
+    pub fn sort(
+        value: Value,
+        reverse: Option<Value>,
+        kwargs: Option<Value>,
+    ) -> Result<Value, Error> {
         let mut items = ok!(value.try_iter().map_err(|err| {
             Error::new(ErrorKind::InvalidOperation, "cannot convert value to list").with_source(err)
         }))
         .collect::<Vec<_>>();
-        items.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Less));
-        if reverse.unwrap_or(false) {
-            items.reverse();
+        let (reverse, kwargs) = match reverse {
+            Some(reverse) if reverse.is_kwargs() => (None, Some(reverse)),
+            reverse => (reverse, kwargs),
+        };
+        let args = ok!(OrderingArgs::from_kwargs(kwargs));
+        let reverse = args
+            .reverse
+            .unwrap_or_else(|| reverse.map_or(false, |x| x.is_true()));
+
+        match args.attribute {
+            None => items.sort_by(|a, b| cmp_for_sort(a, b, reverse, args.missing_last)),
+            Some(ref attribute) => {
+                let sort_keys = attribute
+                    .split(',')
+                    .map(|x| {
+                        let x = x.trim();
+                        match x.strip_prefix('-') {
+                            Some(x) => (x, !reverse),
+                            None => (x, reverse),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let mut keyed = items
+                    .into_iter()
+                    .map(|item| {
+                        let keys = sort_keys
+                            .iter()
+                            .map(|(path, _)| item.get_path(path).unwrap_or(Value::UNDEFINED))
+                            .collect::<Vec<_>>();
+                        (keys, item)
+                    })
+                    .collect::<Vec<_>>();
+                keyed.sort_by(|(a, _), (b, _)| {
+                    for ((a, b), (_, descending)) in a.iter().zip(b.iter()).zip(sort_keys.iter()) {
+                        let rv = cmp_for_sort(a, b, *descending, args.missing_last);
+                        if rv != Ordering::Equal {
+                            return rv;
+                        }
+                    }
+                    Ordering::Equal
+                });
+                items = keyed.into_iter().map(|(_, item)| item).collect();
+            }
         }
         Ok(Value::from(items))
     }
 
+    /// Groups a sequence of objects by an attribute.
+    ///
+    /// The attribute is given as first argument or as `attribute` keyword
+    /// argument and can be a dotted path like `address.city`.  The result is a
+    /// list of groups where each group has a `grouper` attribute holding the
+    /// value that was grouped by and a `list` attribute with the items of that
+    /// group.  Items where the attribute is missing are grouped under the
+    /// value of the `default` keyword argument or `none`.
+    ///
+    /// By default the groups are sorted by their grouper.  With `sort=false`
+    /// groups are returned in the order in which they were first encountered
+    /// instead.  In either case the items within a group keep the order of the
+    /// input.
+    ///
+    /// ```jinja
+    /// <ul>{% for group in users|groupby("city") %}
+    ///   <li>{{ group.grouper }}: {{ group.list|map(attribute="name")|join(", ") }}
+    /// {% endfor %}</ul>
+    /// {% for group in events|groupby(attribute="day", sort=false) %}...{% endfor %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn groupby(
+        value: Value,
+        attribute: Option<Value>,
+        kwargs: Option<Value>,
+    ) -> Result<Value, Error> {
+        let (attribute, kwargs) = match attribute {
+            Some(attribute) if attribute.is_kwargs() => (None, Some(attribute)),
+            attribute => (attribute, kwargs),
+        };
+        let mut attribute = attribute;
+        let mut default = Value::from(());
+        let mut sort = true;
+        if let Some(kwargs) = kwargs {
+            if !kwargs.is_kwargs() {
+                return Err(Error::from(ErrorKind::TooManyArguments));
+            }
+            let value = ok!(kwargs.get_attr("attribute"));
+            if !value.is_undefined() {
+                if attribute.is_some() {
+                    return Err(Error::new(
+                        ErrorKind::TooManyArguments,
+                        "attribute passed both as argument and keyword argument",
+                    ));
+                }
+                attribute = Some(value);
+            }
+            let value = ok!(kwargs.get_attr("default"));
+            if !value.is_undefined() {
+                default = value;
+            }
+            let value = ok!(kwargs.get_attr("sort"));
+            if !value.is_undefined() {
+                sort = value.is_true();
+            }
+        }
+        let attribute = match attribute {
+            Some(attribute) => ok!(attribute.as_str().map(str::to_string).ok_or_else(|| {
+                Error::new(ErrorKind::InvalidOperation, "attribute must be a string")
+            })),
+            None => return Err(Error::from(ErrorKind::MissingArgument)),
+        };
+        let iter = ok!(value.try_iter().map_err(|err| {
+            Error::new(ErrorKind::InvalidOperation, "cannot convert value to list").with_source(err)
+        }));
+
+        let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+        for item in iter {
+            let grouper = match item.get_path(&attribute) {
+                Ok(grouper) if !grouper.is_undefined() => grouper,
+                _ => default.clone(),
+            };
+            match groups.iter_mut().find(|(x, _)| *x == grouper) {
+                Some((_, items)) => items.push(item),
+                None => groups.push((grouper, vec![item])),
+            }
+        }
+        if sort {
+            groups.sort_by(|a, b| cmp_for_sort(&a.0, &b.0, false, true));
+        }
+
+        Ok(Value::from(
+            groups
+                .into_iter()
+                .map(|(grouper, items)| {
+                    [("grouper", grouper), ("list", Value::from(items))]
+                        .into_iter()
+                        .collect::<Value>()
+                })
+                .collect::<Vec<_>>(),
+        ))
+    }
+
+    /// Flattens nested sequences into a single list.
+    ///
+    /// Items of the sequence that are sequences themselves are replaced by
+    /// their items, recursively.  Maps and strings are not flattened.  The
+    /// optional `depth` (also accepted as keyword argument) limits how many
+    /// levels are flattened.  The result is produced lazily, so nested
+    /// sequences are only visited as far as the template consumes the result.
+    ///
+    /// ```jinja
+    /// {{ [["a", "b"], [["c"]], "d"]|flatten|join(" ") }} -> a b c d
+    /// {{ [1, [2, [3]]]|flatten(1) }} -> [1, 2, [3]]
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn flatten(
+        value: Value,
+        depth: Option<Value>,
+        kwargs: Option<Value>,
+    ) -> Result<Value, Error> {
+        let (depth, kwargs) = match depth {
+            Some(depth) if depth.is_kwargs() => (None, Some(depth)),
+            depth => (depth, kwargs),
+        };
+        let mut depth = depth;
+        if let Some(kwargs) = kwargs {
+            if !kwargs.is_kwargs() {
+                return Err(Error::from(ErrorKind::TooManyArguments));
+            }
+            let value = ok!(kwargs.get_attr("depth"));
+            if !value.is_undefined() {
+                depth = Some(value);
+            }
+        }
+        let max_depth = match depth {
+            Some(depth) if !depth.is_none() => Some(ok!(usize::try_from(depth))),
+            _ => None,
+        };
+        if value.kind() != ValueKind::Seq {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("cannot flatten {}", value.kind()),
+            ));
+        }
+        Ok(Value::from_iterator(Flatten {
+            stack: vec![(value, 0)],
+            max_depth,
+        }))
+    }
+
+    /// Lazy iterator for the `flatten` filter.
+    struct Flatten {
+        /// the sequences currently being flattened and the next index in each
+        stack: Vec<(Value, usize)>,
+        max_depth: Option<usize>,
+    }
+
+    impl Iterator for Flatten {
+        type Item = Value;
+
+        fn next(&mut self) -> Option<Value> {
+            loop {
+                let depth = self.stack.len();
+                let (seq, idx) = self.stack.last_mut()?;
+                let item = match seq.as_seq().and_then(|x| x.get_item(*idx)) {
+                    Some(item) => item,
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                };
+                *idx += 1;
+                if item.kind() == ValueKind::Seq
+                    && self.max_depth.map_or(true, |max_depth| depth <= max_depth)
+                {
+                    self.stack.push((item, 0));
+                } else {
+                    return Some(item);
+                }
+            }
+        }
+    }
+
     /// Converts the input value into a list.
     ///
     /// If the value is already a list, then it's returned unchanged.
     /// Applied to a map this returns the list of keys, applied to a
     /// string this returns the characters.  If the value is undefined
-    /// an empty list is returned.
+    /// an empty list is returned.  Dynamic sequence objects are fully
+    /// materialized into a list which is useful if an expensive object
+    /// needs to be accessed repeatedly.
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     pub fn list(value: Value) -> Result<Value, Error> {
         let iter = ok!(value.try_iter().map_err(|err| {
@@ -668,6 +1495,34 @@ mod builtins {
         Ok(Value::from(iter.collect::<Vec<_>>()))
     }
 
+    /// Pretty prints a value for debugging.
+    ///
+    /// Sequences and maps are printed over multiple lines with one item per
+    /// line.  This also works for dynamic objects.  With the `max_depth`
+    /// keyword argument containers nested deeper than the given depth are
+    /// rendered as `...`.  Objects that (directly or indirectly) contain
+    /// themselves are detected and the repeated object is rendered as
+    /// `<cycle>` so that it's safe to print arbitrary objects.
+    ///
+    /// ```jinja
+    /// <pre>{{ config|pprint }}</pre>
+    /// <pre>{{ config|pprint(max_depth=2) }}</pre>
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn pprint(value: &Value, kwargs: Option<Value>) -> Result<String, Error> {
+        let mut max_depth = None;
+        if let Some(kwargs) = kwargs {
+            if !kwargs.is_kwargs() {
+                return Err(Error::from(ErrorKind::TooManyArguments));
+            }
+            let value = ok!(kwargs.get_attr("max_depth"));
+            if !value.is_undefined() && !value.is_none() {
+                max_depth = Some(ok!(usize::try_from(value)));
+            }
+        }
+        Ok(PrettyPrinter::new(max_depth, false).print(value))
+    }
+
     /// Converts the value into a boolean value.
     ///
     /// This behaves the same as the if statement does with regards to
@@ -783,42 +1638,113 @@ mod builtins {
     ///
     /// This filter is only available if the `json` feature is enabled.  The resulting
     /// value is safe to use in HTML as well as it will not contain any special HTML
-    /// characters.  The optional parameter to the filter can be set to `true` to enable
-    /// pretty printing.  Not that the `"` character is left unchanged as it's the
-    /// JSON string delimiter.  If you want to pass JSON serialized this way into an
-    /// HTTP attribute use single quoted HTML attributes:
+    /// characters (`<`, `>`, `&`, `'` and `/` as well as the JavaScript line
+    /// separators U+2028 and U+2029 are escaped).  The optional parameter to the
+    /// filter can be set to `true` to enable pretty printing or to an integer to
+    /// pretty print with that many spaces of indentation.  It can also be passed as
+    /// `indent` keyword argument.  Note that the `"` character is left unchanged as
+    /// it's the JSON string delimiter.  If you want to pass JSON serialized this way
+    /// into an HTTP attribute use single quoted HTML attributes:
     ///
     /// ```jinja
     /// <script>
     ///   const GLOBAL_CONFIG = {{ global_config|tojson }};
     /// </script>
     /// <a href="#" data-info='{{ json_object|tojson }}'>...</a>
+    /// <pre>{{ debug_info|tojson(indent=4) }}</pre>
+    /// ```
+    ///
+    /// Map keys are emitted in the order of the map unless the `sort_keys`
+    /// keyword argument is set to `true`.  The default for this can be changed
+    /// with [`Environment::set_json_sort_keys`](crate::Environment::set_json_sort_keys).
+    /// Likewise output is compact unless an indentation is given or pretty
+    /// printing was turned on with
+    /// [`Environment::set_json_pretty`](crate::Environment::set_json_pretty).
+    ///
+    /// ```jinja
+    /// {{ config|tojson(sort_keys=true) }}
+    /// {{ config|tojson(2, sort_keys=true) }}
     /// ```
+    ///
+    /// Floats are normalized so that `-0.0` is emitted as `0.0`.  `NaN` and
+    /// infinite floats cannot be represented in JSON and are emitted as `null`
+    /// unless [`Environment::set_json_strict_floats`](crate::Environment::set_json_strict_floats)
+    /// is enabled in which case serializing them fails.
+    ///
+    /// Dynamic objects are serialized through
+    /// [`Object::to_serializable_value`](crate::value::Object::to_serializable_value).
+    /// Plain objects that do not provide such a value cannot be serialized.
+    ///
+    /// By default this filter is also registered under the alias `json`.
+    /// For JSON representable data the output can be turned back into an
+    /// equal value with [`fromjson`]: `{{ (x|tojson|fromjson) == x }}`.
     #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "json"))))]
     #[cfg(feature = "json")]
-    pub fn tojson(value: Value, pretty: Option<bool>) -> Result<Value, Error> {
-        if pretty.unwrap_or(false) {
-            serde_json::to_string_pretty(&value)
-        } else {
-            serde_json::to_string(&value)
-        }
-        .map_err(|err| {
-            Error::new(ErrorKind::InvalidOperation, "cannot serialize to JSON").with_source(err)
-        })
-        .map(|s| {
-            // When this filter is used the return value is safe for both HTML and JSON
-            let mut rv = String::with_capacity(s.len());
-            for c in s.chars() {
-                match c {
-                    '<' => rv.push_str("\\u003c"),
-                    '>' => rv.push_str("\\u003e"),
-                    '&' => rv.push_str("\\u0026"),
-                    '\'' => rv.push_str("\\u0027"),
-                    _ => rv.push(c),
+    pub fn tojson(
+        state: &State,
+        value: Value,
+        indent: Option<Value>,
+        kwargs: Option<Value>,
+    ) -> Result<Value, Error> {
+        let mut json = json_options(state);
+        let (mut indent, kwargs) = match indent {
+            Some(indent) if indent.is_kwargs() => (Value::UNDEFINED, Some(indent)),
+            Some(indent) => (indent, kwargs),
+            None => (Value::UNDEFINED, kwargs),
+        };
+        if let Some(kwargs) = kwargs {
+            if !kwargs.is_kwargs() {
+                return Err(Error::from(ErrorKind::TooManyArguments));
+            }
+            let flag = ok!(kwargs.get_attr("sort_keys"));
+            if !flag.is_undefined() {
+                json.sort_keys = flag.is_true();
+            }
+            let value = ok!(kwargs.get_attr("indent"));
+            if !value.is_undefined() {
+                if !indent.is_undefined() {
+                    return Err(Error::new(
+                        ErrorKind::TooManyArguments,
+                        "indent passed both as argument and keyword argument",
+                    ));
                 }
+                indent = value;
             }
-            Value::from_safe_string(rv)
-        })
+        }
+        let indent = match indent.0 {
+            ValueRepr::Undefined => json.default_indent(),
+            ValueRepr::None | ValueRepr::Bool(false) => None,
+            ValueRepr::Bool(true) => Some(2),
+            _ => Some(ok!(usize::try_from(indent))),
+        };
+        crate::utils::to_json_string(&value, indent, json)
+            .map_err(|err| {
+                Error::new(ErrorKind::InvalidOperation, "cannot serialize to JSON").with_source(err)
+            })
+            .map(|s| {
+                // When this filter is used the return value is safe for both HTML and JSON
+                Value::from_safe_string(crate::utils::escape_json_for_html(&s, true))
+            })
+    }
+
+    /// Parses a JSON string into a value.
+    ///
+    /// This filter is only available if the `json` feature is enabled.  It's
+    /// the inverse of [`tojson`] (also available as `json`) and can be used
+    /// to navigate JSON data that is passed to the template as string.
+    /// Invalid JSON results in an error.
+    ///
+    /// ```jinja
+    /// {{ (payload|fromjson).user.name }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "json"))))]
+    #[cfg(feature = "json")]
+    pub fn fromjson(value: &str) -> Result<Value, Error> {
+        serde_json::from_str::<serde_json::Value>(value)
+            .map(|x| Value::from_serializable(&x))
+            .map_err(|err| {
+                Error::new(ErrorKind::CannotDeserialize, "cannot parse JSON").with_source(err)
+            })
     }
 
     /// indents Value with spaces
@@ -872,11 +1798,33 @@ mod builtins {
         output
     }
 
+    /// Encodes a value as TOML.
+    ///
+    /// This filter is only available if the `toml` feature is enabled.  The
+    /// value has to be a map as TOML documents are always tables.  Values that
+    /// cannot be represented in TOML (such as none) result in an error.
+    ///
+    /// ```jinja
+    /// [package.metadata]
+    /// {{ metadata|tomlencode }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "toml"))))]
+    #[cfg(feature = "toml")]
+    pub fn tomlencode(value: Value) -> Result<String, Error> {
+        // going through the toml value type ensures that plain values are
+        // emitted before tables as TOML requires.
+        toml::Value::try_from(&value)
+            .and_then(|value| toml::to_string(&value))
+            .map_err(|err| {
+                Error::new(ErrorKind::BadSerialization, "cannot serialize to TOML").with_source(err)
+            })
+    }
+
     /// URL encodes a value.
     ///
-    /// If given a map it encodes the parameters into a query set, otherwise it
-    /// encodes the stringified value.  If the value is none or undefined, an
-    /// empty string is returned.
+    /// If given a map it encodes the parameters into a query set with the keys
+    /// in sorted order, otherwise it encodes the stringified value.  If the
+    /// value is none or undefined, an empty string is returned.
     ///
     /// ```jinja
     /// <a href="/search?{{ {"q": "my search", "lang": "fr"}|urlencode }}">Search</a>
@@ -892,12 +1840,21 @@ mod builtins {
             .add(b' ');
 
         if value.kind() == ValueKind::Map {
+            let mut items = ok!(value.try_iter_pairs()).collect::<Vec<_>>();
+            items.sort_by(|a, b| {
+                match (
+                    Key::from_borrowed_value(&a.0),
+                    Key::from_borrowed_value(&b.0),
+                ) {
+                    (Ok(a), Ok(b)) => a.cmp(&b),
+                    _ => Ordering::Equal,
+                }
+            });
             let mut rv = String::new();
-            for (idx, k) in ok!(value.try_iter()).enumerate() {
+            for (idx, (k, v)) in items.into_iter().enumerate() {
                 if idx > 0 {
                     rv.push('&');
                 }
-                let v = ok!(value.get_item(&k));
                 write!(
                     rv,
                     "{}={}",
@@ -919,6 +1876,389 @@ mod builtins {
         }
     }
 
+    /// URL decodes a value.
+    ///
+    /// This reverses [`urlencode`] for strings: percent escapes are decoded
+    /// and `+` is turned into a space.  If the value is none or undefined, an
+    /// empty string is returned.
+    ///
+    /// ```jinja
+    /// {{ "hello%20world+again"|urldecode }} -> hello world again
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "urlencode"))))]
+    #[cfg(feature = "urlencode")]
+    pub fn urldecode(value: Value) -> Result<String, Error> {
+        if value.is_undefined() || value.is_none() {
+            return Ok("".into());
+        }
+        let value = value.to_cowstr().replace('+', " ");
+        percent_encoding::percent_decode_str(&value)
+            .decode_utf8()
+            .map(|rv| rv.into_owned())
+            .map_err(|err| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    "urldecoded value is not valid utf-8",
+                )
+                .with_source(err)
+            })
+    }
+
+    /// Encodes a string or bytes value as base64.
+    ///
+    /// The standard alphabet is used unless `urlsafe=true` is passed in which
+    /// case `-` and `_` take the place of `+` and `/`.  This filter is only
+    /// available if the `base64` feature is enabled.
+    ///
+    /// ```jinja
+    /// {{ "hello world"|b64encode }} -> aGVsbG8gd29ybGQ=
+    /// {{ token|b64encode(urlsafe=true) }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "base64"))))]
+    #[cfg(feature = "base64")]
+    pub fn b64encode(value: Value, kwargs: Option<Value>) -> Result<String, Error> {
+        let urlsafe = ok!(b64_flag(&kwargs, "urlsafe"));
+        match value.as_bytes() {
+            Some(data) => Ok(crate::utils::b64encode(data, urlsafe)),
+            None => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("cannot base64 encode {}", value.kind()),
+            )),
+        }
+    }
+
+    /// Decodes a base64 encoded string.
+    ///
+    /// The result are the decoded bytes.  If `string=true` is passed the bytes
+    /// are instead decoded into a string (invalid UTF-8 is replaced).  Pass
+    /// `urlsafe=true` to decode the URL-safe alphabet.  Padding is optional,
+    /// invalid base64 data is an error.  This filter is only available if the
+    /// `base64` feature is enabled.
+    ///
+    /// ```jinja
+    /// {{ "aGVsbG8gd29ybGQ="|b64decode(string=true) }} -> hello world
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "base64"))))]
+    #[cfg(feature = "base64")]
+    pub fn b64decode(value: Cow<'_, str>, kwargs: Option<Value>) -> Result<Value, Error> {
+        let urlsafe = ok!(b64_flag(&kwargs, "urlsafe"));
+        let as_string = ok!(b64_flag(&kwargs, "string"));
+        let data = ok!(crate::utils::b64decode(&value, urlsafe));
+        if as_string {
+            Ok(Value::from(String::from_utf8_lossy(&data).into_owned()))
+        } else {
+            Ok(Value::from(data))
+        }
+    }
+
+    /// Replaces matches of a regular expression.
+    ///
+    /// The replacement can refer to capture groups with `$1` or `${name}`.
+    /// The optional `count` argument (also accepted as keyword argument)
+    /// limits the number of replacements, `0` (the default) replaces all
+    /// matches.  This filter is only available if the `regex` feature is
+    /// enabled.
+    ///
+    /// ```jinja
+    /// {{ "2023-01-15"|regex_replace("(\\d+)-(\\d+)-(\\d+)", "$3.$2.$1") }}
+    ///   -> 15.01.2023
+    /// {{ "a a a"|regex_replace("a", "b", count=1) }} -> b a a
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "regex"))))]
+    #[cfg(feature = "regex")]
+    pub fn regex_replace(
+        v: Cow<'_, str>,
+        pattern: Cow<'_, str>,
+        replacement: Cow<'_, str>,
+        count: Option<Value>,
+    ) -> Result<String, Error> {
+        let count = match count {
+            Some(count) if count.is_kwargs() => ok!(count.get_attr("count")),
+            Some(count) => count,
+            None => Value::UNDEFINED,
+        };
+        let count = if count.is_undefined() || count.is_none() {
+            0
+        } else {
+            ok!(usize::try_from(count))
+        };
+        let re = ok!(crate::utils::compile_regex(&pattern));
+        Ok(re.replacen(&v, count, &replacement as &str).into_owned())
+    }
+
+    /// Searches for the first match of a regular expression.
+    ///
+    /// Returns the matched part of the string or `none` if the pattern does
+    /// not match.  This filter is only available if the `regex` feature is
+    /// enabled.
+    ///
+    /// ```jinja
+    /// {{ "release v1.2.3"|regex_search("v[\\d.]+") }} -> v1.2.3
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "regex"))))]
+    #[cfg(feature = "regex")]
+    pub fn regex_search(v: Cow<'_, str>, pattern: Cow<'_, str>) -> Result<Value, Error> {
+        let re = ok!(crate::utils::compile_regex(&pattern));
+        Ok(re
+            .find(&v)
+            .map_or(Value::from(()), |m| Value::from(m.as_str())))
+    }
+
+    /// Returns all non overlapping matches of a regular expression.
+    ///
+    /// If the pattern has no capture groups a list of the matched strings is
+    /// returned.  With a single group a list of that group's matches is
+    /// returned, with multiple groups a list of lists with one item per group.
+    /// This filter is only available if the `regex` feature is enabled.
+    ///
+    /// ```jinja
+    /// {{ "a=1, b=2"|regex_findall("\\d") }} -> ["1", "2"]
+    /// {{ "a=1, b=2"|regex_findall("(\\w)=(\\d)") }} -> [["a", "1"], ["b", "2"]]
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "regex"))))]
+    #[cfg(feature = "regex")]
+    pub fn regex_findall(v: Cow<'_, str>, pattern: Cow<'_, str>) -> Result<Value, Error> {
+        fn group(m: Option<regex::Match<'_>>) -> Value {
+            Value::from(m.map_or("", |m| m.as_str()))
+        }
+
+        let re = ok!(crate::utils::compile_regex(&pattern));
+        Ok(re
+            .captures_iter(&v)
+            .map(|caps| match caps.len() {
+                1 => group(caps.get(0)),
+                2 => group(caps.get(1)),
+                _ => caps.iter().skip(1).map(group).collect(),
+            })
+            .collect())
+    }
+
+    /// Options shared by `numberformat` and `currencyformat`.
+    #[cfg(feature = "number_format")]
+    struct NumberFormat {
+        decimals: usize,
+        thousands: String,
+        decimal_point: String,
+        parens: bool,
+    }
+
+    #[cfg(feature = "number_format")]
+    impl NumberFormat {
+        fn from_kwargs(kwargs: &Value, default_decimals: usize) -> Result<NumberFormat, Error> {
+            fn get_str(kwargs: &Value, key: &str, default: &str) -> Result<String, Error> {
+                let value = ok!(kwargs.get_attr(key));
+                if value.is_undefined() {
+                    Ok(default.to_string())
+                } else if let Some(s) = value.as_str() {
+                    Ok(s.to_string())
+                } else {
+                    Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        format!("{} must be a string", key),
+                    ))
+                }
+            }
+
+            let decimals = ok!(kwargs.get_attr("decimals"));
+            let decimals = if decimals.is_undefined() {
+                default_decimals
+            } else {
+                ok!(usize::try_from(decimals))
+            };
+            let negative = ok!(get_str(kwargs, "negative", "minus"));
+            Ok(NumberFormat {
+                decimals,
+                thousands: ok!(get_str(kwargs, "thousands", ",")),
+                decimal_point: ok!(get_str(kwargs, "decimal_point", ".")),
+                parens: match &negative as &str {
+                    "minus" => false,
+                    "parens" => true,
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidOperation,
+                            "negative must be \"minus\" or \"parens\"",
+                        ))
+                    }
+                },
+            })
+        }
+
+        /// Formats the absolute value and returns it with the sign.
+        fn format_abs(&self, value: &Value) -> Result<(bool, String), Error> {
+            let (negative, int_part, frac_part) = match value.0 {
+                ValueRepr::F64(f) if f.is_finite() => {
+                    let formatted = format!("{:.*}", self.decimals, f.abs());
+                    let (int_part, frac_part) = match formatted.split_once('.') {
+                        Some((i, f)) => (i.to_string(), f.to_string()),
+                        None => (formatted, String::new()),
+                    };
+                    // don't show a sign if the value rounds to zero
+                    let is_zero = int_part.bytes().chain(frac_part.bytes()).all(|c| c == b'0');
+                    (f < 0.0 && !is_zero, int_part, frac_part)
+                }
+                ValueRepr::U64(_)
+                | ValueRepr::I64(_)
+                | ValueRepr::U128(_)
+                | ValueRepr::I128(_)
+                | ValueRepr::Bool(_) => {
+                    let v = ok!(i128::try_from(value.clone()));
+                    (
+                        v < 0,
+                        v.unsigned_abs().to_string(),
+                        "0".repeat(self.decimals),
+                    )
+                }
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        format!("cannot format value of type {} as number", value.kind()),
+                    ))
+                }
+            };
+
+            let mut rv = String::new();
+            for (idx, c) in int_part.chars().enumerate() {
+                if idx > 0 && (int_part.len() - idx) % 3 == 0 {
+                    rv.push_str(&self.thousands);
+                }
+                rv.push(c);
+            }
+            if !frac_part.is_empty() {
+                rv.push_str(&self.decimal_point);
+                rv.push_str(&frac_part);
+            }
+            Ok((negative, rv))
+        }
+
+        fn wrap_sign(&self, negative: bool, s: String) -> String {
+            match (negative, self.parens) {
+                (false, _) => s,
+                (true, false) => format!("-{}", s),
+                (true, true) => format!("({})", s),
+            }
+        }
+    }
+
+    /// Formats a number with grouped thousands and a fixed number of decimals.
+    ///
+    /// This filter is only available if the `number_format` feature is enabled.
+    /// It accepts integers and floats and can be configured with the following
+    /// keyword arguments:
+    ///
+    /// * `decimals`: the number of decimal places (defaults to `2`)
+    /// * `thousands`: the thousands separator (defaults to `","`)
+    /// * `decimal_point`: the decimal separator (defaults to `"."`)
+    /// * `negative`: `"minus"` to render negative numbers with a leading minus
+    ///   (the default) or `"parens"` to wrap them in parentheses.
+    ///
+    /// No locale database is consulted, so other conventions have to be
+    /// configured explicitly:
+    ///
+    /// ```jinja
+    /// {{ 1234567.891|numberformat }} -> 1,234,567.89
+    /// {{ 1234567.891|numberformat(thousands=".", decimal_point=",") }} -> 1.234.567,89
+    /// {{ -42|numberformat(decimals=0, negative="parens") }} -> (42)
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "number_format"))))]
+    #[cfg(feature = "number_format")]
+    pub fn numberformat(value: Value, kwargs: Option<Value>) -> Result<String, Error> {
+        let kwargs = ok!(number_format_kwargs(kwargs));
+        let opts = ok!(NumberFormat::from_kwargs(&kwargs, 2));
+        let (negative, rv) = ok!(opts.format_abs(&value));
+        Ok(opts.wrap_sign(negative, rv))
+    }
+
+    /// Formats a number as an amount of money.
+    ///
+    /// This filter is only available if the `number_format` feature is enabled.
+    /// It formats like [`numberformat`] and accepts the same keyword arguments
+    /// and additionally:
+    ///
+    /// * `code`: the ISO 4217 currency code (defaults to `"USD"`).  A handful of
+    ///   common currencies are rendered with their symbol, all others are
+    ///   prefixed with the code.  The code also picks the default number of
+    ///   decimals (eg: `0` for `JPY`).
+    /// * `symbol`: overrides the currency symbol.
+    /// * `symbol_after`: if set to `true` the symbol is placed after the amount,
+    ///   separated by a space.
+    ///
+    /// ```jinja
+    /// {{ 1234.5|currencyformat }} -> $1,234.50
+    /// {{ 1234.5|currencyformat(code="EUR", thousands=".", decimal_point=",", symbol_after=true) }} -> 1.234,50 €
+    /// {{ -5|currencyformat(negative="parens") }} -> ($5.00)
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "number_format"))))]
+    #[cfg(feature = "number_format")]
+    pub fn currencyformat(value: Value, kwargs: Option<Value>) -> Result<String, Error> {
+        let kwargs = ok!(number_format_kwargs(kwargs));
+        let code = ok!(kwargs.get_attr("code"));
+        let code = if code.is_undefined() {
+            "USD".to_string()
+        } else {
+            match code.as_str() {
+                Some(code) => code.to_ascii_uppercase(),
+                None => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        "code must be a string",
+                    ))
+                }
+            }
+        };
+        let (default_symbol, default_decimals) = match &code as &str {
+            "USD" => ("$".to_string(), 2),
+            "EUR" => ("€".to_string(), 2),
+            "GBP" => ("£".to_string(), 2),
+            "JPY" => ("¥".to_string(), 0),
+            "INR" => ("₹".to_string(), 2),
+            _ => (code.clone(), 2),
+        };
+        let symbol = ok!(kwargs.get_attr("symbol"));
+        let symbol = if symbol.is_undefined() {
+            default_symbol
+        } else {
+            symbol.to_string()
+        };
+        let symbol_after = ok!(kwargs.get_attr("symbol_after")).is_true();
+
+        let opts = ok!(NumberFormat::from_kwargs(&kwargs, default_decimals));
+        let (negative, amount) = ok!(opts.format_abs(&value));
+        let rv = if symbol_after {
+            format!("{} {}", amount, symbol)
+        } else if symbol.chars().all(|c| c.is_alphabetic()) {
+            format!("{} {}", symbol, amount)
+        } else {
+            format!("{}{}", symbol, amount)
+        };
+        Ok(opts.wrap_sign(negative, rv))
+    }
+
+    #[cfg(feature = "number_format")]
+    fn number_format_kwargs(kwargs: Option<Value>) -> Result<Value, Error> {
+        match kwargs {
+            None => Ok(Value::from(
+                std::collections::BTreeMap::<String, Value>::new(),
+            )),
+            Some(kwargs) if kwargs.is_kwargs() => Ok(kwargs),
+            Some(_) => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "number formatting filters only accept keyword arguments",
+            )),
+        }
+    }
+
+    #[cfg(feature = "base64")]
+    fn b64_flag(kwargs: &Option<Value>, name: &str) -> Result<bool, Error> {
+        match kwargs {
+            None => Ok(false),
+            Some(kwargs) if kwargs.is_kwargs() => Ok(ok!(kwargs.get_attr(name)).is_true()),
+            Some(_) => Err(Error::new(
+                ErrorKind::InvalidOperation,
+                "base64 filters only accept keyword arguments",
+            )),
+        }
+    }
+
     #[cfg(feature = "builtins")]
     fn select_or_reject(
         state: &State,
@@ -1004,7 +2344,13 @@ mod builtins {
 
     /// Creates a new sequence of values that don't pass a test.
     ///
-    /// This is the inverse of [`select`].
+    /// This is the inverse of [`select`].  Without a test, all truthy values
+    /// are removed.
+    ///
+    /// ```jinja
+    /// {{ [1, 2, 3, 4]|reject("odd") }} -> [2, 4]
+    /// {{ ["a", "", none, 0, "b"]|reject|length }} -> 3
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     #[cfg(feature = "builtins")]
     pub fn reject(
@@ -1063,6 +2409,14 @@ mod builtins {
     /// ```jinja
     /// Users on this page: {{ titles|map('lower')|join(', ') }}
     /// ```
+    ///
+    /// All further positional and keyword arguments are passed to the filter
+    /// for each item:
+    ///
+    /// ```jinja
+    /// {{ prices|map('round', 2)|join(', ') }}
+    /// {{ values|map('float', decimal_point=',', default=none) }}
+    /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
     #[cfg(feature = "builtins")]
     pub fn map(
@@ -1070,60 +2424,101 @@ mod builtins {
         value: Value,
         args: crate::value::Rest<Value>,
     ) -> Result<Vec<Value>, Error> {
+        let mapping = ok!(Mapping::from_args(state, &args));
+        let mut rv = Vec::with_capacity(value.len().unwrap_or(0));
+        for value in ok!(value.try_iter()) {
+            rv.push(ok!(mapping.apply(state, value)));
+        }
+        Ok(rv)
+    }
+
+    /// Applies a filter to the values of a map or looks up an attribute.
+    ///
+    /// This works like [`map`] but the input has to be a map.  The keys are
+    /// kept and only the values are mapped, the result is a new map.  (The
+    /// [`map`] filter on a map operates on the keys as that's what iterating
+    /// over a map yields.)
+    ///
+    /// ```jinja
+    /// {{ scores|mapvalues("round", 1) }}
+    /// {{ users_by_id|mapvalues(attribute="name", default="Anonymous") }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn mapvalues(
+        state: &State,
+        value: Value,
+        args: crate::value::Rest<Value>,
+    ) -> Result<Value, Error> {
+        let mapping = ok!(Mapping::from_args(state, &args));
         let mut rv = Vec::with_capacity(value.len().unwrap_or(0));
+        for (key, value) in ok!(value.try_iter_pairs()) {
+            rv.push((ok!(key.try_into_key()), ok!(mapping.apply(state, value))));
+        }
+        Ok(rv.into_iter().collect())
+    }
 
-        // attribute mapping
-        if args.last().map_or(false, |x| x.is_kwargs()) {
-            let kwargs = args.last().unwrap();
-            if let Some(attr) = kwargs
-                .get_attr("attribute")
-                .ok()
-                .filter(|x| !x.is_undefined())
-            {
-                // TODO: extra arguments shouldn't be ignored
-                if args.len() > 1 {
-                    return Err(Error::new(
-                        ErrorKind::InvalidOperation,
-                        "too many arguments",
-                    ));
+    /// The operation performed by [`map`] and [`mapvalues`] on each item.
+    enum Mapping<'a> {
+        Attribute(Value, Option<Value>),
+        Filter(&'a BoxedFilter, &'a [Value]),
+    }
+
+    impl<'a> Mapping<'a> {
+        fn from_args(state: &'a State, args: &'a [Value]) -> Result<Mapping<'a>, Error> {
+            // attribute mapping
+            if let Some(kwargs) = args.last().filter(|x| x.is_kwargs()) {
+                if let Some(attr) = kwargs
+                    .get_attr("attribute")
+                    .ok()
+                    .filter(|x| !x.is_undefined())
+                {
+                    // TODO: extra arguments shouldn't be ignored
+                    if args.len() > 1 {
+                        return Err(Error::new(
+                            ErrorKind::InvalidOperation,
+                            "too many arguments",
+                        ));
+                    }
+                    return Ok(Mapping::Attribute(attr, kwargs.get_attr("default").ok()));
                 }
-                let default = kwargs.get_attr("default").ok();
-                for value in ok!(value.try_iter()) {
+            }
+
+            // filter mapping
+            let filter_name = ok!(args
+                .first()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidOperation, "filter name is required")));
+            let filter_name = ok!(filter_name.as_str().ok_or_else(|| {
+                Error::new(ErrorKind::InvalidOperation, "filter name must be a string")
+            }));
+            let filter = ok!(state
+                .env
+                .get_filter(filter_name)
+                .ok_or_else(|| Error::from(ErrorKind::UnknownFilter)));
+            Ok(Mapping::Filter(filter, &args[1..]))
+        }
+
+        fn apply(&self, state: &State, value: Value) -> Result<Value, Error> {
+            match *self {
+                Mapping::Attribute(ref attr, ref default) => {
                     let sub_val = match attr.as_str() {
                         Some(path) => value.get_path(path),
-                        None => value.get_item(&attr),
+                        None => value.get_item(attr),
                     };
-                    rv.push(match (sub_val, &default) {
-                        (Ok(attr), _) => attr,
-                        (Err(err), None) => return Err(err),
-                        (Err(_), Some(default)) => default.clone(),
-                    });
+                    match (sub_val, default) {
+                        (Ok(attr), _) => Ok(attr),
+                        (Err(err), None) => Err(err),
+                        (Err(_), Some(default)) => Ok(default.clone()),
+                    }
+                }
+                Mapping::Filter(filter, args) => {
+                    let new_args = Some(value)
+                        .into_iter()
+                        .chain(args.iter().cloned())
+                        .collect::<Vec<_>>();
+                    filter.apply_to(state, &new_args)
                 }
-                return Ok(rv);
             }
         }
-
-        // filter mapping
-        let filter_name = ok!(args
-            .0
-            .first()
-            .ok_or_else(|| Error::new(ErrorKind::InvalidOperation, "filter name is required")));
-        let filter_name = ok!(filter_name.as_str().ok_or_else(|| {
-            Error::new(ErrorKind::InvalidOperation, "filter name must be a string")
-        }));
-
-        let filter = ok!(state
-            .env
-            .get_filter(filter_name)
-            .ok_or_else(|| Error::from(ErrorKind::UnknownFilter)));
-        for value in ok!(value.try_iter()) {
-            let new_args = Some(value.clone())
-                .into_iter()
-                .chain(args.0.iter().skip(1).cloned())
-                .collect::<Vec<_>>();
-            rv.push(ok!(filter.apply_to(state, &new_args)));
-        }
-        Ok(rv)
     }
 
     #[test]
@@ -298,19 +298,58 @@ mod builtins {
         }
     }
 
+    /// Returns the first argument that is neither undefined nor none.
+    ///
+    /// If there is no such argument `none` is returned.  When the `skip_empty`
+    /// keyword argument is set to `true`, empty strings are skipped as well.
+    ///
+    /// ```jinja
+    /// {{ coalesce(user.nickname, user.name, "Anonymous") }}
+    /// {{ coalesce(user.nickname, user.name, "Anonymous", skip_empty=true) }}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn coalesce(args: crate::value::Rest<Value>) -> Result<Value, Error> {
+        let mut args = &args[..];
+        let mut skip_empty = false;
+        if let Some((kwargs, rest)) = args.split_last() {
+            if kwargs.is_kwargs() {
+                skip_empty = ok!(kwargs.get_attr("skip_empty")).is_true();
+                args = rest;
+            }
+        }
+        Ok(args
+            .iter()
+            .find(|value| {
+                !(value.is_undefined()
+                    || value.is_none()
+                    || (skip_empty && value.as_str() == Some("")))
+            })
+            .cloned()
+            .unwrap_or(Value::from(())))
+    }
+
     /// Outputs the current context stringified.
     ///
     /// This is a useful function to quickly figure out the state of affairs
     /// in a template.  It emits a stringified debug dump of the current
-    /// engine state including the layers of the context, the current block
-    /// and auto escaping setting.
+    /// engine state including the layers of the context (with the `loop`
+    /// variable if inside a loop), the current block, the auto escaping
+    /// setting as well as the globals, filters and tests registered on the
+    /// environment.
+    ///
+    /// If arguments are passed only those values are dumped instead:
     ///
     /// ```jinja
     /// <pre>{{ debug() }}</pre>
+    /// <pre>{{ debug(user, loop) }}</pre>
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn debug(state: &State) -> String {
-        format!("{state:#?}")
+    pub fn debug(state: &State, args: crate::value::Rest<Value>) -> String {
+        match &args[..] {
+            [] => format!("{state:#?}"),
+            [value] => format!("{value:#?}"),
+            values => format!("{values:#?}"),
+        }
     }
 }
 
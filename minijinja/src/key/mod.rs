@@ -81,14 +81,14 @@ impl<'a> Key<'a> {
             ValueRepr::Bool(v) => Ok(Key::Bool(v)),
             ValueRepr::U64(v) => TryFrom::try_from(v)
                 .map(Key::I64)
-                .map_err(|_| ErrorKind::NonKey.into()),
+                .map_err(|_| invalid_key(value)),
             ValueRepr::U128(v) => TryFrom::try_from(v.0)
                 .map(Key::I64)
-                .map_err(|_| ErrorKind::NonKey.into()),
+                .map_err(|_| invalid_key(value)),
             ValueRepr::I64(v) => Ok(Key::I64(v)),
             ValueRepr::I128(v) => TryFrom::try_from(v.0)
                 .map(Key::I64)
-                .map_err(|_| ErrorKind::NonKey.into()),
+                .map_err(|_| invalid_key(value)),
             ValueRepr::F64(x) => {
                 // if a float is in fact looking like an integer we
                 // allow this to be used for indexing.  Why?  Because
@@ -98,16 +98,24 @@ impl<'a> Key<'a> {
                 if intval as f64 == x {
                     Ok(Key::I64(intval))
                 } else {
-                    Err(ErrorKind::NonKey.into())
+                    Err(invalid_key(value))
                 }
             }
             ValueRepr::Char(c) => Ok(Key::Char(c)),
             ValueRepr::String(ref s, _) => Ok(Key::Str(s)),
-            _ => Err(ErrorKind::NonKey.into()),
+            _ => Err(invalid_key(value)),
         }
     }
 }
 
+/// Creates the error for values that cannot be used as map keys.
+pub(crate) fn invalid_key(value: &Value) -> Error {
+    Error::new(
+        ErrorKind::InvalidOperation,
+        format!("cannot use {} as map key", value.kind()),
+    )
+}
+
 impl<'a> PartialEq for Key<'a> {
     #[inline(always)]
     fn eq(&self, other: &Self) -> bool {
@@ -278,6 +286,24 @@ pub mod key_interning {
         })
     }
 
+    #[test]
+    fn test_string_value_interning() {
+        let v = Value::from_serializable(&vec!["active", "active", "active"]);
+        let items = v.try_iter_owned().unwrap().collect::<Vec<_>>();
+        match (&items[0].0, &items[2].0) {
+            (ValueRepr::String(a, _), ValueRepr::String(b, _)) => assert!(Arc::ptr_eq(a, b)),
+            _ => unreachable!(),
+        }
+
+        // interned and non interned strings must compare the same
+        let interned = v.get_item(&Value::from(0)).unwrap();
+        let fresh = Value::from(String::from("active"));
+        assert_eq!(interned, fresh);
+        let mut m = std::collections::BTreeMap::new();
+        m.insert(Key::String(Arc::new("active".into())), Value::from(42));
+        assert_eq!(Value::from(m).get_item(&interned).unwrap(), Value::from(42));
+    }
+
     #[test]
     fn test_key_interning() {
         let mut m = std::collections::BTreeMap::new();
@@ -54,8 +54,10 @@ impl Serializer for KeySerializer {
         Ok(Key::I64(v))
     }
 
-    fn serialize_i128(self, _: i128) -> Result<StaticKey, SerializationFailed> {
-        Err(ser::Error::custom("unsupported key type i128"))
+    fn serialize_i128(self, v: i128) -> Result<StaticKey, SerializationFailed> {
+        i64::try_from(v)
+            .map(Key::I64)
+            .map_err(|_| ser::Error::custom("out of bounds for i64"))
     }
 
     fn serialize_u8(self, v: u8) -> Result<StaticKey, SerializationFailed> {
@@ -74,16 +76,25 @@ impl Serializer for KeySerializer {
         Key::try_from(v).map_err(|_| ser::Error::custom("out of bounds for i64"))
     }
 
-    fn serialize_u128(self, _: u128) -> Result<StaticKey, SerializationFailed> {
-        Err(ser::Error::custom("unsupported key type u128"))
+    fn serialize_u128(self, v: u128) -> Result<StaticKey, SerializationFailed> {
+        i64::try_from(v)
+            .map(Key::I64)
+            .map_err(|_| ser::Error::custom("out of bounds for i64"))
     }
 
-    fn serialize_f32(self, _: f32) -> Result<StaticKey, SerializationFailed> {
-        Err(ser::Error::custom("unsupported key type f32"))
+    fn serialize_f32(self, v: f32) -> Result<StaticKey, SerializationFailed> {
+        self.serialize_f64(v as f64)
     }
 
-    fn serialize_f64(self, _: f64) -> Result<StaticKey, SerializationFailed> {
-        Err(ser::Error::custom("unsupported key type f64"))
+    fn serialize_f64(self, v: f64) -> Result<StaticKey, SerializationFailed> {
+        // integral floats become integer keys, the same as when a float
+        // is used as key in a template.
+        let intval = v as i64;
+        if intval as f64 == v {
+            Ok(Key::I64(intval))
+        } else {
+            Err(ser::Error::custom("unsupported key type f64"))
+        }
     }
 
     fn serialize_char(self, v: char) -> Result<StaticKey, SerializationFailed> {
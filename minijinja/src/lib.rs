@@ -159,20 +159,32 @@
 //!
 //! - `fuel`: enables the `fuel` feature which makes the engine track fuel consumption which
 //!   can be used to better protect against expensive templates.
-//! - `source`: enables the `Source` type which helps with dynamic loading of templates.
+//! - `source`: enables the `Source` type which helps with dynamic loading of templates
+//!   as well as `Environment::add_template_owned` and the shared compilation cache
+//!   (`Environment::set_compiled_cache`).
 //! - `speedups`: enables all speedups, in particular it turns on the `v_htmlescape` dependency
 //!   for faster HTML escapling.
-//! - `json`: When enabled the `tojson` filter is added as builtin filter as well as
-//!   the ability to auto escape via `AutoEscape::Json`.
-//! - `urlencode`: When enabled the `urlencode` filter is added as builtin filter.
+//! - `json`: When enabled the `tojson` and `fromjson` filters are added as builtin
+//!   filters as well as the ability to auto escape via `AutoEscape::Json`.
+//! - `urlencode`: When enabled the `urlencode` and `urldecode` filters are added as
+//!   builtin filters.
+//! - `toml`: When enabled the `tomlencode` filter is added as builtin filter.
+//! - `number_format`: When enabled the `numberformat` and `currencyformat` filters
+//!   are added as builtin filters.
+//! - `base64`: When enabled the `b64encode` and `b64decode` filters are added as
+//!   builtin filters.
+//! - `regex`: When enabled the `regex_replace`, `regex_search` and `regex_findall`
+//!   filters as well as the `matching` test are added as builtins.
 //! - `preserve_order`: When enable the internal value implementation uses an indexmap
-//!   which preserves the original order of maps and structs.
+//!   which preserves the insertion order of maps and structs instead of sorting
+//!   keys.  See [the ordering guarantees](crate::value#map-ordering) for details.
 //! - `key_interning`: if this feature is enabled the automatic string interning in
 //!   the value type is enabled.  This feature used to be turned on by default but
 //!   has negative performance effects in newer versions of MiniJinja since a lot of
 //!   the previous uses of key interning are no longer needed.  Enabling it however
 //!   cuts down on memory usage slightly in certain scenarios by interning all string
-//!   keys used in dynamic map values.
+//!   keys used in dynamic map values as well as short string values created through
+//!   serialization.
 //!
 //! </details>
 #![allow(clippy::cognitive_complexity)]
@@ -201,6 +213,8 @@ pub mod syntax;
 pub mod tests;
 pub mod value;
 
+#[cfg(feature = "source")]
+mod cache;
 #[cfg(feature = "source")]
 mod source;
 
@@ -212,14 +226,16 @@ pub use self::environment::Environment;
 pub use self::error::{Error, ErrorKind};
 pub use self::expression::Expression;
 pub use self::output::Output;
-pub use self::template::Template;
-pub use self::utils::{AutoEscape, HtmlEscape};
+pub use self::template::{OwnedTemplate, Template};
+pub use self::utils::{AutoEscape, FloatFormat, HtmlEscape, NonFiniteFormat};
 
+#[cfg(feature = "source")]
+pub use self::cache::{CachedTemplate, CompiledCache, LruCompiledCache};
 #[cfg(feature = "source")]
 pub use self::source::Source;
 
 pub use self::macros::__context;
-pub use self::vm::State;
+pub use self::vm::{State, TraceEvent, TraceKind, TracePhase};
 
 /// This module gives access to the low level machinery.
 ///
@@ -232,7 +248,10 @@ pub mod machinery {
     #![allow(missing_docs)]
     pub use crate::compiler::ast;
     pub use crate::compiler::codegen::CodeGenerator;
-    pub use crate::compiler::instructions::{Instruction, Instructions};
+    pub use crate::compiler::instructions::{
+        Instruction, Instructions, LOOP_FLAG_RECURSIVE, LOOP_FLAG_UNPACK_PAIRS,
+        LOOP_FLAG_WITH_LOOP_VAR,
+    };
     pub use crate::compiler::lexer::tokenize;
     pub use crate::compiler::parser::parse;
     pub use crate::compiler::tokens::{Span, Token};
@@ -26,11 +26,27 @@ macro_rules! some {
 /// Hidden utility module for the [`context!`](crate::context!) macro.
 #[doc(hidden)]
 pub mod __context {
+    use crate::error::{Error, ErrorKind};
     use crate::key::Key;
-    use crate::value::{MapType, Value, ValueMap, ValueRepr};
+    use crate::value::{MapType, Object, Value, ValueMap, ValueRepr};
     use crate::Environment;
+    use std::fmt;
     use std::sync::Arc;
 
+    /// A context that could not be built because a spread failed.
+    ///
+    /// Rendering with such a context fails with the original error.
+    #[derive(Debug)]
+    pub struct InvalidContext(String);
+
+    impl fmt::Display for InvalidContext {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "<invalid context: {}>", self.0)
+        }
+    }
+
+    impl Object for InvalidContext {}
+
     #[inline(always)]
     pub fn value_optimization() -> impl Drop {
         crate::value::value_optimization()
@@ -46,11 +62,50 @@ pub mod __context {
         ctx.insert(Key::Str(key), value);
     }
 
+    /// Merges the spread values into the context.
+    ///
+    /// `none` and undefined values spread as empty maps.  On failure the
+    /// error message is returned and the remaining values are skipped.
+    pub fn add_spreads<const N: usize>(ctx: &mut ValueMap, values: [Value; N]) -> Option<String> {
+        for value in values {
+            if value.is_none() || value.is_undefined() {
+                continue;
+            }
+            let iter = match value.try_iter_pairs() {
+                Ok(iter) => iter,
+                Err(_) => return Some(format!("cannot spread {} into context", value.kind())),
+            };
+            for (key, value) in iter {
+                match key.clone().try_into_key() {
+                    Ok(key) => ctx.insert(key, value),
+                    Err(_) => return Some(format!("cannot spread key {key:?} into context")),
+                };
+            }
+        }
+        None
+    }
+
     #[inline(always)]
     pub fn build(ctx: ValueMap) -> Value {
         ValueRepr::Map(Arc::new(ctx), MapType::Normal).into()
     }
 
+    #[inline(always)]
+    pub fn build_spread(ctx: ValueMap, error: Option<String>) -> Value {
+        match error {
+            Some(error) => Value::from_object(InvalidContext(error)),
+            None => build(ctx),
+        }
+    }
+
+    /// Fails if the root value is a context that could not be built.
+    pub(crate) fn check_root(root: &Value) -> Result<(), Error> {
+        match root.downcast_object_ref::<InvalidContext>() {
+            Some(invalid) => Err(Error::new(ErrorKind::InvalidOperation, invalid.0.clone())),
+            None => Ok(()),
+        }
+    }
+
     pub fn thread_local_env() -> Environment<'static> {
         thread_local! {
             static ENV: Environment<'static> = Environment::new()
@@ -80,6 +135,22 @@ pub mod __context {
 ///
 /// The return value is a [`Value`](crate::value::Value).
 ///
+/// Existing maps can be merged into the context by spreading them with `..`
+/// before the other keys.  Anything that serializes into a map (such as a
+/// `BTreeMap`, a struct or another context) can be spread.  Keys that are set
+/// later override earlier ones:
+///
+/// ```rust
+/// # use minijinja::context;
+/// let base = context! { site => "Example", title => "Default" };
+/// let ctx = context! { ..base, title => "About" };
+/// ```
+///
+/// Spreading `none` or an undefined value adds no keys.  If a value that is
+/// not a map or a map with keys that cannot be used in a context is spread,
+/// rendering with the resulting context fails with an
+/// [`InvalidOperation`](crate::ErrorKind::InvalidOperation) error.
+///
 /// Note that [`context!`](crate::context!) can also be used recursively if you need to
 /// create nested objects:
 ///
@@ -107,6 +178,20 @@ macro_rules! context {
             $crate::__context_pair!(ctx, $key $(, $value)?);
         )*
         $crate::__context::build(ctx)
+    }};
+    (
+        $(..$base:expr),+ $(, $($key:ident $(=> $value:expr)?),*)? $(,)?
+    ) => {{
+        let _guard = $crate::__context::value_optimization();
+        let mut ctx = $crate::__context::make();
+        let error = $crate::__context::add_spreads(
+            &mut ctx,
+            [$($crate::value::Value::from_serializable(&$base)),+],
+        );
+        $($(
+            $crate::__context_pair!(ctx, $key $(, $value)?);
+        )*)?
+        $crate::__context::build_spread(ctx, error)
     }}
 }
 
@@ -195,6 +280,59 @@ fn test_context() {
     assert_eq!(ctx.get_attr("var2").unwrap(), Value::from(42));
 }
 
+#[test]
+fn test_context_spread() {
+    use crate::value::Value;
+    use std::collections::BTreeMap;
+
+    let base = context! { a => 1, b => 2 };
+    let ctx = context! { ..base.clone(), b => 3, c => 4 };
+    assert_eq!(ctx.get_attr("a").unwrap(), Value::from(1));
+    assert_eq!(ctx.get_attr("b").unwrap(), Value::from(3));
+    assert_eq!(ctx.get_attr("c").unwrap(), Value::from(4));
+
+    let mut extra = BTreeMap::new();
+    extra.insert("a", "x");
+    let ctx = context! { ..base, ..extra };
+    assert_eq!(ctx.get_attr("a").unwrap(), Value::from("x"));
+    assert_eq!(ctx.get_attr("b").unwrap(), Value::from(2));
+}
+
+#[test]
+fn test_context_spread_none() {
+    use crate::value::Value;
+
+    let ctx = context! { ..None::<()>, ..Value::UNDEFINED, a => 1 };
+    assert_eq!(ctx.get_attr("a").unwrap(), Value::from(1));
+}
+
+#[test]
+fn test_context_spread_non_map() {
+    use crate::value::Value;
+
+    let env = crate::Environment::new();
+    let err = env
+        .render_str("{{ a }}", context! { ..42, a => 1 })
+        .unwrap_err();
+    assert_eq!(err.kind(), crate::ErrorKind::InvalidOperation);
+    assert!(err
+        .to_string()
+        .contains("cannot spread number into context"));
+
+    let ctx = context! { ..Value::from(vec![1]) };
+    assert_eq!(
+        ctx.to_string(),
+        "<invalid context: cannot spread sequence into context>"
+    );
+    #[cfg(feature = "multi_template")]
+    {
+        let tmpl = env
+            .template_from_str("{% block x %}{% endblock %}")
+            .unwrap();
+        assert!(tmpl.as_template().render_block("x", ctx).is_err());
+    }
+}
+
 #[test]
 fn test_render() {
     let env = crate::Environment::new();
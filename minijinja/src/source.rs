@@ -9,6 +9,7 @@ use std::sync::Arc;
 use memo_map::MemoMap;
 use self_cell::self_cell;
 
+use crate::compiler::parser::ParserConfig;
 use crate::error::{Error, ErrorKind};
 use crate::template::CompiledTemplate;
 
@@ -16,6 +17,7 @@ use crate::template::CompiledTemplate;
 use similar_asserts::assert_eq;
 
 type LoadFunc = dyn for<'a> Fn(&'a str) -> Result<String, Error> + Send + Sync;
+type ListFunc = dyn Fn() -> Result<Vec<String>, Error> + Send + Sync;
 
 /// Utility for dynamic template loading.
 ///
@@ -31,10 +33,17 @@ type LoadFunc = dyn for<'a> Fn(&'a str) -> Result<String, Error> + Send + Sync;
 /// Alternatively sources can also be used to implement completely dynamic template
 /// lookups by using [`with_loader`](Source::with_loader) in which case templates
 /// are loaded on first use.
+///
+/// Compiled templates are reference counted within the source.  Cloning a
+/// source is thus cheap and the clone shares all templates that were already
+/// compiled at the time of cloning.  This makes it possible to compile a set
+/// of templates once and to hand clones of the source to many short lived
+/// environments without lexing, parsing or compiling the templates again.
 #[derive(Clone)]
 #[cfg_attr(docsrs, doc(cfg(feature = "source")))]
 pub struct Source {
     backing: SourceBacking,
+    parser_config: ParserConfig,
 }
 
 #[derive(Clone)]
@@ -42,6 +51,7 @@ enum SourceBacking {
     Dynamic {
         templates: MemoMap<String, Arc<LoadedTemplate>>,
         loader: Arc<LoadFunc>,
+        lister: Option<Arc<ListFunc>>,
     },
     Static {
         templates: HashMap<String, Arc<LoadedTemplate>>,
@@ -70,7 +80,7 @@ impl fmt::Debug for Source {
 }
 
 self_cell! {
-    struct LoadedTemplate {
+    pub(crate) struct LoadedTemplate {
         owner: (String, String),
         #[covariant]
         dependent: CompiledTemplate,
@@ -83,6 +93,21 @@ impl fmt::Debug for LoadedTemplate {
     }
 }
 
+/// Compiles a template that owns its name and source.
+pub(crate) fn load_template(
+    name: String,
+    source: String,
+    parser_config: ParserConfig,
+) -> Result<Arc<LoadedTemplate>, Error> {
+    let tmpl = ok!(LoadedTemplate::try_new(
+        (name, source),
+        |(name, source)| -> Result<_, Error> {
+            CompiledTemplate::new(name.as_str(), source, parser_config)
+        }
+    ));
+    Ok(Arc::new(tmpl))
+}
+
 impl Source {
     /// Creates an empty source.
     ///
@@ -101,6 +126,7 @@ impl Source {
             backing: SourceBacking::Static {
                 templates: HashMap::new(),
             },
+            parser_config: ParserConfig::default(),
         }
     }
 
@@ -138,8 +164,28 @@ impl Source {
                     Some(rv) => Ok(rv),
                     None => Err(Error::new_not_found(name)),
                 }),
+                lister: None,
             },
+            parser_config: ParserConfig::default(),
+        }
+    }
+
+    /// Creates a source with a dynamic loader and a function to list templates.
+    ///
+    /// This works like [`with_loader`](Source::with_loader) but additionally
+    /// accepts a function that returns the names of all templates the loader
+    /// can load.  This is used by [`compile_all`](Source::compile_all) to
+    /// discover templates.
+    pub fn with_loader_and_lister<F, L>(f: F, list: L) -> Source
+    where
+        F: Fn(&str) -> Result<Option<String>, Error> + Send + Sync + 'static,
+        L: Fn() -> Result<Vec<String>, Error> + Send + Sync + 'static,
+    {
+        let mut rv = Source::with_loader(f);
+        if let SourceBacking::Dynamic { ref mut lister, .. } = rv.backing {
+            *lister = Some(Arc::new(list));
         }
+        rv
     }
 
     /// Creates a source that loads on demand from a given directory.
@@ -160,20 +206,32 @@ impl Source {
     /// ```
     pub fn from_path<P: AsRef<Path>>(dir: P) -> Source {
         let dir = dir.as_ref().to_path_buf();
-        Source::with_loader(move |name| {
-            let path = match safe_join(&dir, name) {
-                Some(path) => path,
-                None => return Ok(None),
-            };
-            match fs::read_to_string(path) {
-                Ok(result) => Ok(Some(result)),
-                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
-                Err(err) => Err(
-                    Error::new(ErrorKind::InvalidOperation, "could not read template")
-                        .with_source(err),
-                ),
-            }
-        })
+        let list_dir = dir.clone();
+        Source::with_loader_and_lister(
+            move |name| {
+                let path = match safe_join(&dir, name) {
+                    Some(path) => path,
+                    None => return Ok(None),
+                };
+                match fs::read_to_string(path) {
+                    Ok(result) => Ok(Some(result)),
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                    Err(err) => Err(Error::new(
+                        ErrorKind::InvalidOperation,
+                        "could not read template",
+                    )
+                    .with_source(err)),
+                }
+            },
+            move || {
+                let mut rv = Vec::new();
+                ok!(list_templates(&list_dir, "", &mut rv).map_err(|err| {
+                    Error::new(ErrorKind::InvalidOperation, "could not list templates")
+                        .with_source(err)
+                }));
+                Ok(rv)
+            },
+        )
     }
 
     /// Adds a new template into the source.
@@ -186,24 +244,21 @@ impl Source {
         name: N,
         source: S,
     ) -> Result<(), Error> {
-        let source = source.into();
         let name = name.into();
-        let owner = (name.clone(), source);
-        let tmpl = ok!(LoadedTemplate::try_new(
-            owner,
-            |(name, source)| -> Result<_, Error> {
-                CompiledTemplate::from_name_and_source(name.as_str(), source)
-            }
+        let tmpl = ok!(load_template(
+            name.clone(),
+            source.into(),
+            self.parser_config
         ));
 
         match self.backing {
             SourceBacking::Dynamic {
                 ref mut templates, ..
             } => {
-                templates.replace(name, Arc::new(tmpl));
+                templates.replace(name, tmpl);
             }
             SourceBacking::Static { ref mut templates } => {
-                templates.insert(name, Arc::new(tmpl));
+                templates.insert(name, tmpl);
             }
         }
         Ok(())
@@ -217,24 +272,82 @@ impl Source {
         };
     }
 
+    /// Removes all loaded templates from the source.
+    ///
+    /// Sources with a loader will load templates again on next use.
+    pub fn clear_templates(&mut self) {
+        match &mut self.backing {
+            SourceBacking::Dynamic { templates, .. } => templates.clear(),
+            SourceBacking::Static { templates } => templates.clear(),
+        }
+    }
+
+    /// Returns the names of all currently loaded templates.
+    ///
+    /// For sources with a loader this only includes the templates which
+    /// were already loaded.  The order of the names is unspecified.
+    pub fn template_names(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        match &self.backing {
+            SourceBacking::Dynamic { templates, .. } => {
+                Box::new(templates.keys().map(|x| x.as_str()))
+            }
+            SourceBacking::Static { templates } => Box::new(templates.keys().map(|x| x.as_str())),
+        }
+    }
+
+    /// Compiles all templates known to the source.
+    ///
+    /// For sources with a loader the templates are discovered with the lister
+    /// passed to [`with_loader_and_lister`](Source::with_loader_and_lister).
+    /// Sources with a loader but without a lister only check templates that
+    /// were already loaded.  All errors are collected and returned together
+    /// with the name of the template that caused them.  If listing the
+    /// templates fails, the error is reported with an empty name.
+    pub fn compile_all(&self) -> Result<(), Vec<(String, Error)>> {
+        match &self.backing {
+            SourceBacking::Dynamic {
+                lister: Some(lister),
+                ..
+            } => {
+                let names = match lister() {
+                    Ok(names) => names,
+                    Err(err) => return Err(vec![(String::new(), err)]),
+                };
+                let errors = names
+                    .into_iter()
+                    .filter_map(|name| match self.get_compiled_template(&name) {
+                        Ok(_) => None,
+                        Err(err) => Some((name, err)),
+                    })
+                    .collect::<Vec<_>>();
+                if errors.is_empty() {
+                    Ok(())
+                } else {
+                    Err(errors)
+                }
+            }
+            // templates that were added explicitly are already compiled
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets the parser config for templates compiled afterwards.
+    pub(crate) fn set_parser_config(&mut self, config: ParserConfig) {
+        self.parser_config = config;
+    }
+
     /// Gets a compiled template from the source.
     pub(crate) fn get_compiled_template(&self, name: &str) -> Result<&CompiledTemplate<'_>, Error> {
         match &self.backing {
-            SourceBacking::Dynamic { templates, loader } => Ok(ok!(templates.get_or_try_insert(
-                name,
-                || -> Result<_, Error> {
+            SourceBacking::Dynamic {
+                templates, loader, ..
+            } => Ok(
+                ok!(templates.get_or_try_insert(name, || -> Result<_, Error> {
                     let source = ok!(loader(name));
-                    let owner = (name.to_owned(), source);
-                    let tmpl = ok!(LoadedTemplate::try_new(
-                        owner,
-                        |(name, source)| -> Result<_, Error> {
-                            CompiledTemplate::from_name_and_source(name.as_str(), source)
-                        }
-                    ));
-                    Ok(Arc::new(tmpl))
-                }
-            ))
-            .borrow_dependent()),
+                    load_template(name.to_owned(), source, self.parser_config)
+                }))
+                .borrow_dependent(),
+            ),
             SourceBacking::Static { templates } => templates
                 .get(name)
                 .map(|value| value.borrow_dependent())
@@ -243,6 +356,28 @@ impl Source {
     }
 }
 
+fn list_templates(dir: &Path, prefix: &str, rv: &mut Vec<String>) -> io::Result<()> {
+    for entry in ok!(fs::read_dir(dir)) {
+        let entry = ok!(entry);
+        let filename = entry.file_name();
+        let filename = match filename.to_str() {
+            Some(filename) if !filename.starts_with('.') => filename,
+            _ => continue,
+        };
+        let name = if prefix.is_empty() {
+            filename.to_string()
+        } else {
+            format!("{prefix}/{filename}")
+        };
+        if ok!(entry.file_type()).is_dir() {
+            ok!(list_templates(&entry.path(), &name, rv));
+        } else {
+            rv.push(name);
+        }
+    }
+    Ok(())
+}
+
 fn safe_join(base: &Path, template: &str) -> Option<PathBuf> {
     let mut rv = base.to_path_buf();
     for segment in template.split('/') {
@@ -276,6 +411,43 @@ fn test_source_replace_dynamic() {
     assert_eq!(rv, "2");
 }
 
+#[test]
+fn test_source_clear_templates() {
+    let mut source = Source::new();
+    source.add_template("a", "1").unwrap();
+    source.add_template("b", "2").unwrap();
+    let mut names = source.template_names().collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["a", "b"]);
+    source.clear_templates();
+    assert_eq!(source.template_names().count(), 0);
+    assert!(source.get_compiled_template("a").is_err());
+}
+
+#[test]
+fn test_source_compile_all() {
+    let source = Source::with_loader_and_lister(
+        |name| match name {
+            "good" => Ok(Some("{{ 42 }}".into())),
+            "bad" => Ok(Some("{% if %}".into())),
+            _ => Ok(None),
+        },
+        || Ok(vec!["good".into(), "bad".into(), "missing".into()]),
+    );
+    let errors = source.compile_all().unwrap_err();
+    assert_eq!(
+        errors
+            .iter()
+            .map(|(name, err)| (name.as_str(), err.kind()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("bad", ErrorKind::SyntaxError),
+            ("missing", ErrorKind::TemplateNotFound)
+        ]
+    );
+    assert!(source.get_compiled_template("good").is_ok());
+}
+
 #[test]
 fn test_safe_join() {
     assert_eq!(
@@ -286,3 +458,14 @@ fn test_safe_join() {
     assert_eq!(safe_join(Path::new("foo"), "bar/.baz"), None);
     assert_eq!(safe_join(Path::new("foo"), "bar/../baz"), None);
 }
+
+#[test]
+fn test_source_clone_shares_compiled() {
+    let mut source = Source::new();
+    source.add_template("a", "{{ 1 + 1 }}").unwrap();
+    let cloned = source.clone();
+    assert!(std::ptr::eq(
+        source.get_compiled_template("a").unwrap(),
+        cloned.get_compiled_template("a").unwrap()
+    ));
+}
@@ -131,7 +131,12 @@
 //!
 //! - ``is``/``is not``: Performs a [test](crate::tests).
 //! - ``in``/``not in``: Performs a containment check.
-//! - ``|`` (pipe, vertical bar): Applies a [filter](crate::filters).
+//! - ``|`` (pipe, vertical bar): Applies a [filter](crate::filters).  Filters can
+//!   also be called like functions with the value as first argument, so
+//!   ``{{ length(seq) }}`` is the same as ``{{ seq|length }}``.  Likewise a
+//!   function or macro can be applied as filter if no filter with that name
+//!   exists.
+//!   If a function and a filter share a name, the function is used for calls.
 //! - ``~`` (tilde): Converts all operands into strings and concatenates them.
 //!   ``{{ "Hello " ~ name ~ "!" }}`` would return (assuming `name` is set
 //!   to ``'John'``) ``Hello John!``.
@@ -193,6 +198,16 @@
 //! </ul>
 //! ```
 //!
+//! Iterating over a map yields its keys.  If the loop unpacks into exactly two
+//! targets the map's key/value pairs are iterated instead, so the `items` filter
+//! is not needed (unlike in Jinja2):
+//!
+//! ```jinja
+//! {% for key, value in config %}
+//!   {{ key }} = {{ value }}
+//! {% endfor %}
+//! ```
+//!
 //! Inside of the for block you can access some special variables:
 //!
 //! - `loop.index`: The current iteration of the loop. (1 indexed)
@@ -420,6 +435,20 @@
 //! ```
 //!  
 //! Included templates have access to the variables of the active context.
+//! This includes variables set with `{% set %}`, `{% with %}` blocks and the
+//! state of enclosing loops, so a partial rendered per iteration can refer to
+//! `loop.index` (or the loop target) directly.  This is what `with context`
+//! spells out explicitly.  With `without context` the included template is
+//! rendered in an empty context and only sees the globals of the environment:
+//!
+//! ```jinja
+//! {% for user in users %}
+//!   {% include 'row.html' %}
+//! {% endfor %}
+//! {% include 'footer.html' without context %}
+//! ```
+//!
+//! The context modifier goes after `ignore missing` if both are used.
 //!
 //! ## `{% import %}`
 //!
@@ -479,6 +508,15 @@
 //! {% set navigation = [('index.html', 'Index'), ('about.html', 'About')] %}
 //! ```
 //!
+//! Multiple variables can be assigned at once by unpacking a sequence.  The
+//! sequence has to have exactly as many items as there are targets, otherwise
+//! an error is raised.  Targets can be nested with parentheses:
+//!
+//! ```jinja
+//! {% set key, value = pair %}
+//! {% set first, (second, third) = [1, [2, 3]] %}
+//! ```
+//!
 //! Please keep in mind that it is not possible to set variables inside a block
 //! and have them show up outside of it.  This also applies to loops.  The only
 //! exception to that rule are if statements which do not introduce a scope.
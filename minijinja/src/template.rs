@@ -1,11 +1,13 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
 use std::{fmt, io};
 
 use serde::Serialize;
 
 use crate::compiler::codegen::CodeGenerator;
 use crate::compiler::instructions::Instructions;
-use crate::compiler::parser::parse;
+use crate::compiler::meta::find_undeclared;
+use crate::compiler::parser::{parse_template, ParserConfig};
 use crate::environment::Environment;
 use crate::error::{attach_basic_debug_info, Error, ErrorKind};
 use crate::output::{Output, WriteWrapper};
@@ -27,6 +29,80 @@ pub struct Template<'env> {
     initial_auto_escape: AutoEscape,
 }
 
+/// A template that is not stored in the environment.
+///
+/// This is returned by [`Environment::template_from_named_str`].  The compiled
+/// template is owned by this value while the source stays borrowed.  Cloning
+/// it is cheap as the compiled template is reference counted.  To use the full
+/// API of a [`Template`] a handle can be borrowed with
+/// [`as_template`](Self::as_template).
+#[derive(Clone)]
+pub struct OwnedTemplate<'env> {
+    env: &'env Environment<'env>,
+    compiled: CompiledTemplateRef<'env>,
+    initial_auto_escape: AutoEscape,
+}
+
+/// The compiled template held by an [`OwnedTemplate`].
+#[derive(Clone)]
+pub(crate) enum CompiledTemplateRef<'env> {
+    Owned(Arc<CompiledTemplate<'env>>),
+    #[cfg(feature = "source")]
+    Cached(crate::cache::CachedTemplate),
+}
+
+impl<'env> CompiledTemplateRef<'env> {
+    fn get(&self) -> &CompiledTemplate<'_> {
+        match self {
+            CompiledTemplateRef::Owned(compiled) => compiled,
+            #[cfg(feature = "source")]
+            CompiledTemplateRef::Cached(cached) => cached.compiled(),
+        }
+    }
+}
+
+impl<'env> fmt::Debug for OwnedTemplate<'env> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.as_template(), f)
+    }
+}
+
+impl<'env> OwnedTemplate<'env> {
+    pub(crate) fn new(
+        env: &'env Environment<'env>,
+        compiled: CompiledTemplateRef<'env>,
+        initial_auto_escape: AutoEscape,
+    ) -> OwnedTemplate<'env> {
+        OwnedTemplate {
+            env,
+            compiled,
+            initial_auto_escape,
+        }
+    }
+
+    /// Returns a [`Template`] handle that borrows from this template.
+    pub fn as_template(&self) -> Template<'_> {
+        Template::new(self.env, self.compiled.get(), self.initial_auto_escape)
+    }
+
+    /// Returns the name of the template.
+    pub fn name(&self) -> &str {
+        self.compiled.get().instructions.name()
+    }
+
+    /// Returns the source code of the template.
+    pub fn source(&self) -> &str {
+        self.compiled.get().instructions.source()
+    }
+
+    /// Renders the template into a string.
+    ///
+    /// See [`Template::render`].
+    pub fn render<S: Serialize>(&self, ctx: S) -> Result<String, Error> {
+        self.as_template().render(ctx)
+    }
+}
+
 impl<'env> fmt::Debug for Template<'env> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut ds = f.debug_struct("Template");
@@ -87,10 +163,67 @@ impl<'env> Template<'env> {
         self._render(Value::from_serializable(&ctx))
     }
 
-    fn _render(&self, root: Value) -> Result<String, Error> {
+    pub(crate) fn _render(&self, root: Value) -> Result<String, Error> {
         let mut rv = String::with_capacity(self.compiled.buffer_size_hint);
-        self._eval(root, &mut Output::with_string(&mut rv))
-            .map(|_| rv)
+        self._render_into(root, &mut rv).map(|_| rv)
+    }
+
+    /// Renders the template and falls back to another template on error.
+    ///
+    /// If rendering fails the `fallback` template is rendered with
+    /// `fallback_ctx` instead.  On success the rendered output is returned
+    /// together with the error of the failed render (if any) so that it can
+    /// still be logged.  Output produced before the error is discarded.  If the
+    /// fallback template fails as well, its error is returned.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// env.add_template("page", "{{ items|length }} items").unwrap();
+    /// env.add_template("error", "Something went wrong").unwrap();
+    /// let tmpl = env.get_template("page").unwrap();
+    /// let fallback = env.get_template("error").unwrap();
+    /// let (rv, err) = tmpl.render_or(context!(items => 42), &fallback, ()).unwrap();
+    /// assert_eq!(rv, "Something went wrong");
+    /// assert!(err.is_some());
+    /// ```
+    pub fn render_or<S: Serialize, F: Serialize>(
+        &self,
+        ctx: S,
+        fallback: &Template<'_>,
+        fallback_ctx: F,
+    ) -> Result<(String, Option<Error>), Error> {
+        match self.render(ctx) {
+            Ok(rv) => Ok((rv, None)),
+            Err(err) => fallback.render(fallback_ctx).map(|rv| (rv, Some(err))),
+        }
+    }
+
+    /// Renders the template and appends the output to a string buffer.
+    ///
+    /// This works like [`render`](Self::render) but instead of allocating a
+    /// new string the rendered template is appended to `buf`.  This makes it
+    /// possible to reuse a buffer across many renders by clearing it in
+    /// between.  If rendering fails the buffer might contain partial output.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// # env.add_template("hello", "Hello {{ name }}!").unwrap();
+    /// let tmpl = env.get_template("hello").unwrap();
+    /// let mut buf = String::new();
+    /// for name in ["John", "Jane"] {
+    ///     buf.clear();
+    ///     tmpl.render_into(context!(name), &mut buf).unwrap();
+    ///     println!("{}", buf);
+    /// }
+    /// ```
+    pub fn render_into<S: Serialize>(&self, ctx: S, buf: &mut String) -> Result<(), Error> {
+        self._render_into(Value::from_serializable(&ctx), buf)
+    }
+
+    fn _render_into(&self, root: Value, buf: &mut String) -> Result<(), Error> {
+        self._eval(root, &mut Output::with_string(buf)).map(|_| ())
     }
 
     /// Renders the template into a [`io::Write`].
@@ -129,6 +262,127 @@ impl<'env> Template<'env> {
         })
     }
 
+    /// Returns a set of all undeclared variables in the template.
+    ///
+    /// This returns a set of all variables that might be looked up from the
+    /// context at runtime.  Variables that are assigned within the template
+    /// such as loop variables, macro arguments or variables created with
+    /// `{% set %}` are not included.  Note that this also reports globals of
+    /// the environment (such as `range`) if the template uses them.
+    ///
+    /// If `nested` is set to `true` attribute lookups on undeclared variables
+    /// are reported as dotted paths.  For instance `{{ user.name }}` reports
+    /// `user` normally and `user.name` with `nested` enabled.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// env.add_template("x", "{% set x = foo %}{{ x }}{{ bar.baz }}").unwrap();
+    /// let tmpl = env.get_template("x").unwrap();
+    /// let undeclared = tmpl.undeclared_variables(false);
+    /// // returns ["foo", "bar"]
+    /// # assert_eq!(undeclared, ["foo", "bar"].into_iter().map(|x| x.to_string()).collect());
+    /// ```
+    pub fn undeclared_variables(&self, nested: bool) -> HashSet<String> {
+        match parse_template(self.source(), self.name(), self.env.parser_config()) {
+            Ok(ast) => find_undeclared(&ast, nested),
+            // the template was already compiled, so it parses
+            Err(_) => HashSet::new(),
+        }
+    }
+
+    /// Returns the names of all blocks defined by this template.
+    ///
+    /// This includes the blocks of all templates the template extends from.
+    /// Parent templates can only be discovered if they are referenced by a
+    /// constant string in an `{% extends %}` tag that is not nested in another
+    /// tag (such as `{% if %}`), and are loaded from the environment.
+    /// The names are returned in sorted order.
+    ///
+    /// ```
+    /// # use minijinja::Environment;
+    /// # let mut env = Environment::new();
+    /// env.add_template("layout", "{% block title %}{% endblock %}{% block body %}{% endblock %}").unwrap();
+    /// env.add_template("x", "{% extends 'layout' %}{% block sidebar %}{% endblock %}").unwrap();
+    /// let tmpl = env.get_template("x").unwrap();
+    /// assert_eq!(tmpl.blocks().unwrap(), vec!["body", "sidebar", "title"]);
+    /// ```
+    #[cfg(feature = "multi_template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi_template")))]
+    pub fn blocks(&self) -> Result<Vec<String>, Error> {
+        let mut rv = std::collections::BTreeSet::new();
+        for tmpl in ok!(self.inheritance_chain()) {
+            rv.extend(tmpl.compiled.blocks.keys().map(|x| x.to_string()));
+        }
+        Ok(rv.into_iter().collect())
+    }
+
+    /// Renders a single block of the template.
+    ///
+    /// The block is looked up in the template and the templates it extends
+    /// from, so blocks that are only defined in a parent template can be
+    /// rendered too.  Only the block itself is evaluated, code outside of the
+    /// block (such as top-level `{% set %}` tags) is not executed.  If the
+    /// block does not exist an [`InvalidOperation`](ErrorKind::InvalidOperation)
+    /// error is returned.
+    ///
+    /// ```
+    /// # use minijinja::{Environment, context};
+    /// # let mut env = Environment::new();
+    /// env.add_template("x", "<title>{% block title %}Hi {{ name }}{% endblock %}</title>").unwrap();
+    /// let tmpl = env.get_template("x").unwrap();
+    /// let rv = tmpl.render_block("title", context!(name => "John")).unwrap();
+    /// assert_eq!(rv, "Hi John");
+    /// ```
+    #[cfg(feature = "multi_template")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi_template")))]
+    pub fn render_block<S: Serialize>(&self, block_name: &str, ctx: S) -> Result<String, Error> {
+        self._render_block(block_name, Value::from_serializable(&ctx))
+    }
+
+    #[cfg(feature = "multi_template")]
+    fn _render_block(&self, block_name: &str, root: Value) -> Result<String, Error> {
+        let chain = ok!(self.inheritance_chain());
+        let blocks = chain.iter().map(|x| x.blocks_map()).collect::<Vec<_>>();
+        let mut rv = String::new();
+        Vm::new(self.env)
+            .eval_block(
+                block_name,
+                &self.compiled.instructions,
+                root,
+                &blocks,
+                &mut Output::with_string(&mut rv),
+                self.initial_auto_escape,
+            )
+            .map(|_| rv)
+    }
+
+    /// Returns this template followed by all templates it extends from.
+    ///
+    /// Only templates extended by a constant name can be resolved.
+    #[cfg(feature = "multi_template")]
+    fn inheritance_chain(&self) -> Result<Vec<Template<'env>>, Error> {
+        let mut rv = Vec::new();
+        let mut seen = std::collections::BTreeSet::new();
+        let mut tmpl = *self;
+        loop {
+            if !seen.insert(tmpl.compiled.instructions.name()) {
+                break;
+            }
+            let parent = tmpl
+                .compiled
+                .static_extends
+                .as_deref()
+                .map(|name| self.env.join_template_path(name, tmpl.name()).into_owned());
+            rv.push(tmpl);
+            match parent {
+                Some(name) => tmpl = ok!(self.env.get_template(&name)),
+                None => break,
+            }
+        }
+        Ok(rv)
+    }
+
     fn _eval(&self, root: Value, out: &mut Output) -> Result<Option<Value>, Error> {
         Vm::new(self.env).eval(
             &self.compiled.instructions,
@@ -139,23 +393,11 @@ impl<'env> Template<'env> {
         )
     }
 
-    /// Returns the root instructions.
-    #[cfg(feature = "multi_template")]
-    pub(crate) fn instructions(&self) -> &'env Instructions<'env> {
-        &self.compiled.instructions
-    }
-
     /// Returns the blocks.
     #[cfg(feature = "multi_template")]
-    pub(crate) fn blocks(&self) -> &'env BTreeMap<&'env str, Instructions<'env>> {
+    fn blocks_map(&self) -> &BTreeMap<&'env str, Instructions<'env>> {
         &self.compiled.blocks
     }
-
-    /// Returns the initial auto escape setting.
-    #[cfg(feature = "multi_template")]
-    pub(crate) fn initial_auto_escape(&self) -> AutoEscape {
-        self.initial_auto_escape
-    }
 }
 
 /// Represents a compiled template in memory.
@@ -166,6 +408,9 @@ pub struct CompiledTemplate<'source> {
     pub blocks: BTreeMap<&'source str, Instructions<'source>>,
     /// Optional size hint for string rendering.
     pub buffer_size_hint: usize,
+    /// The name of the template that is always extended, if known.
+    #[cfg(feature = "multi_template")]
+    pub static_extends: Option<String>,
 }
 
 impl<'env> fmt::Debug for CompiledTemplate<'env> {
@@ -182,29 +427,44 @@ impl<'env> fmt::Debug for CompiledTemplate<'env> {
 
 impl<'source> CompiledTemplate<'source> {
     /// Creates a compiled template from name and source.
+    #[cfg(feature = "unstable_machinery")]
     pub fn from_name_and_source(
         name: &'source str,
         source: &'source str,
     ) -> Result<CompiledTemplate<'source>, Error> {
-        attach_basic_debug_info(Self::_from_name_and_source_impl(name, source), source)
+        CompiledTemplate::new(name, source, ParserConfig::default())
+    }
+
+    /// Creates a compiled template with the given parser config.
+    pub(crate) fn new(
+        name: &'source str,
+        source: &'source str,
+        config: ParserConfig,
+    ) -> Result<CompiledTemplate<'source>, Error> {
+        attach_basic_debug_info(Self::_new_impl(name, source, config), source)
     }
 
-    fn _from_name_and_source_impl(
+    fn _new_impl(
         name: &'source str,
         source: &'source str,
+        config: ParserConfig,
     ) -> Result<CompiledTemplate<'source>, Error> {
         // the parser/compiler combination can create constants in which case
         // we can probably benefit from the value optimization a bit.
         let _guard = value::value_optimization();
-        let ast = ok!(parse(source, name));
+        let ast = ok!(parse_template(source, name, config));
         let mut gen = CodeGenerator::new(name, source);
         gen.compile_stmt(&ast);
         let buffer_size_hint = gen.buffer_size_hint();
+        #[cfg(feature = "multi_template")]
+        let static_extends = gen.static_extends().map(|x| x.to_string());
         let (instructions, blocks) = gen.finish();
         Ok(CompiledTemplate {
             instructions,
             blocks,
             buffer_size_hint,
+            #[cfg(feature = "multi_template")]
+            static_extends,
         })
     }
 }
@@ -264,6 +264,22 @@ mod builtins {
         i128::try_from(v).ok().map_or(false, |x| x % 2 == 0)
     }
 
+    /// Checks if a value is divisible by another number.
+    ///
+    /// Values that are not integers are never divisible.
+    ///
+    /// ```jinja
+    /// {{ 42 is divisibleby(3) }} -> true
+    /// {% if loop.index is divisibleby 3 %}...{% endif %}
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_divisibleby(v: Value, other: Value) -> bool {
+        match (i128::try_from(v), i128::try_from(other)) {
+            (Ok(a), Ok(b)) if b != 0 => a % b == 0,
+            _ => false,
+        }
+    }
+
     /// Checks if this value is a number.
     ///
     /// ```jinja
@@ -308,26 +324,60 @@ mod builtins {
         matches!(v.kind(), ValueKind::Map)
     }
 
+    /// Checks if this value can be iterated over.
+    ///
+    /// This is true for sequences, mappings and strings.
+    ///
+    /// ```jinja
+    /// {{ [1, 2, 3] is iterable }} -> true
+    /// {{ "foo" is iterable }} -> true
+    /// {{ 42 is iterable }} -> false
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
+    pub fn is_iterable(v: Value) -> bool {
+        v.is_iterable()
+    }
+
     /// Checks if the value is starting with a string.
     ///
+    /// Values that are not strings never start with a string.
+    ///
     /// ```jinja
     /// {{ "foobar" is startingwith "foo" }} -> true
     /// {{ "foobar" is startingwith "bar" }} -> false
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn is_startingwith(v: Cow<'_, str>, other: Cow<'_, str>) -> bool {
-        v.starts_with(&other as &str)
+    pub fn is_startingwith(v: Value, other: Cow<'_, str>) -> bool {
+        v.as_str().map_or(false, |s| s.starts_with(&other as &str))
     }
 
     /// Checks if the value is ending with a string.
     ///
+    /// Values that are not strings never end with a string.
+    ///
     /// ```jinja
     /// {{ "foobar" is endingwith "bar" }} -> true
     /// {{ "foobar" is endingwith "foo" }} -> false
     /// ```
     #[cfg_attr(docsrs, doc(cfg(feature = "builtins")))]
-    pub fn is_endingwith(v: Cow<'_, str>, other: Cow<'_, str>) -> bool {
-        v.ends_with(&other as &str)
+    pub fn is_endingwith(v: Value, other: Cow<'_, str>) -> bool {
+        v.as_str().map_or(false, |s| s.ends_with(&other as &str))
+    }
+
+    /// Checks if the value matches a regular expression.
+    ///
+    /// The pattern may match anywhere in the string, use `^` and `$` to
+    /// anchor it.  Values that are not strings never match.  This test is
+    /// only available if the `regex` feature is enabled.
+    ///
+    /// ```jinja
+    /// {{ "v1.2.3" is matching "^v\\d+" }} -> true
+    /// ```
+    #[cfg_attr(docsrs, doc(cfg(all(feature = "builtins", feature = "regex"))))]
+    #[cfg(feature = "regex")]
+    pub fn is_matching(v: Value, pattern: Cow<'_, str>) -> Result<bool, Error> {
+        let re = ok!(crate::utils::compile_regex(&pattern));
+        Ok(v.as_str().map_or(false, |s| re.is_match(s)))
     }
 
     /// Test version of `==`.
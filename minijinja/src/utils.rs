@@ -3,6 +3,7 @@ use std::collections::BTreeMap;
 use std::fmt;
 use std::iter::{once, repeat};
 use std::str::Chars;
+use std::sync::Arc;
 
 use crate::error::{Error, ErrorKind};
 use crate::value::{StringType, Value, ValueKind, ValueRepr};
@@ -58,7 +59,21 @@ fn write_with_html_escaping(out: &mut Output, value: &Value) -> fmt::Result {
     } else if let Some(s) = value.as_str() {
         write!(out, "{}", HtmlEscape(s))
     } else {
-        write!(out, "{}", HtmlEscape(&value.to_string()))
+        fmt::Write::write_fmt(&mut HtmlEscapeWriter(out), format_args!("{value}"))
+    }
+}
+
+/// Writer that HTML escapes everything written to it.
+///
+/// This lets values be formatted straight into the output without having
+/// to stringify them first.  As escaping works on individual characters it
+/// does not matter how the formatted value is split into chunks.
+struct HtmlEscapeWriter<'a, 'b>(&'a mut Output<'b>);
+
+impl fmt::Write for HtmlEscapeWriter<'_, '_> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write!(self.0, "{}", HtmlEscape(s))
     }
 }
 
@@ -69,11 +84,286 @@ fn invalid_autoescape(name: &str) -> Result<(), Error> {
     ))
 }
 
+/// Escapes JSON so that it can be safely embedded into HTML.
+///
+/// This replaces the characters that could terminate a `<script>` tag or
+/// start an HTML entity as well as the JavaScript line separators with their
+/// unicode escapes.  The result is still valid JSON.  If `escape_quote` is
+/// set the single quote is escaped as well which makes the output safe for
+/// single quoted HTML attributes.
+#[cfg(feature = "json")]
+pub fn escape_json_for_html(s: &str, escape_quote: bool) -> String {
+    let mut rv = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '<' => rv.push_str("\\u003c"),
+            '>' => rv.push_str("\\u003e"),
+            '&' => rv.push_str("\\u0026"),
+            '/' => rv.push_str("\\u002f"),
+            '\'' if escape_quote => rv.push_str("\\u0027"),
+            '\u{2028}' => rv.push_str("\\u2028"),
+            '\u{2029}' => rv.push_str("\\u2029"),
+            _ => rv.push(c),
+        }
+    }
+    rv
+}
+
+/// Serializes a value for JSON output.
+///
+/// This applies the [`JsonOptions`]: the keys of maps are optionally emitted in
+/// sorted order and floats are normalized (see [`to_json_string`]).
+#[cfg(feature = "json")]
+struct JsonValue<'a>(&'a Value, JsonOptions);
+
+#[cfg(feature = "json")]
+impl<'a> serde::Serialize for JsonValue<'a> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error as _;
+        match self.0 .0 {
+            ValueRepr::F64(val) if !val.is_finite() => {
+                if self.1.strict_floats {
+                    Err(S::Error::custom("cannot serialize non-finite float"))
+                } else {
+                    serializer.serialize_unit()
+                }
+            }
+            // ECMAScript's JSON.stringify also emits -0 as 0
+            ValueRepr::F64(0.0) => serializer.serialize_f64(0.0),
+            ValueRepr::Dynamic(ref obj) => match obj.to_serializable_value() {
+                Some(value) => JsonValue(&value, self.1).serialize(serializer),
+                None if matches!(obj.kind(), crate::value::ObjectKind::Plain) => {
+                    Err(S::Error::custom(format!(
+                        "object {:?} cannot be serialized",
+                        obj
+                    )))
+                }
+                None => self.serialize_kind(serializer),
+            },
+            _ => self.serialize_kind(serializer),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl<'a> JsonValue<'a> {
+    /// Serializes maps and sequences item by item, everything else directly.
+    fn serialize_kind<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeMap, SerializeSeq};
+        use serde::Serialize;
+        match self.0.kind() {
+            ValueKind::Map => {
+                let mut items = match self.0.try_iter_pairs() {
+                    Ok(iter) => iter.collect::<Vec<_>>(),
+                    Err(_) => return self.0.serialize(serializer),
+                };
+                if self.1.sort_keys {
+                    items.sort_by(|a, b| {
+                        match (
+                            crate::key::Key::from_borrowed_value(&a.0),
+                            crate::key::Key::from_borrowed_value(&b.0),
+                        ) {
+                            (Ok(a), Ok(b)) => a.cmp(&b),
+                            _ => std::cmp::Ordering::Equal,
+                        }
+                    });
+                }
+                let mut map = ok!(serializer.serialize_map(Some(items.len())));
+                for (key, value) in &items {
+                    ok!(map.serialize_entry(key, &JsonValue(value, self.1)));
+                }
+                map.end()
+            }
+            ValueKind::Seq => {
+                let items = match self.0.try_iter() {
+                    Ok(iter) => iter,
+                    Err(_) => return self.0.serialize(serializer),
+                };
+                let mut seq = ok!(serializer.serialize_seq(self.0.len()));
+                for item in items {
+                    ok!(seq.serialize_element(&JsonValue(&item, self.1)));
+                }
+                seq.end()
+            }
+            _ => self.0.serialize(serializer),
+        }
+    }
+}
+
+/// Serializes a value to JSON, pretty printed if an indentation is given.
+///
+/// If `sort_keys` is enabled in the options the keys of all maps are emitted in
+/// sorted order.  Floats are normalized: `-0.0` is emitted as `0.0` and `NaN`
+/// as well as infinite values are emitted as `null` unless `strict_floats` is
+/// enabled in which case they fail to serialize.
+#[cfg(feature = "json")]
+pub fn to_json_string(
+    value: &Value,
+    indent: Option<usize>,
+    json: JsonOptions,
+) -> Result<String, serde_json::Error> {
+    let value = JsonValue(value, json);
+    match indent {
+        None => serde_json::to_string(&value),
+        Some(indent) => {
+            let indent = " ".repeat(indent);
+            let mut out = Vec::new();
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+            let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+            ok!(serde::Serialize::serialize(&value, &mut serializer));
+            // serde_json only ever emits valid utf-8
+            Ok(String::from_utf8(out).unwrap())
+        }
+    }
+}
+
+/// Compiles a regular expression for use in templates.
+///
+/// Compiled expressions are cached per thread keyed by the pattern so that
+/// filters and tests invoked in a loop do not recompile the same pattern.
+#[cfg(feature = "regex")]
+pub fn compile_regex(pattern: &str) -> Result<regex::Regex, Error> {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    const MAX_CACHED_REGEXES: usize = 64;
+
+    thread_local! {
+        static REGEX_CACHE: RefCell<HashMap<String, regex::Regex>> = Default::default();
+    }
+
+    if let Some(re) = REGEX_CACHE.with(|cache| cache.borrow().get(pattern).cloned()) {
+        return Ok(re);
+    }
+
+    let re = ok!(regex::Regex::new(pattern).map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("invalid regular expression {pattern:?}"),
+        )
+        .with_source(err)
+    }));
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.len() >= MAX_CACHED_REGEXES {
+            cache.clear();
+        }
+        cache.insert(pattern.to_string(), re.clone());
+    });
+    Ok(re)
+}
+
+#[cfg(feature = "base64")]
+const B64_STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+#[cfg(feature = "base64")]
+const B64_URLSAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Encodes bytes as padded base64 with the standard or URL-safe alphabet.
+#[cfg(feature = "base64")]
+pub fn b64encode(data: &[u8], urlsafe: bool) -> String {
+    let alphabet = if urlsafe { B64_URLSAFE } else { B64_STANDARD };
+    let mut rv = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                rv.push(alphabet[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                rv.push('=');
+            }
+        }
+    }
+    rv
+}
+
+/// Decodes base64 with the standard or URL-safe alphabet.
+///
+/// Padding is optional and ASCII whitespace is ignored.
+#[cfg(feature = "base64")]
+pub fn b64decode(data: &str, urlsafe: bool) -> Result<Vec<u8>, Error> {
+    let alphabet = if urlsafe { B64_URLSAFE } else { B64_STANDARD };
+    let invalid = || Error::new(ErrorKind::InvalidOperation, "invalid base64 data");
+    let data = data.trim_end_matches(|c: char| c == '=' || c.is_ascii_whitespace());
+    let mut rv = Vec::with_capacity(data.len() / 4 * 3);
+    let mut buf = 0u32;
+    let mut bits = 0;
+    for c in data.bytes() {
+        if c.is_ascii_whitespace() {
+            continue;
+        }
+        let idx = match alphabet.iter().position(|&x| x == c) {
+            Some(idx) => idx as u32,
+            None => return Err(invalid()),
+        };
+        buf = buf << 6 | idx;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            rv.push((buf >> bits) as u8);
+            buf &= (1 << bits) - 1;
+        }
+    }
+    // a single trailing character can never encode a full byte
+    if bits >= 6 {
+        return Err(invalid());
+    }
+    Ok(rv)
+}
+
+/// The JSON serialization settings of an environment.
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+pub struct JsonOptions {
+    /// Sort the keys of maps.
+    pub sort_keys: bool,
+    /// Pretty print with an indentation of two spaces.
+    pub pretty: bool,
+    /// Fail on non-finite floats instead of emitting `null`.
+    pub strict_floats: bool,
+}
+
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+impl JsonOptions {
+    /// Returns the indentation to use if no explicit one is requested.
+    pub fn default_indent(&self) -> Option<usize> {
+        if self.pretty {
+            Some(2)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the JSON serialization settings for this state.
 #[inline(always)]
+#[cfg_attr(not(feature = "json"), allow(unused_variables))]
+pub fn json_options(state: &crate::vm::State) -> JsonOptions {
+    #[cfg(feature = "json")]
+    {
+        JsonOptions {
+            sort_keys: state.env().json_sort_keys(),
+            pretty: state.env().json_pretty(),
+            strict_floats: state.env().json_strict_floats(),
+        }
+    }
+    #[cfg(not(feature = "json"))]
+    {
+        JsonOptions::default()
+    }
+}
+
+#[inline(always)]
+#[cfg_attr(not(feature = "json"), allow(unused_variables))]
 pub fn write_escaped(
     out: &mut Output,
     auto_escape: AutoEscape,
     value: &Value,
+    json: JsonOptions,
 ) -> Result<(), Error> {
     // common case of safe strings or strings without auto escaping
     if let ValueRepr::String(ref s, ty) = value.0 {
@@ -87,10 +377,14 @@ pub fn write_escaped(
         AutoEscape::Html => write_with_html_escaping(out, value).map_err(Error::from),
         #[cfg(feature = "json")]
         AutoEscape::Json => {
-            let value = ok!(serde_json::to_string(&value).map_err(|err| {
-                Error::new(ErrorKind::BadSerialization, "unable to format to JSON").with_source(err)
-            }));
-            write!(out, "{value}").map_err(Error::from)
+            let value = ok!(
+                to_json_string(value, json.default_indent(), json).map_err(|err| {
+                    Error::new(ErrorKind::BadSerialization, "unable to format to JSON")
+                        .with_source(err)
+                })
+            );
+            out.write_str(&escape_json_for_html(&value, false))
+                .map_err(Error::from)
         }
         AutoEscape::Custom(name) => invalid_autoescape(name),
     }
@@ -115,6 +409,9 @@ pub enum AutoEscape {
     ///
     /// Any value effectively ends up being serialized to JSON upon printing.  The
     /// serialized values will be compatible with JavaScript and YAML as well.
+    /// The characters `<`, `>` and `&` as well as the line separators U+2028 and
+    /// U+2029 are emitted as unicode escapes so that the output can also be
+    /// embedded into a `<script>` tag.
     #[cfg(feature = "json")]
     #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
     Json,
@@ -126,6 +423,117 @@ pub enum AutoEscape {
     Custom(&'static str),
 }
 
+/// Controls how floats are printed.
+///
+/// For more information see
+/// [`set_float_format`](crate::Environment::set_float_format).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FloatFormat {
+    /// Always emit a decimal point, rendering `5.0` as `5.0`.
+    ///
+    /// This is the default.
+    AlwaysDecimal,
+    /// Emit the shortest representation that round-trips, rendering `5.0`
+    /// as `5` and `0.1` as `0.1`.
+    Minimal,
+    /// Emit a fixed number of digits after the decimal point.
+    Fixed(usize),
+}
+
+impl Default for FloatFormat {
+    fn default() -> FloatFormat {
+        FloatFormat::AlwaysDecimal
+    }
+}
+
+/// Controls how `NaN` and infinite floats are printed.
+///
+/// For more information see
+/// [`set_non_finite_format`](crate::Environment::set_non_finite_format).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NonFiniteFormat {
+    /// Emit the given tokens for `NaN` and infinity.
+    ///
+    /// Negative infinity is emitted as the infinity token prefixed with `-`.
+    /// The default is `NaN` and `inf`.
+    Tokens {
+        /// The token emitted for `NaN`.
+        nan: &'static str,
+        /// The token emitted for infinity.
+        inf: &'static str,
+    },
+    /// Fail with an error when a non-finite float is printed.
+    Error,
+}
+
+impl Default for NonFiniteFormat {
+    fn default() -> NonFiniteFormat {
+        NonFiniteFormat::Tokens {
+            nan: "NaN",
+            inf: "inf",
+        }
+    }
+}
+
+/// Writes a float in the given format.
+///
+/// `NaN` and infinities are written the same way in all formats.  As this
+/// cannot fail, [`NonFiniteFormat::Error`] falls back to the default tokens.
+pub(crate) fn write_float<W: fmt::Write>(
+    w: &mut W,
+    val: f64,
+    format: FloatFormat,
+    non_finite: NonFiniteFormat,
+) -> fmt::Result {
+    let (nan, inf) = match non_finite {
+        NonFiniteFormat::Tokens { nan, inf } => (nan, inf),
+        NonFiniteFormat::Error => ("NaN", "inf"),
+    };
+    if val.is_nan() {
+        w.write_str(nan)
+    } else if val.is_infinite() {
+        write!(
+            w,
+            "{}{}",
+            if val.is_sign_negative() { "-" } else { "" },
+            inf
+        )
+    } else {
+        match format {
+            FloatFormat::AlwaysDecimal => {
+                let mut num = val.to_string();
+                if !num.contains('.') {
+                    num.push_str(".0");
+                }
+                w.write_str(&num)
+            }
+            FloatFormat::Minimal => write!(w, "{val}"),
+            FloatFormat::Fixed(precision) => write!(w, "{val:.precision$}"),
+        }
+    }
+}
+
+/// Writes a float with the float settings of the environment.
+///
+/// Unlike [`write_float`] this fails for non-finite floats if the
+/// environment is configured to reject them.
+pub(crate) fn write_env_float<W: fmt::Write>(
+    w: &mut W,
+    val: f64,
+    env: &crate::Environment,
+) -> Result<(), Error> {
+    let non_finite = env.non_finite_format();
+    if !val.is_finite() && non_finite == NonFiniteFormat::Error {
+        return Err(Error::new(
+            ErrorKind::InvalidOperation,
+            format!("cannot print non-finite float {val}"),
+        ));
+    }
+    write_float(w, val, env.float_format(), non_finite).map_err(Error::from)
+}
+
 /// Helper to HTML escape a string.
 pub struct HtmlEscape<'a>(pub &'a str);
 
@@ -135,44 +543,71 @@ impl<'a> fmt::Display for HtmlEscape<'a> {
         {
             fmt::Display::fmt(&v_htmlescape::escape(self.0), f)
         }
-        // this is taken from askama-escape
         #[cfg(not(feature = "v_htmlescape"))]
         {
-            let bytes = self.0.as_bytes();
             let mut start = 0;
-
-            for (i, b) in bytes.iter().enumerate() {
-                macro_rules! escaping_body {
-                    ($quote:expr) => {{
-                        if start < i {
-                            ok!(f.write_str(unsafe {
-                                std::str::from_utf8_unchecked(&bytes[start..i])
-                            }));
-                        }
-                        ok!(f.write_str($quote));
-                        start = i + 1;
-                    }};
-                }
-                if b.wrapping_sub(b'"') <= b'>' - b'"' {
-                    match *b {
-                        b'<' => escaping_body!("&lt;"),
-                        b'>' => escaping_body!("&gt;"),
-                        b'&' => escaping_body!("&amp;"),
-                        b'"' => escaping_body!("&quot;"),
-                        b'\'' => escaping_body!("&#x27;"),
-                        b'/' => escaping_body!("&#x2f;"),
-                        _ => (),
-                    }
+            while let Some(offset) = find_html_special(&self.0.as_bytes()[start..]) {
+                let i = start + offset;
+                // all special characters are ascii so slicing here always
+                // happens on character boundaries.
+                if start < i {
+                    ok!(f.write_str(&self.0[start..i]));
                 }
+                ok!(f.write_str(match self.0.as_bytes()[i] {
+                    b'<' => "&lt;",
+                    b'>' => "&gt;",
+                    b'&' => "&amp;",
+                    b'"' => "&quot;",
+                    b'\'' => "&#x27;",
+                    _ => "&#x2f;",
+                }));
+                start = i + 1;
             }
+            f.write_str(&self.0[start..])
+        }
+    }
+}
 
-            if start < bytes.len() {
-                f.write_str(unsafe { std::str::from_utf8_unchecked(&bytes[start..]) })
-            } else {
-                Ok(())
-            }
+#[cfg(not(feature = "v_htmlescape"))]
+#[inline(always)]
+fn is_html_special(b: u8) -> bool {
+    matches!(b, b'<' | b'>' | b'&' | b'"' | b'\'' | b'/')
+}
+
+/// Finds the first character that needs HTML escaping.
+///
+/// Most strings do not contain any such characters so this scans eight
+/// bytes at a time and only looks at individual bytes once a chunk is
+/// known to contain one.
+#[cfg(not(feature = "v_htmlescape"))]
+fn find_html_special(bytes: &[u8]) -> Option<usize> {
+    const LO: u64 = 0x0101_0101_0101_0101;
+    const HI: u64 = 0x8080_8080_8080_8080;
+
+    #[inline(always)]
+    fn has_byte(chunk: u64, b: u8) -> bool {
+        let x = chunk ^ (LO * b as u64);
+        x.wrapping_sub(LO) & !x & HI != 0
+    }
+
+    let mut offset = 0;
+    for chunk in bytes.chunks_exact(8) {
+        let chunk = u64::from_ne_bytes(chunk.try_into().unwrap());
+        if has_byte(chunk, b'<')
+            || has_byte(chunk, b'>')
+            || has_byte(chunk, b'&')
+            || has_byte(chunk, b'"')
+            || has_byte(chunk, b'\'')
+            || has_byte(chunk, b'/')
+        {
+            break;
         }
+        offset += 8;
     }
+    bytes[offset..]
+        .iter()
+        .position(|&b| is_html_special(b))
+        .map(|idx| offset + idx)
 }
 
 struct Unescaper {
@@ -257,6 +692,101 @@ pub fn unescape(s: &str) -> Result<String, Error> {
     .unescape(s)
 }
 
+/// Renders values in the multi-line format of the `pprint` filter.
+pub struct PrettyPrinter {
+    out: String,
+    max_depth: Option<usize>,
+    sort_keys: bool,
+    /// identities of the containers that are currently being printed
+    path: Vec<usize>,
+}
+
+impl PrettyPrinter {
+    pub fn new(max_depth: Option<usize>, sort_keys: bool) -> PrettyPrinter {
+        PrettyPrinter {
+            out: String::new(),
+            max_depth,
+            sort_keys,
+            path: Vec::new(),
+        }
+    }
+
+    pub fn print(mut self, value: &Value) -> String {
+        self.write(value, 0);
+        self.out
+    }
+
+    fn write(&mut self, value: &Value, depth: usize) {
+        use std::fmt::Write;
+        let is_map = match value.kind() {
+            ValueKind::Seq => false,
+            ValueKind::Map => true,
+            ValueKind::Undefined => {
+                self.out.push_str("undefined");
+                return;
+            }
+            ValueKind::None => {
+                self.out.push_str("none");
+                return;
+            }
+            _ => {
+                write!(self.out, "{value:?}").ok();
+                return;
+            }
+        };
+        if self.max_depth.map_or(false, |max_depth| depth >= max_depth) {
+            self.out.push_str("...");
+            return;
+        }
+        let id = match value.0 {
+            ValueRepr::Seq(ref x) => Arc::as_ptr(x) as *const () as usize,
+            ValueRepr::Map(ref x, _) => Arc::as_ptr(x) as *const () as usize,
+            ValueRepr::Dynamic(ref x) => Arc::as_ptr(x) as *const () as usize,
+            _ => 0,
+        };
+        if self.path.contains(&id) {
+            self.out.push_str("<cycle>");
+            return;
+        }
+        let mut items = match value.try_iter() {
+            Ok(iter) => iter.collect::<Vec<_>>(),
+            Err(_) => {
+                write!(self.out, "{value:?}").ok();
+                return;
+            }
+        };
+        if is_map && self.sort_keys {
+            items.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        }
+        let (open, close) = if is_map { ('{', '}') } else { ('[', ']') };
+        self.out.push(open);
+        self.path.push(id);
+        for item in &items {
+            self.out.push('\n');
+            self.indent(depth + 1);
+            self.write(item, depth + 1);
+            if is_map {
+                self.out.push_str(": ");
+                let value = value.get_item(item).unwrap_or(Value::UNDEFINED);
+                self.write(&value, depth + 1);
+            }
+            self.out.push(',');
+        }
+        self.path.pop();
+        if !items.is_empty() {
+            self.out.push('\n');
+            self.indent(depth);
+        }
+        self.out.push(close);
+    }
+
+    fn indent(&mut self, depth: usize) {
+        for _ in 0..depth {
+            self.out.push_str("    ");
+        }
+    }
+}
+
 pub struct BTreeMapKeysDebug<'a, K: fmt::Debug, V>(pub &'a BTreeMap<K, V>);
 
 impl<'a, K: fmt::Debug, V> fmt::Debug for BTreeMapKeysDebug<'a, K, V> {
@@ -286,6 +816,40 @@ fn test_html_escape() {
     assert_eq!(output, "&lt;&gt;&amp;&quot;&#x27;&#x2f;");
 }
 
+#[test]
+#[cfg(not(feature = "v_htmlescape"))]
+fn test_find_html_special() {
+    let naive = |s: &[u8]| s.iter().position(|&b| is_html_special(b));
+    let mut input = Vec::new();
+    for idx in 0..40 {
+        input.push(b'a' + (idx % 26) as u8);
+        for special in [b'<', b'>', b'&', b'"', b'\'', b'/'] {
+            let mut s = input.clone();
+            s.push(special);
+            s.extend_from_slice("äbc".as_bytes());
+            assert_eq!(find_html_special(&s), naive(&s));
+        }
+        assert_eq!(find_html_special(&input), None);
+    }
+    // bytes right next to the special characters must not match
+    assert_eq!(find_html_special(b"!#%;=?.0;=?.0;=?.0%"), None);
+}
+
+#[test]
+fn test_html_escape_non_string() {
+    let value = Value::from(vec!["<a>", "b&c"]);
+    let mut rv = String::new();
+    write_escaped(
+        &mut Output::with_string(&mut rv),
+        AutoEscape::Html,
+        &value,
+        JsonOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(rv, HtmlEscape(&value.to_string()).to_string());
+    assert_eq!(rv, "[&quot;&lt;a&gt;&quot;, &quot;b&amp;c&quot;]");
+}
+
 #[test]
 fn test_unescape() {
     assert_eq!(unescape(r"foo\u2603bar").unwrap(), "foo\u{2603}bar");
@@ -293,3 +857,30 @@ fn test_unescape() {
     assert_eq!(unescape("foobarbaz").unwrap(), "foobarbaz");
     assert_eq!(unescape(r"\ud83d\udca9").unwrap(), "💩");
 }
+
+#[test]
+#[cfg(feature = "base64")]
+fn test_b64() {
+    for (raw, encoded) in [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ] {
+        assert_eq!(b64encode(raw.as_bytes(), false), encoded);
+        assert_eq!(b64decode(encoded, false).unwrap(), raw.as_bytes());
+        assert_eq!(
+            b64decode(encoded.trim_end_matches('='), false).unwrap(),
+            raw.as_bytes()
+        );
+    }
+    assert_eq!(b64encode(b"\xfb\xff", false), "+/8=");
+    assert_eq!(b64encode(b"\xfb\xff", true), "-_8=");
+    assert_eq!(b64decode("-_8=", true).unwrap(), b"\xfb\xff");
+    assert!(b64decode("-_8=", false).is_err());
+    assert!(b64decode("Zm9vY", false).is_err());
+    assert!(b64decode("Zm=9v", false).is_err());
+}
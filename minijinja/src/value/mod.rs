@@ -47,6 +47,34 @@
 //! let v = u64::try_from(Value::from(42)).unwrap();
 //! ```
 //!
+//! Values can also be compared directly against strings, bools and numbers.
+//! This follows the rules of the `==` operator in templates, so integers and
+//! floats compare by their numeric value:
+//!
+//! ```
+//! # use minijinja::value::Value;
+//! assert!(Value::from(42) == 42.0);
+//! assert!(Value::from("foo") == "foo");
+//! assert!(true == Value::from(true));
+//! ```
+//!
+//! # Map Ordering
+//!
+//! By default maps are backed by a [`BTreeMap`](std::collections::BTreeMap)
+//! so their keys are iterated (and serialized, for instance by the `tojson`
+//! filter) in sorted order, no matter in which order they were inserted.
+//! When the `preserve_order` feature is enabled maps are backed by an index
+//! map instead and keep the order in which keys were first inserted.  This
+//! applies to maps created from Rust (via [`FromIterator`] or
+//! [`Value::from_serializable`], when the source map or struct itself is
+//! ordered) and to map literals in templates.
+//! Assigning to a key that already exists does not change its position.
+//!
+//! Note that the `preserve_order` feature only affects the maps of MiniJinja.
+//! Data that passes through other libraries first needs to preserve its order
+//! there as well (for instance `serde_json` requires its own `preserve_order`
+//! feature for `serde_json::Value` to be ordered).
+//!
 //! # Value Function Arguments
 //!
 //! [Filters](crate::filters) and [tests](crate::tests) can take values as arguments
@@ -114,9 +142,9 @@ use serde::ser::{Serialize, Serializer};
 
 use crate::error::{Error, ErrorKind};
 use crate::functions;
-use crate::key::{Key, StaticKey};
-use crate::utils::OnDrop;
-use crate::value::object::{SimpleSeqObject, SimpleStructObject};
+use crate::key::{invalid_key, Key, StaticKey};
+use crate::utils::{write_float, FloatFormat, NonFiniteFormat, OnDrop};
+use crate::value::object::{IteratorSeqObject, SimpleSeqObject, SimpleStructObject};
 use crate::value::serialize::ValueSerializer;
 use crate::vm::State;
 
@@ -323,24 +351,149 @@ impl fmt::Debug for ValueRepr {
 #[derive(Clone)]
 pub struct Value(pub(crate) ValueRepr);
 
+/// The maximum nesting of containers that are compared structurally.
+const MAX_EQ_DEPTH: usize = 500;
+
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match (&self.0, &other.0) {
-            (ValueRepr::None, ValueRepr::None) => true,
-            (ValueRepr::String(ref a, _), ValueRepr::String(ref b, _)) => a == b,
-            (ValueRepr::Bytes(a), ValueRepr::Bytes(b)) => a == b,
-            _ => match ops::coerce(self, other) {
-                Some(ops::CoerceResult::F64(a, b)) => a == b,
-                Some(ops::CoerceResult::I128(a, b)) => a == b,
-                Some(ops::CoerceResult::Str(a, b)) => a == b,
-                None => false,
-            },
+        eq_impl(self, other, &mut Vec::new())
+    }
+}
+
+/// Compares two values.
+///
+/// `path` holds the identities of the pairs of containers that are currently
+/// being compared so that self-referential objects do not recurse forever.
+fn eq_impl(a: &Value, b: &Value, path: &mut Vec<(usize, usize)>) -> bool {
+    match (&a.0, &b.0) {
+        (ValueRepr::None, ValueRepr::None) => true,
+        (ValueRepr::String(ref a, _), ValueRepr::String(ref b, _)) => a == b,
+        (ValueRepr::Bytes(a), ValueRepr::Bytes(b)) => a == b,
+        _ => match ops::coerce(a, b) {
+            Some(ops::CoerceResult::F64(a, b)) => a == b,
+            Some(ops::CoerceResult::I128(a, b)) => a == b,
+            Some(ops::CoerceResult::Str(a, b)) => a == b,
+            None => eq_containers(a, b, path),
+        },
+    }
+}
+
+/// Compares sequences and maps item by item.
+fn eq_containers(a: &Value, b: &Value, path: &mut Vec<(usize, usize)>) -> bool {
+    let is_map = match (a.kind(), b.kind()) {
+        (ValueKind::Seq, ValueKind::Seq) => false,
+        (ValueKind::Map, ValueKind::Map) => true,
+        _ => return false,
+    };
+    let id = (container_id(a), container_id(b));
+    // a pair that is already being compared is equal unless other items differ
+    if path.contains(&id) {
+        return true;
+    }
+    if path.len() >= MAX_EQ_DEPTH {
+        return false;
+    }
+    path.push(id);
+    let rv = if is_map {
+        a.len() == b.len()
+            && a.try_iter_pairs().map_or(false, |mut iter| {
+                iter.all(|(key, value)| {
+                    b.get_item(&key)
+                        .map_or(false, |x| !x.is_undefined() && eq_impl(&value, &x, path))
+                })
+            })
+    } else {
+        match (a.as_seq(), b.as_seq()) {
+            (Some(a), Some(b)) => {
+                a.item_count() == b.item_count()
+                    && a.iter().zip(b.iter()).all(|(a, b)| eq_impl(&a, &b, path))
+            }
+            _ => false,
         }
+    };
+    path.pop();
+    rv
+}
+
+/// Returns the identity of a container.
+fn container_id(value: &Value) -> usize {
+    match value.0 {
+        ValueRepr::Seq(ref x) => Arc::as_ptr(x) as *const () as usize,
+        ValueRepr::Map(ref x, _) => Arc::as_ptr(x) as *const () as usize,
+        ValueRepr::Dynamic(ref x) => Arc::as_ptr(x) as *const () as usize,
+        _ => 0,
     }
 }
 
 impl Eq for Value {}
 
+macro_rules! impl_scalar_eq {
+    ($($ty:ty),*) => {
+        $(
+            impl PartialEq<$ty> for Value {
+                #[inline]
+                fn eq(&self, other: &$ty) -> bool {
+                    // numbers are cheap to convert, so this reuses the coercion
+                    // rules of the engine.
+                    let other = Value::from(*other);
+                    *self == other
+                }
+            }
+
+            impl PartialEq<Value> for $ty {
+                #[inline]
+                fn eq(&self, other: &Value) -> bool {
+                    *other == *self
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar_eq!(bool, i32, i64, u32, u64, f64);
+
+impl PartialEq<str> for Value {
+    #[inline]
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == Some(other)
+    }
+}
+
+impl PartialEq<&str> for Value {
+    #[inline]
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == Some(*other)
+    }
+}
+
+impl PartialEq<String> for Value {
+    #[inline]
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == Some(other.as_str())
+    }
+}
+
+impl PartialEq<Value> for str {
+    #[inline]
+    fn eq(&self, other: &Value) -> bool {
+        other == self
+    }
+}
+
+impl PartialEq<Value> for &str {
+    #[inline]
+    fn eq(&self, other: &Value) -> bool {
+        other == self
+    }
+}
+
+impl PartialEq<Value> for String {
+    #[inline]
+    fn eq(&self, other: &Value) -> bool {
+        other == self
+    }
+}
+
 impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (&self.0, &other.0) {
@@ -369,19 +522,12 @@ impl fmt::Display for Value {
             ValueRepr::Bool(val) => write!(f, "{val}"),
             ValueRepr::U64(val) => write!(f, "{val}"),
             ValueRepr::I64(val) => write!(f, "{val}"),
-            ValueRepr::F64(val) => {
-                if val.is_nan() {
-                    write!(f, "NaN")
-                } else if val.is_infinite() {
-                    write!(f, "{}inf", if val.is_sign_negative() { "-" } else { "" })
-                } else {
-                    let mut num = val.to_string();
-                    if !num.contains('.') {
-                        num.push_str(".0");
-                    }
-                    write!(f, "{num}")
-                }
-            }
+            ValueRepr::F64(val) => write_float(
+                f,
+                *val,
+                FloatFormat::AlwaysDecimal,
+                NonFiniteFormat::default(),
+            ),
             ValueRepr::Char(val) => write!(f, "{val}"),
             ValueRepr::None => write!(f, "none"),
             ValueRepr::I128(val) => write!(f, "{}", { val.0 }),
@@ -460,11 +606,37 @@ impl Value {
     /// let val = Value::from_serializable(&vec![1, 2, 3]);
     /// ```
     ///
+    /// # Map Keys
+    ///
+    /// Map keys are not coerced to strings but keep their type.  A map keyed
+    /// by integers thus has to be indexed with integers (`map[1]`) and not with
+    /// strings (`map["1"]`).  The following keys are supported:
+    ///
+    /// * strings and chars
+    /// * booleans
+    /// * integers that fit into an `i64` (this includes 128 bit integers
+    ///   within that range)
+    /// * floats without a fractional part which become integer keys, the
+    ///   same as when such a float is used as key in a template
+    /// * unit enum variants which become strings with the name of the variant
+    /// * newtype structs and newtype variants which use the key of the
+    ///   wrapped value
+    ///
+    /// ```
+    /// # use std::collections::BTreeMap;
+    /// # use minijinja::value::Value;
+    /// let map = BTreeMap::from([(1, "one"), (2, "two")]);
+    /// let val = Value::from_serializable(&map);
+    /// assert_eq!(val.get_item(&Value::from(1)).unwrap(), Value::from("one"));
+    /// assert!(val.get_item(&Value::from("1")).unwrap().is_undefined());
+    /// ```
+    ///
     /// # Panics
     ///
     /// This method panics if types are passed which are not supported by the
     /// underlying rendering system.  Today this is the case for value types that
-    /// have unrepresentable keys.  The desire is that eventually this call will no
+    /// have unrepresentable keys (for instance floats with a fractional part,
+    /// sequences or structs).  The desire is that eventually this call will no
     /// longer panic under any circumstances.
     pub fn from_serializable<T: Serialize>(value: &T) -> Value {
         let _serialization_guard = mark_internal_serialization();
@@ -485,7 +657,11 @@ impl Value {
     /// ```
     /// # use minijinja::value::Value;
     /// let val = Value::from_safe_string("<em>note</em>".into());
+    /// assert!(val.is_safe());
     /// ```
+    ///
+    /// This is useful for HTML that was already rendered or escaped in Rust,
+    /// for instance by a markdown renderer, before it's placed in the context.
     pub fn from_safe_string(value: String) -> Value {
         ValueRepr::String(Arc::new(value), StringType::Safe).into()
     }
@@ -545,6 +721,37 @@ impl Value {
         Value::from_object(SimpleSeqObject(value))
     }
 
+    /// Creates a sequence value from an iterator.
+    ///
+    /// The iterator is not consumed when the value is created but only once
+    /// the template uses it.  Items are buffered as they are produced so the
+    /// resulting sequence can be iterated over as often as needed.  Note that
+    /// looping over the sequence or asking for its length consumes the entire
+    /// iterator as the engine needs to know the number of items upfront, so
+    /// the iterator must be finite.
+    ///
+    /// ```
+    /// # use minijinja::{context, Environment};
+    /// # use minijinja::value::Value;
+    /// let squares = Value::from_iterator((1..=3).map(|x| x * x));
+    /// let env = Environment::new();
+    /// let rv = env.render_str("{{ squares|join(',') }}", context!(squares)).unwrap();
+    /// assert_eq!(rv, "1,4,9");
+    /// ```
+    ///
+    /// **Note:** objects created this way cannot be downcasted via
+    /// [`downcast_object_ref`](Self::downcast_object_ref).
+    pub fn from_iterator<I>(iter: I) -> Value
+    where
+        I: IntoIterator,
+        I::IntoIter: Send + 'static,
+        I::Item: Into<Value> + 'static,
+    {
+        Value::from_seq_object(IteratorSeqObject::new(Box::new(
+            iter.into_iter().map(Into::into),
+        )))
+    }
+
     /// Creates a value from an owned [`StructObject`].
     ///
     /// This is a simplified API for creating dynamic structs
@@ -562,6 +769,22 @@ impl Value {
     /// # use minijinja::value::Value;
     /// let pow = Value::from_function(|a: u32| a * a);
     /// ```
+    ///
+    /// Such values can be placed anywhere a value can go, not only as
+    /// globals on the environment.  Passed in the context of a render call
+    /// they act as per-render functions, which makes it possible to hand
+    /// request specific behavior to a template without changing the shared
+    /// environment:
+    ///
+    /// ```
+    /// # use minijinja::{context, Environment};
+    /// # use minijinja::value::Value;
+    /// let env = Environment::new();
+    /// let base = String::from("/en/");
+    /// let url_for = Value::from_function(move |page: String| format!("{base}{page}"));
+    /// let rv = env.render_str("{{ url_for('about') }}", context!(url_for)).unwrap();
+    /// assert_eq!(rv, "/en/about");
+    /// ```
     pub fn from_function<F, Rv, Args>(f: F) -> Value
     where
         // the crazy bounds here exist to enable borrowing in closures
@@ -599,12 +822,53 @@ impl Value {
         }
     }
 
+    /// Returns `true` if the value can be iterated over.
+    ///
+    /// This is the case for sequences, maps and strings.  All of these can be
+    /// iterated over repeatedly.  There is no support for one-shot generators
+    /// in the engine, sequences created from iterators via
+    /// [`from_iterator`](Self::from_iterator) buffer their items instead.
+    ///
+    /// Note that looping over none or undefined is permitted in templates
+    /// (the loop body is just never executed) but they are not considered
+    /// iterable.
+    pub fn is_iterable(&self) -> bool {
+        matches!(
+            self.kind(),
+            ValueKind::String | ValueKind::Seq | ValueKind::Map
+        )
+    }
+
     /// Returns `true` if the map represents keyword arguments.
     pub fn is_kwargs(&self) -> bool {
         matches!(self.0, ValueRepr::Map(_, MapType::Kwargs))
     }
 
     /// Is this value true?
+    ///
+    /// This is the truthiness the engine uses for `if`, `not`, `and`/`or`,
+    /// the `bool` filter and the argument-less `select` and `reject`, so
+    /// custom filters and functions should use it where they accept "a value
+    /// that is true".  The following values are false, everything else is
+    /// true:
+    ///
+    /// * `undefined` and `none`
+    /// * `false`
+    /// * the number zero (in any of the supported number types)
+    /// * empty strings and byte strings as well as the character `\0`
+    /// * empty sequences and maps (including dynamic sequence and struct
+    ///   objects without items or fields)
+    ///
+    /// Plain dynamic objects are always true.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// assert!(Value::from("x").is_true());
+    /// assert!(!Value::from("").is_true());
+    /// assert!(!Value::from(0.0).is_true());
+    /// assert!(!Value::from(Vec::<i32>::new()).is_true());
+    /// assert!(!Value::UNDEFINED.is_true());
+    /// ```
     pub fn is_true(&self) -> bool {
         match self.0 {
             ValueRepr::Bool(val) => val,
@@ -628,6 +892,10 @@ impl Value {
     }
 
     /// Returns `true` if this value is safe.
+    ///
+    /// Safe values are strings that bypass auto escaping.  They are created
+    /// with [`from_safe_string`](Self::from_safe_string) or by the `safe` and
+    /// `escape` filters.
     pub fn is_safe(&self) -> bool {
         matches!(&self.0, ValueRepr::String(_, StringType::Safe))
     }
@@ -659,6 +927,67 @@ impl Value {
         }
     }
 
+    /// If the value is an integer that fits into an `i64`, return it.
+    ///
+    /// Floats are converted if they have no fractional part.  Other values
+    /// (including bools) return `None`.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// assert_eq!(Value::from(42).as_i64(), Some(42));
+    /// assert_eq!(Value::from(42.0).as_i64(), Some(42));
+    /// assert_eq!(Value::from(42.5).as_i64(), None);
+    /// assert_eq!(Value::from(u64::MAX).as_i64(), None);
+    /// ```
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.0 {
+            ValueRepr::Bool(_) => None,
+            _ => i64::try_from(self.clone()).ok(),
+        }
+    }
+
+    /// If the value is an integer that fits into an `u64`, return it.
+    ///
+    /// This works like [`as_i64`](Self::as_i64) but for unsigned integers.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.0 {
+            ValueRepr::Bool(_) => None,
+            _ => u64::try_from(self.clone()).ok(),
+        }
+    }
+
+    /// If the value is a number, return it as `f64`.
+    ///
+    /// Integers are converted to floats which can lose precision for very
+    /// large values.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// assert_eq!(Value::from(42).as_f64(), Some(42.0));
+    /// assert_eq!(Value::from(0.5).as_f64(), Some(0.5));
+    /// assert_eq!(Value::from("42").as_f64(), None);
+    /// ```
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.0 {
+            ValueRepr::U64(x) => Some(x as f64),
+            ValueRepr::U128(x) => Some(x.0 as f64),
+            ValueRepr::I64(x) => Some(x as f64),
+            ValueRepr::I128(x) => Some(x.0 as f64),
+            ValueRepr::F64(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// If the value is a bool, return it.
+    ///
+    /// Unlike [`is_true`](Self::is_true) this does not test for truthiness.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.0 {
+            ValueRepr::Bool(x) => Some(x),
+            _ => None,
+        }
+    }
+
     /// If the value is an object, it's returned as [`Object`].
     pub fn as_object(&self) -> Option<&dyn Object> {
         match self.0 {
@@ -691,14 +1020,44 @@ impl Value {
         None
     }
 
+    /// If the value is a map, returns a [`MapRef`] to look up its items.
+    ///
+    /// This is the case for maps as well as for struct objects.  Together with
+    /// [`as_seq`](Self::as_seq) this lets filters branch on the container type
+    /// and use keyed or indexed lookups directly.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let value = Value::from_iter([("a", 1), ("b", 2)]);
+    /// let map = value.as_map().unwrap();
+    /// assert_eq!(map.len(), 2);
+    /// assert_eq!(map.get(&Value::from("b")), Some(Value::from(2)));
+    /// assert!(Value::from(vec![1, 2]).as_map().is_none());
+    /// ```
+    pub fn as_map(&self) -> Option<MapRef<'_>> {
+        match self.0 {
+            ValueRepr::Map(..) => Some(MapRef(self)),
+            ValueRepr::Dynamic(ref dy) if matches!(dy.kind(), ObjectKind::Struct(_)) => {
+                Some(MapRef(self))
+            }
+            _ => None,
+        }
+    }
+
     /// Returns the length of the contained value.
     ///
-    /// Values without a length will return `None`.
+    /// This is the Rust counterpart of the `length` filter.  For strings it
+    /// returns the number of characters, for maps and struct objects the
+    /// number of entries and for sequences (including sequence objects) the
+    /// number of items.  Values without a length (like numbers, none or
+    /// undefined) will return `None`.
     ///
     /// ```
     /// # use minijinja::value::Value;
     /// let seq = Value::from(vec![1, 2, 3, 4]);
     /// assert_eq!(seq.len(), Some(4));
+    /// assert_eq!(Value::from("äöü").len(), Some(3));
+    /// assert_eq!(Value::from(42).len(), None);
     /// ```
     pub fn len(&self) -> Option<usize> {
         match self.0 {
@@ -797,6 +1156,35 @@ impl Value {
         }
     }
 
+    /// Looks up a value by a dotted path.
+    ///
+    /// The path is split on `.` and every segment is looked up in turn.  If a
+    /// segment is an integer it's used to index into sequences, otherwise it's
+    /// looked up as attribute.  A missing segment at the end results in
+    /// [`UNDEFINED`](Self::UNDEFINED), looking up a segment on an undefined
+    /// value is an error.  This is the lookup used by filters that accept an
+    /// `attribute` argument such as `map` or `sort`.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let ctx = minijinja::context! {
+    ///     items => vec![minijinja::context! { name => "Peter" }],
+    /// };
+    /// let value = ctx.get_path("items.0.name").unwrap();
+    /// assert_eq!(value.to_string(), "Peter");
+    /// ```
+    pub fn get_path(&self, path: &str) -> Result<Value, Error> {
+        let mut rv = self.clone();
+        for part in path.split('.') {
+            if let Ok(num) = part.parse::<usize>() {
+                rv = ok!(rv.get_item_by_index(num));
+            } else {
+                rv = ok!(rv.get_attr(part));
+            }
+        }
+        Ok(rv)
+    }
+
     /// Iterates over the value.
     ///
     /// Depending on the [`kind`](Self::kind) of the value the iterator
@@ -828,6 +1216,97 @@ impl Value {
         })
     }
 
+    /// Merges two maps into a new map.
+    ///
+    /// The resulting map contains all keys of both maps.  If a key exists in
+    /// both maps the value of `other` wins.  Nested maps are not merged, use
+    /// the [`combine`](crate::filters::combine) filter with `recursive=true`
+    /// for this.  If either value is not a map an error is returned.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let defaults: Value = [("port", 80), ("workers", 4)].into_iter().collect();
+    /// let overrides: Value = [("port", 8080)].into_iter().collect();
+    /// let config = defaults.merge(&overrides).unwrap();
+    /// assert_eq!(config.get_attr("port").unwrap(), 8080);
+    /// assert_eq!(config.get_attr("workers").unwrap(), 4);
+    /// ```
+    pub fn merge(&self, other: &Value) -> Result<Value, Error> {
+        self.merge_maps(other, false)
+    }
+
+    pub(crate) fn merge_maps(&self, other: &Value, recursive: bool) -> Result<Value, Error> {
+        let mut rv = ValueMap::default();
+        for (key, value) in ok!(self.try_iter_pairs()) {
+            rv.insert(ok!(key.try_into_key()), value);
+        }
+        for (key, value) in ok!(other.try_iter_pairs()) {
+            let key = ok!(key.try_into_key());
+            let value = match rv.get(&key) {
+                Some(old)
+                    if recursive
+                        && old.kind() == ValueKind::Map
+                        && value.kind() == ValueKind::Map =>
+                {
+                    ok!(old.merge_maps(&value, true))
+                }
+                _ => value,
+            };
+            rv.insert(key, value);
+        }
+        Ok(ValueRepr::Map(Arc::new(rv), MapType::Normal).into())
+    }
+
+    /// Iterates over the key/value pairs of a map.
+    ///
+    /// The pairs are returned in the iteration order of the map.  If the value
+    /// is not a map an error is returned.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// # fn test() -> Result<(), minijinja::Error> {
+    /// let value: Value = [("foo", 42), ("bar", 23)].into_iter().collect();
+    /// for (key, value) in value.try_iter_pairs()? {
+    ///     println!("{} = {}", key, value);
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn try_iter_pairs(&self) -> Result<ValuePairIter<'_>, Error> {
+        if self.kind() != ValueKind::Map {
+            return Err(Error::new(
+                ErrorKind::InvalidOperation,
+                format!("cannot iterate over pairs of {}", self.kind()),
+            ));
+        }
+        self.try_iter()
+            .map(|keys| ValuePairIter { map: self, keys })
+    }
+
+    /// Renders the value into a human readable, multi-line string.
+    ///
+    /// This uses the same format as the [`pprint`](crate::filters::pprint)
+    /// filter but the keys of maps are always sorted, so the output is stable
+    /// independent of the map's iteration order.  This makes it useful for
+    /// logging and snapshot tests.
+    ///
+    /// ```
+    /// # use minijinja::value::Value;
+    /// let value = Value::from_serializable(&serde_json::json!({
+    ///     "b": [1, 2],
+    ///     "a": true,
+    /// }));
+    /// assert_eq!(value.to_pretty_string(), r#"{
+    ///     "a": true,
+    ///     "b": [
+    ///         1,
+    ///         2,
+    ///     ],
+    /// }"#);
+    /// ```
+    pub fn to_pretty_string(&self) -> String {
+        crate::utils::PrettyPrinter::new(None, true).print(self)
+    }
+
     /// Returns some reference to the boxed object if it is of type `T`, or None if it isn’t.
     ///
     /// This is basically the "reverse" of [`from_object`](Self::from_object).  It's also
@@ -893,7 +1372,29 @@ impl Value {
     }
 
     /// Calls the value directly.
-    pub(crate) fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
+    ///
+    /// This invokes callable values such as functions added with
+    /// [`Value::from_function`], macros or objects implementing
+    /// [`Object::call`].  Calling any other value fails with an
+    /// [`InvalidOperation`](ErrorKind::InvalidOperation) error.  As a state is
+    /// required this is typically used from filters or functions which can
+    /// request the [`State`] as first argument:
+    ///
+    /// ```
+    /// # use minijinja::{Environment, Error, State};
+    /// # use minijinja::value::Value;
+    /// # let mut env = Environment::new();
+    /// fn apply(state: &State, value: Value, func: Value) -> Result<Value, Error> {
+    ///     func.call(state, &[value])
+    /// }
+    /// env.add_filter("apply", apply);
+    /// let rv = env.render_str(
+    ///     "{% macro double(x) %}{{ x * 2 }}{% endmacro %}{{ 21|apply(double) }}",
+    ///     (),
+    /// );
+    /// assert_eq!(rv.unwrap(), "42");
+    /// ```
+    pub fn call(&self, state: &State, args: &[Value]) -> Result<Value, Error> {
         if let ValueRepr::Dynamic(ref dy) = self.0 {
             dy.call(state, args)
         } else {
@@ -914,12 +1415,11 @@ impl Value {
     }
 
     /// Calls a method on the value.
-    pub(crate) fn call_method(
-        &self,
-        state: &State,
-        name: &str,
-        args: &[Value],
-    ) -> Result<Value, Error> {
+    ///
+    /// For dynamic objects this dispatches to [`Object::call_method`], for maps
+    /// the value stored under `name` is called.  If there is no such method
+    /// an [`UnknownMethod`](ErrorKind::UnknownMethod) error is returned.
+    pub fn call_method(&self, state: &State, name: &str, args: &[Value]) -> Result<Value, Error> {
         match self.0 {
             ValueRepr::Dynamic(ref dy) => return dy.call_method(state, name, args),
             ValueRepr::Map(ref map, _) => {
@@ -930,7 +1430,7 @@ impl Value {
             _ => {}
         }
         Err(Error::new(
-            ErrorKind::InvalidOperation,
+            ErrorKind::UnknownMethod,
             format!("object has no method named {name}"),
         ))
     }
@@ -940,17 +1440,17 @@ impl Value {
             ValueRepr::Bool(val) => Ok(Key::Bool(val)),
             ValueRepr::U64(v) => TryFrom::try_from(v)
                 .map(Key::I64)
-                .map_err(|_| ErrorKind::NonKey.into()),
+                .map_err(|_| invalid_key(&self)),
             ValueRepr::U128(v) => TryFrom::try_from(v.0)
                 .map(Key::I64)
-                .map_err(|_| ErrorKind::NonKey.into()),
+                .map_err(|_| invalid_key(&self)),
             ValueRepr::I64(v) => Ok(Key::I64(v)),
             ValueRepr::I128(v) => TryFrom::try_from(v.0)
                 .map(Key::I64)
-                .map_err(|_| ErrorKind::NonKey.into()),
+                .map_err(|_| invalid_key(&self)),
             ValueRepr::Char(c) => Ok(Key::Char(c)),
             ValueRepr::String(ref s, _) => Ok(Key::String(s.clone())),
-            _ => Err(ErrorKind::NonKey.into()),
+            _ => Err(invalid_key(&self)),
         }
     }
 
@@ -1005,19 +1505,6 @@ impl Value {
         };
         Ok(OwnedValueIterator { iter_state, len })
     }
-
-    #[cfg(feature = "builtins")]
-    pub(crate) fn get_path(&self, path: &str) -> Result<Value, Error> {
-        let mut rv = self.clone();
-        for part in path.split('.') {
-            if let Ok(num) = part.parse::<usize>() {
-                rv = ok!(rv.get_item_by_index(num));
-            } else {
-                rv = ok!(rv.get_attr(part));
-            }
-        }
-        Ok(rv)
-    }
 }
 
 impl Serialize for Value {
@@ -1059,33 +1546,38 @@ impl Serialize for Value {
                 }
                 map.end()
             }
-            ValueRepr::Dynamic(ref dy) => match dy.kind() {
-                ObjectKind::Plain => serializer.serialize_str(&dy.to_string()),
-                ObjectKind::Seq(s) => {
-                    use serde::ser::SerializeSeq;
-                    let mut seq = ok!(serializer.serialize_seq(Some(s.item_count())));
-                    for item in s.iter() {
-                        ok!(seq.serialize_element(&item));
-                    }
-                    seq.end()
+            ValueRepr::Dynamic(ref dy) => {
+                if let Some(v) = dy.to_serializable_value() {
+                    return v.serialize(serializer);
                 }
-                ObjectKind::Struct(s) => {
-                    use serde::ser::SerializeMap;
-                    let mut map = ok!(serializer.serialize_map(None));
-                    if let Some(fields) = s.static_fields() {
-                        for k in fields {
-                            let v = s.get_field(k).unwrap_or(Value::UNDEFINED);
-                            ok!(map.serialize_entry(k, &v));
+                match dy.kind() {
+                    ObjectKind::Plain => serializer.serialize_str(&dy.to_string()),
+                    ObjectKind::Seq(s) => {
+                        use serde::ser::SerializeSeq;
+                        let mut seq = ok!(serializer.serialize_seq(Some(s.item_count())));
+                        for item in s.iter() {
+                            ok!(seq.serialize_element(&item));
                         }
-                    } else {
-                        for k in s.fields() {
-                            let v = s.get_field(&k).unwrap_or(Value::UNDEFINED);
-                            ok!(map.serialize_entry(k.as_str(), &v));
+                        seq.end()
+                    }
+                    ObjectKind::Struct(s) => {
+                        use serde::ser::SerializeMap;
+                        let mut map = ok!(serializer.serialize_map(None));
+                        if let Some(fields) = s.static_fields() {
+                            for k in fields {
+                                let v = s.get_field(k).unwrap_or(Value::UNDEFINED);
+                                ok!(map.serialize_entry(k, &v));
+                            }
+                        } else {
+                            for k in s.fields() {
+                                let v = s.get_field(&k).unwrap_or(Value::UNDEFINED);
+                                ok!(map.serialize_entry(k.as_str(), &v));
+                            }
                         }
+                        map.end()
                     }
-                    map.end()
                 }
-            },
+            }
         }
     }
 }
@@ -1105,6 +1597,102 @@ impl<'a> Iterator for ValueIter<'a> {
     }
 }
 
+/// A borrowed view of a map or struct object.
+///
+/// See [`Value::as_map`].
+#[derive(Clone, Copy)]
+pub struct MapRef<'a>(&'a Value);
+
+impl<'a> MapRef<'a> {
+    /// Returns the number of items in the map.
+    pub fn len(&self) -> usize {
+        self.0.len().unwrap_or(0)
+    }
+
+    /// Returns `true` if the map has no items.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Looks up the value for a key.
+    pub fn get(&self, key: &Value) -> Option<Value> {
+        self.0.get_item_opt(key)
+    }
+
+    /// Iterates over the keys of the map.
+    pub fn keys(&self) -> ValueIter<'a> {
+        self.0.try_iter().unwrap()
+    }
+
+    /// Iterates over the key/value pairs of the map.
+    pub fn iter(&self) -> ValuePairIter<'a> {
+        self.0.try_iter_pairs().unwrap()
+    }
+}
+
+impl<'a> fmt::Debug for MapRef<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.0, f)
+    }
+}
+
+/// Iterates over the key/value pairs of a map.
+///
+/// See [`Value::try_iter_pairs`].
+pub struct ValuePairIter<'a> {
+    map: &'a Value,
+    keys: ValueIter<'a>,
+}
+
+impl<'a> Iterator for ValuePairIter<'a> {
+    type Item = (Value, Value);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.keys.next().map(|key| {
+            let value = self.map.get_item(&key).unwrap_or(Value::UNDEFINED);
+            (key, value)
+        })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+/// Iterates over a value by reference.
+///
+/// This yields the same items as looping over the value in a template:
+/// the items of sequences, the keys of maps and the characters of strings.
+/// Unlike [`Value::try_iter`] this never fails.  Values that cannot be
+/// iterated over (such as numbers) produce an empty iterator.
+///
+/// ```
+/// # use minijinja::value::Value;
+/// let value = Value::from(vec![1, 2, 3]);
+/// let mut sum = 0;
+/// for item in &value {
+///     sum += i64::try_from(item).unwrap();
+/// }
+/// assert_eq!(sum, 6);
+/// assert_eq!((&Value::from(42)).into_iter().count(), 0);
+/// ```
+impl<'a> IntoIterator for &'a Value {
+    type Item = Value;
+    type IntoIter = ValueIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.try_iter().unwrap_or_else(|_| ValueIter {
+            _marker: PhantomData,
+            inner: OwnedValueIterator {
+                iter_state: ValueIteratorState::Empty,
+                len: 0,
+            },
+        })
+    }
+}
+
 pub(crate) struct OwnedValueIterator {
     iter_state: ValueIteratorState,
     len: usize,
@@ -1,7 +1,7 @@
 use std::any::{Any, TypeId};
 use std::fmt;
 use std::ops::Range;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use crate::error::{Error, ErrorKind};
 use crate::value::{intern, Value};
@@ -74,6 +74,43 @@ pub trait Object: fmt::Display + fmt::Debug + Any + Sync + Send {
             "tried to call non callable object",
         ))
     }
+
+    /// Returns a value that represents the object when it's serialized.
+    ///
+    /// By default objects are serialized according to their kind: sequences
+    /// as sequences, structs as maps of their fields and plain objects as
+    /// their string representation.  Except for that last case the
+    /// [`tojson`](crate::filters::tojson) filter refuses to serialize plain
+    /// objects, so objects that should be usable with it can return a value
+    /// (typically a map) that is serialized in their place:
+    ///
+    /// ```
+    /// # use minijinja::value::{Value, Object};
+    /// # use std::fmt;
+    /// #[derive(Debug)]
+    /// struct User {
+    ///     id: u64,
+    ///     name: String,
+    /// }
+    ///
+    /// impl fmt::Display for User {
+    ///     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    ///         write!(f, "{}", self.name)
+    ///     }
+    /// }
+    ///
+    /// impl Object for User {
+    ///     fn to_serializable_value(&self) -> Option<Value> {
+    ///         Some(Value::from_iter([
+    ///             ("id", Value::from(self.id)),
+    ///             ("name", Value::from(self.name.as_str())),
+    ///         ]))
+    ///     }
+    /// }
+    /// ```
+    fn to_serializable_value(&self) -> Option<Value> {
+        None
+    }
 }
 
 impl dyn Object {
@@ -171,6 +208,11 @@ pub enum ObjectKind<'a> {
 /// An object holding a sequence of values (tuple, list etc.) can be
 /// represented by this trait.
 ///
+/// The engine assumes that sequences can be iterated over more than once, for
+/// instance to first figure out the length of a loop.  If items are produced
+/// by something that can only be consumed once, they need to be buffered.
+/// [`Value::from_iterator`] does this automatically.
+///
 /// # Simplified Example
 ///
 /// For sequences which do not need any special method behavior, the [`Value`]
@@ -555,6 +597,57 @@ impl<T: SeqObject + 'static> Object for SimpleSeqObject<T> {
     }
 }
 
+/// A sequence that buffers the items of an iterator as they are requested.
+pub struct IteratorSeqObject {
+    state: Mutex<IteratorSeqState>,
+}
+
+struct IteratorSeqState {
+    iter: Option<Box<dyn Iterator<Item = Value> + Send>>,
+    items: Vec<Value>,
+}
+
+impl IteratorSeqState {
+    fn fill(&mut self, count: Option<usize>) {
+        if let Some(ref mut iter) = self.iter {
+            while count.map_or(true, |count| self.items.len() < count) {
+                match iter.next() {
+                    Some(item) => self.items.push(item),
+                    None => {
+                        self.iter = None;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl IteratorSeqObject {
+    pub fn new(iter: Box<dyn Iterator<Item = Value> + Send>) -> IteratorSeqObject {
+        IteratorSeqObject {
+            state: Mutex::new(IteratorSeqState {
+                iter: Some(iter),
+                items: Vec::new(),
+            }),
+        }
+    }
+}
+
+impl SeqObject for IteratorSeqObject {
+    fn get_item(&self, idx: usize) -> Option<Value> {
+        let mut state = self.state.lock().unwrap();
+        state.fill(idx.checked_add(1));
+        state.items.get(idx).cloned()
+    }
+
+    fn item_count(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.fill(None);
+        state.items.len()
+    }
+}
+
 #[repr(transparent)]
 pub struct SimpleStructObject<T>(pub T);
 
@@ -5,7 +5,7 @@ use serde::{ser, Serialize, Serializer};
 use crate::key::{Key, KeySerializer, StaticKey};
 use crate::utils::SerializationFailed;
 use crate::value::{
-    value_map_with_capacity, Arc, MapType, Packed, StringType, Value, ValueMap, ValueRepr,
+    intern, value_map_with_capacity, Arc, MapType, Packed, StringType, Value, ValueMap, ValueRepr,
     VALUE_HANDLES, VALUE_HANDLE_MARKER,
 };
 pub struct ValueSerializer;
@@ -79,7 +79,8 @@ impl Serializer for ValueSerializer {
     }
 
     fn serialize_str(self, value: &str) -> Result<Value, SerializationFailed> {
-        Ok(ValueRepr::String(Arc::new(value.to_owned()), StringType::Normal).into())
+        // short strings are interned like keys if `key_interning` is enabled
+        Ok(ValueRepr::String(intern(value), StringType::Normal).into())
     }
 
     fn serialize_bytes(self, value: &[u8]) -> Result<Value, SerializationFailed> {
@@ -4,13 +4,14 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
 use crate::compiler::instructions::{
-    Instruction, Instructions, LOOP_FLAG_RECURSIVE, LOOP_FLAG_WITH_LOOP_VAR, MAX_LOCALS,
+    Instruction, Instructions, LOOP_FLAG_RECURSIVE, LOOP_FLAG_UNPACK_PAIRS,
+    LOOP_FLAG_WITH_LOOP_VAR, MAX_LOCALS,
 };
 use crate::environment::Environment;
 use crate::error::{Error, ErrorKind};
 use crate::output::{CaptureMode, Output};
 use crate::utils::AutoEscape;
-use crate::value::{self, ops, value_map_with_capacity, MapType, Value, ValueRepr};
+use crate::value::{self, ops, value_map_with_capacity, MapType, Value, ValueKind, ValueRepr};
 use crate::vm::context::{Context, Frame, LoopState, Stack};
 use crate::vm::loop_object::Loop;
 use crate::vm::state::BlockStack;
@@ -22,6 +23,7 @@ use crate::vm::macro_object::{Macro, MacroData};
 use crate::vm::fuel::FuelTracker;
 
 pub use crate::vm::state::State;
+pub use crate::vm::trace::{TraceEvent, TraceKind, TracePhase};
 
 mod context;
 #[cfg(feature = "fuel")]
@@ -30,6 +32,7 @@ mod loop_object;
 #[cfg(feature = "macros")]
 mod macro_object;
 mod state;
+mod trace;
 
 // the cost of a single include against the stack limit.
 #[cfg(feature = "multi_template")]
@@ -54,6 +57,32 @@ fn prepare_blocks<'env, 'vm>(
         .collect()
 }
 
+/// Invokes the trace callback of the environment if there is one.
+#[inline(always)]
+fn trace(state: &State, pc: usize, kind: TraceKind<'_>, phase: TracePhase) {
+    if let Some(ref callback) = state.env.trace_callback {
+        callback(&TraceEvent {
+            kind,
+            phase,
+            template_name: state.instructions.name(),
+            line: state.instructions.get_line(pc).unwrap_or(0),
+        });
+    }
+}
+
+/// Checks if a value is a function or a macro and can stand in for a filter.
+fn is_function(value: &Value) -> bool {
+    #[cfg(feature = "macros")]
+    {
+        if value.downcast_object_ref::<Macro>().is_some() {
+            return true;
+        }
+    }
+    value
+        .downcast_object_ref::<crate::functions::BoxedFunction>()
+        .is_some()
+}
+
 fn get_or_lookup_local<T, F>(vec: &mut [Option<T>], local_id: u8, f: F) -> Option<T>
 where
     T: Copy,
@@ -85,6 +114,7 @@ impl<'env> Vm<'env> {
         out: &mut Output,
         auto_escape: AutoEscape,
     ) -> Result<Option<Value>, Error> {
+        ok!(crate::__context::check_root(&root));
         let _guard = value::value_optimization();
         self.eval_state(
             &mut State {
@@ -105,6 +135,57 @@ impl<'env> Vm<'env> {
         )
     }
 
+    /// Evaluates a single block.
+    ///
+    /// The blocks are given from the template to render to the root of the
+    /// inheritance chain so that `super()` works within the block.
+    #[cfg(feature = "multi_template")]
+    pub fn eval_block<'vm>(
+        &self,
+        name: &str,
+        instructions: &'vm Instructions<'env>,
+        root: Value,
+        blocks: &[&'vm BTreeMap<&'env str, Instructions<'env>>],
+        out: &mut Output,
+        auto_escape: AutoEscape,
+    ) -> Result<Option<Value>, Error> {
+        ok!(crate::__context::check_root(&root));
+        let _guard = value::value_optimization();
+        let mut state = State {
+            env: self.env,
+            ctx: Context::new(Frame::new(root)),
+            current_block: None,
+            current_call: None,
+            auto_escape,
+            instructions,
+            blocks: BTreeMap::new(),
+            loaded_templates: BTreeSet::new(),
+            #[cfg(feature = "macros")]
+            macros: Arc::new(Vec::new()),
+            #[cfg(feature = "fuel")]
+            fuel_tracker: self.env.fuel().map(FuelTracker::new),
+        };
+        for (block_name, instr) in blocks.iter().flat_map(|x| x.iter()) {
+            state
+                .blocks
+                .entry(*block_name)
+                .or_default()
+                .append_instructions(instr);
+        }
+        let (name, block_instructions) = match state.blocks.get_key_value(name) {
+            Some((name, block_stack)) => (*name, block_stack.instructions()),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("block {name:?} does not exist"),
+                ))
+            }
+        };
+        state.current_block = Some(name);
+        state.instructions = block_instructions;
+        self.eval_state(&mut state, out)
+    }
+
     /// Evaluate a macro in a state.
     #[cfg(feature = "macros")]
     pub fn eval_macro(
@@ -354,6 +435,7 @@ impl<'env> Vm<'env> {
                 }
                 Instruction::PopFrame => {
                     if let Some(mut loop_ctx) = state.ctx.pop_frame().current_loop {
+                        trace(state, pc, TraceKind::Loop, TracePhase::Exit);
                         if let Some((target, end_capture)) = loop_ctx.current_recursion_jump.take()
                         {
                             pc = target;
@@ -371,6 +453,7 @@ impl<'env> Vm<'env> {
                 }
                 Instruction::PushLoop(flags) => {
                     a = stack.pop();
+                    trace(state, pc, TraceKind::Loop, TracePhase::Enter);
                     ctx_ok!(self.push_loop(state, a, *flags, pc, next_loop_recursion_jump.take()));
                 }
                 Instruction::Iterate(jump_target) => {
@@ -436,6 +519,7 @@ impl<'env> Vm<'env> {
                         let old_block = state.current_block;
                         state.current_block = Some(name);
                         if let Some(block_stack) = state.blocks.get(name) {
+                            trace(state, pc, TraceKind::Block(name), TracePhase::Enter);
                             let old_instructions =
                                 mem::replace(&mut state.instructions, block_stack.instructions());
                             ctx_ok!(state.ctx.push_frame(Frame::default()));
@@ -443,6 +527,7 @@ impl<'env> Vm<'env> {
                             state.ctx.pop_frame();
                             state.instructions = old_instructions;
                             ctx_ok!(rv);
+                            trace(state, pc, TraceKind::Block(name), TracePhase::Exit);
                         } else {
                             bail!(Error::new(
                                 ErrorKind::InvalidOperation,
@@ -468,20 +553,27 @@ impl<'env> Vm<'env> {
                 }
                 Instruction::ApplyFilter(name, arg_count, local_id) => {
                     state.current_call = Some(name);
-                    let filter =
-                        ctx_ok!(get_or_lookup_local(&mut loaded_filters, *local_id, || {
+                    trace(state, pc, TraceKind::Filter(name), TracePhase::Enter);
+                    let args = stack.slice_top(*arg_count);
+                    if let Some(filter) =
+                        get_or_lookup_local(&mut loaded_filters, *local_id, || {
                             state.env.get_filter(name)
                         })
-                        .ok_or_else(|| {
-                            Error::new(
-                                ErrorKind::UnknownFilter,
-                                format!("filter {name} is unknown"),
-                            )
-                        }));
-                    let args = stack.slice_top(*arg_count);
-                    a = ctx_ok!(filter.apply_to(state, args));
+                    {
+                        a = ctx_ok!(filter.apply_to(state, args));
+                    // functions can be used as filters, the filtered value
+                    // becomes the first argument.
+                    } else if let Some(func) = state.lookup(name).filter(is_function) {
+                        a = ctx_ok!(func.call(state, args));
+                    } else {
+                        bail!(Error::new(
+                            ErrorKind::UnknownFilter,
+                            format!("filter {name} is unknown"),
+                        ));
+                    }
                     stack.drop_top(*arg_count);
                     stack.push(a);
+                    trace(state, pc, TraceKind::Filter(name), TracePhase::Exit);
                     state.current_call = Some(name);
                 }
                 Instruction::PerformTest(name, arg_count, local_id) => {
@@ -521,10 +613,21 @@ impl<'env> Vm<'env> {
                         // leave the one argument on the stack for the recursion
                         recurse_loop!(true);
                     } else if let Some(func) = state.lookup(name) {
+                        trace(state, pc, TraceKind::Function(name), TracePhase::Enter);
                         let args = stack.slice_top(*arg_count);
                         a = ctx_ok!(func.call(state, args));
                         stack.drop_top(*arg_count);
                         stack.push(a);
+                        trace(state, pc, TraceKind::Function(name), TracePhase::Exit);
+                    // filters can be called like functions, the first argument
+                    // is the value to filter.
+                    } else if let Some(filter) = state.env.get_filter(name) {
+                        trace(state, pc, TraceKind::Function(name), TracePhase::Enter);
+                        let args = stack.slice_top(*arg_count);
+                        a = ctx_ok!(filter.apply_to(state, args));
+                        stack.drop_top(*arg_count);
+                        stack.push(a);
+                        trace(state, pc, TraceKind::Function(name), TracePhase::Exit);
                     } else {
                         bail!(Error::new(
                             ErrorKind::UnknownFunction,
@@ -537,7 +640,15 @@ impl<'env> Vm<'env> {
                 Instruction::CallMethod(name, arg_count) => {
                     state.current_call = Some(name);
                     let args = stack.slice_top(*arg_count);
-                    a = ctx_ok!(args[0].call_method(state, name, &args[1..]));
+                    a = ctx_ok!(match args[0].call_method(state, name, &args[1..]) {
+                        Err(err) if err.kind() == ErrorKind::UnknownMethod => {
+                            match state.env.unknown_method_callback {
+                                Some(ref callback) => callback(state, &args[0], name, &args[1..]),
+                                None => Err(err),
+                            }
+                        }
+                        rv => rv,
+                    });
                     stack.drop_top(*arg_count);
                     stack.push(a);
                     state.current_call = None;
@@ -620,9 +731,9 @@ impl<'env> Vm<'env> {
                     continue;
                 }
                 #[cfg(feature = "multi_template")]
-                Instruction::Include(ignore_missing) => {
+                Instruction::Include(ignore_missing, with_context) => {
                     a = stack.pop();
-                    ctx_ok!(self.perform_include(a, state, out, *ignore_missing));
+                    ctx_ok!(self.perform_include(a, state, out, *ignore_missing, *with_context));
                 }
                 #[cfg(feature = "multi_template")]
                 Instruction::ExportLocals => {
@@ -653,6 +764,7 @@ impl<'env> Vm<'env> {
         state: &mut State<'_, 'env>,
         out: &mut Output,
         ignore_missing: bool,
+        with_context: bool,
     ) -> Result<(), Error> {
         use crate::value::SeqObject;
 
@@ -669,7 +781,8 @@ impl<'env> Vm<'env> {
                     "template name was not a string",
                 )
             }));
-            let tmpl = match self.env.get_template(name) {
+            let name = self.env.join_template_path(name, state.name());
+            let tmpl = match self.env.get_compiled_template(&name) {
                 Ok(tmpl) => tmpl,
                 Err(err) => {
                     if err.kind() == ErrorKind::TemplateNotFound {
@@ -680,19 +793,35 @@ impl<'env> Vm<'env> {
                     continue;
                 }
             };
-            let old_escape = mem::replace(&mut state.auto_escape, tmpl.initial_auto_escape());
-            let old_instructions = mem::replace(&mut state.instructions, tmpl.instructions());
-            let old_blocks = mem::replace(&mut state.blocks, prepare_blocks(tmpl.blocks()));
+            let old_escape = mem::replace(
+                &mut state.auto_escape,
+                self.env.get_initial_auto_escape(&name),
+            );
+            let old_instructions = mem::replace(&mut state.instructions, &tmpl.instructions);
+            let old_blocks = mem::replace(&mut state.blocks, prepare_blocks(&tmpl.blocks));
+            // without context the template only sees the globals.  The depth is
+            // carried over so that recursion limits keep working.
+            let old_ctx = if with_context {
+                None
+            } else {
+                let depth = state.ctx.depth();
+                let old_ctx = mem::replace(&mut state.ctx, Context::new(Frame::default()));
+                ok!(state.ctx.incr_depth(depth));
+                Some(old_ctx)
+            };
             ok!(state.ctx.incr_depth(INCLUDE_RECURSION_COST));
             let rv = self.eval_state(state, out);
             state.ctx.decr_depth(INCLUDE_RECURSION_COST);
+            if let Some(old_ctx) = old_ctx {
+                state.ctx = old_ctx;
+            }
             state.auto_escape = old_escape;
             state.instructions = old_instructions;
             state.blocks = old_blocks;
             ok!(rv.map_err(|err| {
                 Error::new(
                     ErrorKind::BadInclude,
-                    format!("error in \"{}\"", tmpl.name()),
+                    format!("error in \"{}\"", tmpl.instructions.name()),
                 )
                 .with_source(err)
             }));
@@ -790,22 +919,23 @@ impl<'env> Vm<'env> {
                 ))
             }
         };
-        if state.loaded_templates.contains(&name) {
+        let name = self.env.join_template_path(name, state.name());
+        if state.loaded_templates.contains(&name as &str) {
             return Err(Error::new(
                 ErrorKind::InvalidOperation,
                 format!("cycle in template inheritance. {name:?} was referenced more than once"),
             ));
         }
-        let tmpl = ok!(self.env.get_template(name));
-        state.loaded_templates.insert(tmpl.instructions().name());
-        for (name, instr) in tmpl.blocks().iter() {
+        let tmpl = ok!(self.env.get_compiled_template(&name));
+        state.loaded_templates.insert(tmpl.instructions.name());
+        for (name, instr) in tmpl.blocks.iter() {
             state
                 .blocks
                 .entry(name)
-                .or_insert_with(BlockStack::default)
+                .or_default()
                 .append_instructions(instr);
         }
-        Ok(tmpl.instructions())
+        Ok(&tmpl.instructions)
     }
 
     fn derive_auto_escape(
@@ -813,7 +943,7 @@ impl<'env> Vm<'env> {
         value: Value,
         initial_auto_escape: AutoEscape,
     ) -> Result<AutoEscape, Error> {
-        match (value.as_str(), value == Value::from(true)) {
+        match (value.as_str(), value == true) {
             (Some("html"), _) => Ok(AutoEscape::Html),
             #[cfg(feature = "json")]
             (Some("json"), _) => Ok(AutoEscape::Json),
@@ -838,6 +968,15 @@ impl<'env> Vm<'env> {
         pc: usize,
         current_recursion_jump: Option<(usize, bool)>,
     ) -> Result<(), Error> {
+        // maps iterate over their keys unless the loop unpacks into two
+        // targets in which case they iterate over `(key, value)` pairs.
+        let iterable = if flags & LOOP_FLAG_UNPACK_PAIRS != 0 && iterable.kind() == ValueKind::Map {
+            ok!(iterable.try_iter_pairs())
+                .map(|(key, value)| Value::from(vec![key, value]))
+                .collect()
+        } else {
+            iterable
+        };
         #[allow(unused_mut)]
         let mut iterator = ok!(iterable.try_iter_owned());
         let len = iterator.len();
@@ -938,9 +1077,13 @@ fn process_err(err: &mut Error, pc: usize, state: &State) {
         }
     }
     // only attach debug info if we don't have one yet and we are in debug mode.
+    // errors from templates compiled on the fly (includes etc.) can come with
+    // debug info which is dropped again if debug mode is off.
     #[cfg(feature = "debug")]
     {
-        if state.env.debug() && err.debug_info().is_none() {
+        if !state.env.debug() {
+            err.discard_debug_info();
+        } else if err.debug_info().is_none() {
             err.attach_debug_info(state.make_debug_info(pc, state.instructions));
         }
     }
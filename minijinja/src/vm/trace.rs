@@ -0,0 +1,58 @@
+/// The kind of operation a [`TraceEvent`] refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TraceKind<'a> {
+    /// A `for` loop (including every level of a recursive loop).
+    Loop,
+    /// A filter with the given name is applied.
+    Filter(&'a str),
+    /// A function (or macro) with the given name is called.
+    Function(&'a str),
+    /// The block with the given name is rendered.
+    Block(&'a str),
+}
+
+/// Indicates if a [`TraceEvent`] marks the start or the end of an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TracePhase {
+    /// The operation is about to start.
+    Enter,
+    /// The operation finished successfully.
+    Exit,
+}
+
+/// An event passed to the trace callback.
+///
+/// See [`Environment::set_trace_callback`](crate::Environment::set_trace_callback)
+/// for more information.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEvent<'a> {
+    pub(crate) kind: TraceKind<'a>,
+    pub(crate) phase: TracePhase,
+    pub(crate) template_name: &'a str,
+    pub(crate) line: usize,
+}
+
+impl<'a> TraceEvent<'a> {
+    /// Returns the kind of operation that is traced.
+    pub fn kind(&self) -> TraceKind<'a> {
+        self.kind
+    }
+
+    /// Returns `Enter` or `Exit` depending on the phase of the operation.
+    pub fn phase(&self) -> TracePhase {
+        self.phase
+    }
+
+    /// Returns the name of the template that performs the operation.
+    pub fn template_name(&self) -> &'a str {
+        self.template_name
+    }
+
+    /// Returns the line of the operation in the template.
+    ///
+    /// This is `0` if the line could not be determined.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
@@ -1,12 +1,12 @@
 #![cfg(feature = "unstable_machinery")]
-use minijinja::machinery::{CodeGenerator, Instruction};
+use minijinja::machinery::{CodeGenerator, Instruction, LOOP_FLAG_WITH_LOOP_VAR};
 use minijinja::value::Value;
 
 #[test]
 fn test_for_loop() {
     let mut c = CodeGenerator::new("<unknown>", "");
     c.add(Instruction::Lookup("items"));
-    c.start_for_loop(true, false);
+    c.start_for_loop(LOOP_FLAG_WITH_LOOP_VAR);
     c.add(Instruction::Emit);
     c.end_for_loop(false);
     c.add(Instruction::EmitRaw("!"));
@@ -3,7 +3,7 @@ use std::collections::BTreeMap;
 use similar_asserts::assert_eq;
 
 use minijinja::value::Value;
-use minijinja::Environment;
+use minijinja::{context, Environment, Error, ErrorKind};
 
 #[test]
 fn test_basic() {
@@ -72,3 +72,767 @@ fn test_template_removal() {
     env.remove_template("test");
     assert!(env.get_template("test").is_err());
 }
+
+#[test]
+fn test_clear_templates() {
+    let mut env = Environment::new();
+    env.add_template("a", "A").unwrap();
+    env.add_template("b", "B").unwrap();
+    assert_eq!(env.template_names().collect::<Vec<_>>(), vec!["a", "b"]);
+    env.clear_templates();
+    assert_eq!(env.template_names().count(), 0);
+    assert!(env.get_template("a").is_err());
+}
+
+#[test]
+fn test_keep_trailing_newline() {
+    let mut env = Environment::new();
+    env.add_template("a", "A\n").unwrap();
+    env.set_keep_trailing_newline(true);
+    env.add_template("b", "B\n\n").unwrap();
+    assert_eq!(env.get_template("a").unwrap().render(()).unwrap(), "A");
+    assert_eq!(env.get_template("b").unwrap().render(()).unwrap(), "B\n\n");
+    env.set_keep_trailing_newline(false);
+    assert_eq!(env.render_str("C\n\n", ()).unwrap(), "C\n");
+}
+
+#[test]
+#[cfg(feature = "macros")]
+fn test_undeclared_variables() {
+    let mut env = Environment::new();
+    env.add_template(
+        "x",
+        "{% set x = foo %}{{ x }}{{ bar.baz.qux }}\
+         {% for item in seq %}{{ item }}{{ loop.index }}{{ other }}{% endfor %}\
+         {% macro m(arg) %}{{ arg }}{{ closure.attr }}{% endmacro %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("x").unwrap();
+    let mut undeclared = tmpl
+        .undeclared_variables(false)
+        .into_iter()
+        .collect::<Vec<_>>();
+    undeclared.sort();
+    assert_eq!(undeclared, vec!["bar", "closure", "foo", "other", "seq"]);
+    let mut undeclared = tmpl
+        .undeclared_variables(true)
+        .into_iter()
+        .collect::<Vec<_>>();
+    undeclared.sort();
+    assert_eq!(
+        undeclared,
+        vec!["bar.baz.qux", "closure.attr", "foo", "other", "seq"]
+    );
+
+    // the value of an assignment is looked up before the target is set
+    env.add_template("y", "{% set x = x ~ '!' %}{% with y = y %}{% endwith %}")
+        .unwrap();
+    let tmpl = env.get_template("y").unwrap();
+    let mut undeclared = tmpl
+        .undeclared_variables(false)
+        .into_iter()
+        .collect::<Vec<_>>();
+    undeclared.sort();
+    assert_eq!(undeclared, vec!["x", "y"]);
+}
+
+#[test]
+#[cfg(feature = "multi_template")]
+fn test_template_blocks() {
+    let mut env = Environment::new();
+    env.add_template(
+        "base",
+        "{% block a %}{% endblock %}{% block b %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template("middle", "{% extends 'base' %}{% block c %}{% endblock %}")
+        .unwrap();
+    env.add_template("child", "{% extends 'middle' %}{% block a %}{% endblock %}")
+        .unwrap();
+    env.add_template("loop", "{% extends 'loop' %}{% block x %}{% endblock %}")
+        .unwrap();
+    env.add_template("missing", "{% extends 'unknown' %}")
+        .unwrap();
+    let tmpl = env.get_template("child").unwrap();
+    assert_eq!(tmpl.blocks().unwrap(), vec!["a", "b", "c"]);
+    let tmpl = env.get_template("loop").unwrap();
+    assert_eq!(tmpl.blocks().unwrap(), vec!["x"]);
+    let tmpl = env.get_template("missing").unwrap();
+    assert!(tmpl.blocks().is_err());
+
+    // conditional and dynamic parents cannot be resolved statically
+    env.add_template(
+        "conditional",
+        "{% if true %}{% extends 'base' %}{% endif %}{% block d %}{% endblock %}",
+    )
+    .unwrap();
+    env.add_template("dynamic", "{% extends layout %}{% block e %}{% endblock %}")
+        .unwrap();
+    let tmpl = env.get_template("conditional").unwrap();
+    assert_eq!(tmpl.blocks().unwrap(), vec!["d"]);
+    let tmpl = env.get_template("dynamic").unwrap();
+    assert_eq!(tmpl.blocks().unwrap(), vec!["e"]);
+}
+
+#[test]
+#[cfg(feature = "multi_template")]
+fn test_render_block() {
+    let mut env = Environment::new();
+    env.add_template(
+        "base",
+        "{% set x = 1 %}<{% block a %}[{{ name }}]{% endblock %}{% block b %}B{% endblock %}>",
+    )
+    .unwrap();
+    env.add_template(
+        "child",
+        "{% extends 'base' %}{% block a %}{{ super() }}{% block c %}C{% endblock %}{% endblock %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("child").unwrap();
+    let ctx = minijinja::context!(name => "x");
+    assert_eq!(tmpl.render_block("a", &ctx).unwrap(), "[x]C");
+    assert_eq!(tmpl.render_block("b", &ctx).unwrap(), "B");
+    assert_eq!(tmpl.render_block("c", &ctx).unwrap(), "C");
+    let err = tmpl.render_block("missing", &ctx).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert!(err.to_string().contains("block \"missing\" does not exist"));
+}
+
+#[test]
+fn test_nested_comments() {
+    let mut env = Environment::new();
+    assert_eq!(env.render_str("a{# {% if %} {{ #}b", ()).unwrap(), "ab");
+    assert_eq!(env.render_str("a{# {# x #} y #}b", ()).unwrap(), "a y #}b");
+    env.set_nested_comments(true);
+    assert!(env.nested_comments());
+    assert_eq!(env.render_str("a{# {# x #} {% y %} #}b", ()).unwrap(), "ab");
+    assert_eq!(env.render_str("a {#- {# x #} -#} b", ()).unwrap(), "ab");
+    assert!(env.render_str("a{# {# x #}b", ()).is_err());
+}
+
+#[test]
+fn test_formatter_delegation() {
+    let mut env = Environment::new();
+    env.set_formatter(|out, state, value| {
+        if value.is_none() {
+            out.write_str("-")?;
+            Ok(())
+        } else {
+            minijinja::escape_formatter(out, state, value)
+        }
+    });
+    let ctx = minijinja::context!(a => "<b>", b => ());
+    assert_eq!(
+        env.render_named_str("x.html", "{{ a }}{{ b }}{{ a|safe }}", &ctx)
+            .unwrap(),
+        "&lt;b&gt;-<b>"
+    );
+    assert_eq!(
+        env.render_named_str("x.txt", "{{ a }}{{ b }}", &ctx)
+            .unwrap(),
+        "<b>-"
+    );
+}
+
+#[test]
+fn test_default_auto_escape_extensions() {
+    use minijinja::AutoEscape;
+
+    let mut env = Environment::new();
+    env.set_default_auto_escape_extensions(&[("html", AutoEscape::Html), ("j2", AutoEscape::Html)]);
+    assert_eq!(
+        env.render_named_str("a.j2", "{{ '<' }}", ()).unwrap(),
+        "&lt;"
+    );
+    assert_eq!(
+        env.render_named_str("a.html", "{{ '<' }}", ()).unwrap(),
+        "&lt;"
+    );
+    assert_eq!(env.render_named_str("a.xml", "{{ '<' }}", ()).unwrap(), "<");
+    assert_eq!(env.render_named_str("html", "{{ '<' }}", ()).unwrap(), "<");
+}
+
+#[test]
+fn test_parse_all_errors() {
+    let env = Environment::new();
+    assert!(env
+        .parse_all_errors("{% for x in y %}{{ x }}{% endfor %}")
+        .is_ok());
+
+    let errors = env
+        .parse_all_errors("{% for x in y %}\n{{ x x }}\n{% endfor %}{{ ) }}\n{{ ok }}{{ oops")
+        .unwrap_err();
+    let errors = errors
+        .iter()
+        .map(|err| (err.kind(), err.name(), err.line()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        errors,
+        [
+            (minijinja::ErrorKind::SyntaxError, Some("<string>"), Some(2)),
+            (minijinja::ErrorKind::SyntaxError, Some("<string>"), Some(3)),
+            (minijinja::ErrorKind::SyntaxError, Some("<string>"), Some(4)),
+        ]
+    );
+
+    // regular parsing still stops at the first error
+    let err = env.render_str("{{ 1 + }}{{ x x }}", ()).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::SyntaxError);
+}
+
+#[test]
+#[cfg(all(feature = "multi_template", feature = "builtins"))]
+fn test_trace_callback() {
+    use minijinja::{TraceKind, TracePhase};
+    use std::sync::{Arc, Mutex};
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let mut env = Environment::new();
+    let collected = events.clone();
+    env.set_trace_callback(move |event| {
+        let kind = match event.kind() {
+            TraceKind::Loop => "loop".to_string(),
+            TraceKind::Filter(name) => format!("filter:{name}"),
+            TraceKind::Function(name) => format!("function:{name}"),
+            TraceKind::Block(name) => format!("block:{name}"),
+            _ => unreachable!(),
+        };
+        let phase = match event.phase() {
+            TracePhase::Enter => "enter",
+            TracePhase::Exit => "exit",
+        };
+        collected.lock().unwrap().push(format!(
+            "{} {} {}:{}",
+            phase,
+            kind,
+            event.template_name(),
+            event.line()
+        ));
+    });
+    env.add_template("layout.html", "{% block body %}{% endblock %}")
+        .unwrap();
+    env.add_template(
+        "page.html",
+        "{% extends 'layout.html' %}{% block body %}\n{% for x in range(2) %}{{ x|upper }}{% endfor %}{% endblock %}",
+    )
+    .unwrap();
+    let rv = env.get_template("page.html").unwrap().render(()).unwrap();
+    assert_eq!(rv, "\n01");
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            "enter block:body layout.html:1",
+            "enter function:range page.html:2",
+            "exit function:range page.html:2",
+            "enter loop page.html:2",
+            "enter filter:upper page.html:2",
+            "exit filter:upper page.html:2",
+            "enter filter:upper page.html:2",
+            "exit filter:upper page.html:2",
+            "exit loop page.html:2",
+            "exit block:body layout.html:1",
+        ]
+    );
+}
+
+#[test]
+fn test_environment_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Environment<'static>>();
+    assert_send_sync::<minijinja::Template<'static>>();
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_json_sort_keys() {
+    let mut env = Environment::new();
+    env.add_template(
+        "x.txt",
+        r#"{{ {"b": 1, "a": {"d": [{"z": 1, "y": 2}], "c": 2} }|tojson(sort_keys=true) }}"#,
+    )
+    .unwrap();
+    env.add_template("y.json", r#"{{ {"b": 1, "a": 2} }}"#)
+        .unwrap();
+    let sorted = r#"{"a":{"c":2,"d":[{"y":2,"z":1}]},"b":1}"#;
+    assert_eq!(
+        env.get_template("x.txt").unwrap().render(()).unwrap(),
+        sorted
+    );
+
+    assert!(!env.json_sort_keys());
+    env.set_json_sort_keys(true);
+    env.add_template("z.txt", r#"{{ {"b": 1, "a": 2}|tojson }}"#)
+        .unwrap();
+    assert_eq!(
+        env.get_template("z.txt").unwrap().render(()).unwrap(),
+        r#"{"a":2,"b":1}"#
+    );
+    assert_eq!(
+        env.get_template("y.json").unwrap().render(()).unwrap(),
+        r#"{"a":2,"b":1}"#
+    );
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_json_pretty() {
+    let mut env = Environment::new();
+    env.add_template(
+        "x.txt",
+        "{{ [1]|tojson }}|{{ [1]|tojson(indent=false) }}|{{ [1]|tojson(1) }}",
+    )
+    .unwrap();
+    env.add_template("y.json", "{{ [1] }}").unwrap();
+    let render = |env: &Environment, name| env.get_template(name).unwrap().render(()).unwrap();
+    assert!(!env.json_pretty());
+    assert_eq!(render(&env, "x.txt"), "[1]|[1]|[\n 1\n]");
+    assert_eq!(render(&env, "y.json"), "[1]");
+
+    env.set_json_pretty(true);
+    assert!(env.json_pretty());
+    assert_eq!(render(&env, "x.txt"), "[\n  1\n]|[1]|[\n 1\n]");
+    assert_eq!(render(&env, "y.json"), "[\n  1\n]");
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_json_floats() {
+    let mut env = Environment::new();
+    let ctx = context!(values => vec![-0.0, 0.0, 1.5, f64::NAN, f64::INFINITY]);
+    assert_eq!(
+        env.render_str("{{ values|tojson }}", &ctx).unwrap(),
+        "[0.0,0.0,1.5,null,null]"
+    );
+    assert_eq!(
+        env.render_named_str("x.json", "{{ values[0] }}", &ctx)
+            .unwrap(),
+        "0.0"
+    );
+    // comparisons are unaffected
+    assert_eq!(
+        env.render_str("{{ values[0] == 0 }}", &ctx).unwrap(),
+        "true"
+    );
+
+    assert!(!env.json_strict_floats());
+    env.set_json_strict_floats(true);
+    assert!(env.json_strict_floats());
+    let err = env.render_str("{{ values|tojson }}", &ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    let err = env
+        .render_named_str("x.json", "{{ values[3] }}", &ctx)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BadSerialization);
+    assert_eq!(
+        env.render_str("{{ values[:3]|tojson }}", &ctx).unwrap(),
+        "[0.0,0.0,1.5]"
+    );
+}
+
+#[test]
+fn test_error_accessors() {
+    let mut env = Environment::new();
+    let source = "Hello\n{{ foo + }}";
+    let err = env.add_template("hello.txt", source).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::SyntaxError);
+    assert_eq!(err.detail(), Some("unexpected end of variable block"));
+    assert_eq!(err.name(), Some("hello.txt"));
+    assert_eq!(err.line(), Some(2));
+    let range = err.range().unwrap();
+    assert_eq!(&source[range], "}}");
+
+    let err = env
+        .render_str("{% for x in 42 %}{% endfor %}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(err.detail(), Some("number is not iterable"));
+    assert_eq!(err.line(), Some(1));
+}
+
+#[test]
+#[cfg(feature = "multi_template")]
+fn test_template_from_named_str() {
+    let mut env = Environment::new();
+    env.add_template("layout.html", "<{% block body %}{% endblock %}>")
+        .unwrap();
+
+    // the source only has to outlive the template, not the environment
+    let source = String::from("{% extends 'layout.html' %}{% block body %}{{ x }}{% endblock %}");
+    let tmpl = env.template_from_named_str("page.html", &source).unwrap();
+    assert_eq!(tmpl.name(), "page.html");
+    assert_eq!(tmpl.source(), source);
+    assert_eq!(tmpl.render(context! { x => "<a>" }).unwrap(), "<&lt;a&gt;>");
+    assert!(env.get_template("page.html").is_err());
+    let copy = tmpl.clone();
+    drop(tmpl);
+    assert_eq!(copy.render(context! { x => 1 }).unwrap(), "<1>");
+
+    // handles borrowed from the owned template are copyable like all others
+    let handle = copy.as_template();
+    let handle_copy = handle;
+    assert_eq!(handle.name(), handle_copy.name());
+    assert_eq!(handle.blocks().unwrap(), vec!["body"]);
+
+    let tmpl = env.template_from_str("{{ 1 + 1 }}").unwrap();
+    assert_eq!(tmpl.name(), "<string>");
+    assert_eq!(tmpl.render(()).unwrap(), "2");
+
+    let err = env.template_from_named_str("bad.txt", "{{ }}").unwrap_err();
+    assert_eq!(err.name(), Some("bad.txt"));
+}
+
+#[test]
+#[cfg(feature = "source")]
+fn test_add_template_owned() {
+    fn load(env: &mut Environment<'static>, name: &str) {
+        let source = format!("[{name}]{{% block x %}}{{% endblock %}}");
+        env.add_template_owned(name.to_string(), source).unwrap();
+    }
+
+    let mut env = Environment::new();
+    env.add_template(
+        "static.txt",
+        "{% extends 'owned.txt' %}{% block x %}!{% endblock %}",
+    )
+    .unwrap();
+    load(&mut env, "owned.txt");
+    assert_eq!(
+        env.get_template("static.txt").unwrap().render(()).unwrap(),
+        "[owned.txt]!"
+    );
+    assert_eq!(
+        env.template_names().collect::<Vec<_>>(),
+        vec!["owned.txt", "static.txt"]
+    );
+
+    let err = env.add_template_owned("bad.txt", "{{").unwrap_err();
+    assert_eq!(err.name(), Some("bad.txt"));
+    env.remove_template("owned.txt");
+    assert!(env.get_template("owned.txt").is_err());
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_float_format() {
+    use minijinja::FloatFormat;
+
+    let mut env = Environment::new();
+    let tmpl = "{{ a }}|{{ b }}|{{ a|string }}|{{ c }}";
+    let ctx = context!(a => 5.0, b => 0.1, c => 42);
+    assert_eq!(env.render_str(tmpl, &ctx).unwrap(), "5.0|0.1|5.0|42");
+
+    env.set_float_format(FloatFormat::Minimal);
+    assert_eq!(env.float_format(), FloatFormat::Minimal);
+    assert_eq!(env.render_str(tmpl, &ctx).unwrap(), "5|0.1|5|42");
+
+    env.set_float_format(FloatFormat::Fixed(2));
+    assert_eq!(env.render_str(tmpl, &ctx).unwrap(), "5.00|0.10|5.00|42");
+    assert_eq!(
+        env.render_named_str("x.html", "{{ a }}", &ctx).unwrap(),
+        "5.00"
+    );
+}
+
+#[test]
+#[cfg(feature = "builtins")]
+fn test_non_finite_format() {
+    use minijinja::NonFiniteFormat;
+
+    let mut env = Environment::new();
+    let tmpl = "{{ a }}|{{ b }}|{{ -b }}|{{ a|string }}|{{ c }}";
+    let ctx = context!(a => f64::NAN, b => f64::INFINITY, c => 1.5);
+    assert_eq!(env.render_str(tmpl, &ctx).unwrap(), "NaN|inf|-inf|NaN|1.5");
+    assert_eq!(env.non_finite_format(), NonFiniteFormat::default());
+
+    env.set_non_finite_format(NonFiniteFormat::Tokens {
+        nan: "n/a",
+        inf: "Infinity",
+    });
+    assert_eq!(
+        env.render_str(tmpl, &ctx).unwrap(),
+        "n/a|Infinity|-Infinity|n/a|1.5"
+    );
+
+    env.set_non_finite_format(NonFiniteFormat::Error);
+    for tmpl in ["{{ a }}", "{{ b }}", "{{ a|string }}"] {
+        let err = env.render_str(tmpl, &ctx).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    }
+    assert_eq!(env.render_str("{{ c }}", &ctx).unwrap(), "1.5");
+
+    // comparisons with NaN are never true but do not fail
+    assert_eq!(
+        env.render_str("{{ a < c }}|{{ a > c }}|{{ a == a }}", &ctx)
+            .unwrap(),
+        "false|false|false"
+    );
+}
+
+#[test]
+fn test_unknown_method_callback() {
+    let mut env = Environment::new();
+    let tmpl = "{{ 'hello'.shout() }}";
+    let err = env.render_str(tmpl, ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownMethod);
+
+    env.set_unknown_method_callback(|_state, value, name, args| match (value.as_str(), name) {
+        (Some(s), "shout") => Ok(Value::from(format!("{}{}", s.to_uppercase(), args.len()))),
+        _ => Err(Error::new(ErrorKind::UnknownMethod, name.to_string())),
+    });
+    assert_eq!(env.render_str(tmpl, ()).unwrap(), "HELLO0");
+    assert_eq!(env.render_str("{{ 'hi'.shout(1, 2) }}", ()).unwrap(), "HI2");
+    // methods that resolve on the value itself do not invoke the callback
+    assert_eq!(
+        env.render_str(
+            "{% for x in [1, 2] %}{{ loop.cycle('a', 'b') }}{% endfor %}",
+            ()
+        )
+        .unwrap(),
+        "ab"
+    );
+    let err = env.render_str("{{ (42).missing() }}", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownMethod);
+}
+
+#[test]
+#[cfg(feature = "debug")]
+fn test_runtime_debug_toggle() {
+    let mut env = Environment::new();
+    env.set_debug(false);
+    let err = env.add_template("broken.txt", "{{ 1 + }}").unwrap_err();
+    assert_eq!(
+        format!("{:#}", err),
+        "syntax error: unexpected end of variable block (in broken.txt:1)"
+    );
+    env.add_template("fail.txt", "{{ 1 + x }}").unwrap();
+    let err = env
+        .get_template("fail.txt")
+        .unwrap()
+        .render(context!(x => "a"))
+        .unwrap_err();
+    assert!(!format!("{:#}", err).contains("{{ 1 + x }}"));
+    let err = env.compile_expression("1 +").unwrap_err();
+    assert!(!format!("{:#}", err).contains("1 +\n"));
+
+    env.set_debug(true);
+    let err = env.add_template("broken.txt", "{{ 1 + }}").unwrap_err();
+    assert!(format!("{:#}", err).contains("{{ 1 + }}"));
+    let err = env
+        .get_template("fail.txt")
+        .unwrap()
+        .render(context!(x => "a"))
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("{{ 1 + x }}"));
+}
+
+#[test]
+fn test_try_add_filter() {
+    let mut env = Environment::new();
+    assert!(!env.has_filter("double"));
+    env.try_add_filter("double", |x: i64| x * 2).unwrap();
+    assert!(env.has_filter("double"));
+    let err = env.try_add_filter("double", |x: i64| x * 3).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert_eq!(env.render_str("{{ 21|double }}", ()).unwrap(), "42");
+
+    // built-in filters count as existing
+    assert_eq!(env.has_filter("upper"), cfg!(feature = "builtins"));
+    if cfg!(feature = "builtins") {
+        assert!(env.try_add_filter("upper", |x: String| x).is_err());
+    }
+
+    // add_filter keeps overwriting
+    env.add_filter("double", |x: i64| x * 3);
+    assert_eq!(env.render_str("{{ 21|double }}", ()).unwrap(), "63");
+}
+
+#[test]
+fn test_registry_introspection() {
+    let mut env = Environment::empty();
+    assert_eq!(env.filters().count(), 0);
+    assert_eq!(env.tests().count(), 0);
+    assert_eq!(env.functions().count(), 0);
+
+    env.add_filter("shout", |x: String| x.to_uppercase());
+    env.add_filter("double", |x: i64| x * 2);
+    env.add_test("big", |x: i64| x > 100);
+    env.add_function("hello", || "Hello!");
+    env.add_global("answer", 42);
+    assert_eq!(env.filters().collect::<Vec<_>>(), ["double", "shout"]);
+    assert_eq!(env.tests().collect::<Vec<_>>(), ["big"]);
+    assert_eq!(env.functions().collect::<Vec<_>>(), ["hello"]);
+
+    let env = Environment::new();
+    assert_eq!(
+        env.filters().any(|x| x == "upper"),
+        cfg!(feature = "builtins")
+    );
+    assert_eq!(env.tests().any(|x| x == "odd"), cfg!(feature = "builtins"));
+    assert_eq!(
+        env.functions().any(|x| x == "range"),
+        cfg!(feature = "builtins")
+    );
+}
+
+#[test]
+fn test_context_functions() {
+    use minijinja::value::Value;
+
+    let env = Environment::new();
+    let render = |prefix: &'static str| {
+        let url_for = Value::from_function(move |page: String| format!("{prefix}{page}"));
+        env.render_str(
+            "{{ url_for('a') }} {{ helpers.url_for('b') }}",
+            context!(url_for => url_for.clone(), helpers => context!(url_for)),
+        )
+        .unwrap()
+    };
+    assert_eq!(render("/en/"), "/en/a /en/b");
+    assert_eq!(render("/de/"), "/de/a /de/b");
+
+    // context functions shadow globals of the same name for that render only
+    let mut env = Environment::new();
+    env.add_function("greet", || "global");
+    let greet = Value::from_function(|| "local");
+    assert_eq!(
+        env.render_str("{{ greet() }}", context!(greet)).unwrap(),
+        "local"
+    );
+    assert_eq!(env.render_str("{{ greet() }}", ()).unwrap(), "global");
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_json_objects() {
+    use minijinja::value::{Object, StructObject, Value};
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct Opaque;
+
+    impl fmt::Display for Opaque {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "opaque")
+        }
+    }
+
+    impl Object for Opaque {}
+
+    #[derive(Debug)]
+    struct User;
+
+    impl fmt::Display for User {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "user")
+        }
+    }
+
+    impl Object for User {
+        fn to_serializable_value(&self) -> Option<Value> {
+            Some(Value::from_iter([("name", "Peter")]))
+        }
+    }
+
+    struct Point;
+
+    impl StructObject for Point {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            match name {
+                "x" => Some(Value::from(1)),
+                "y" => Some(Value::from(2)),
+                _ => None,
+            }
+        }
+
+        fn static_fields(&self) -> Option<&'static [&'static str]> {
+            Some(&["x", "y"])
+        }
+    }
+
+    let env = Environment::new();
+    let ctx = context! {
+        opaque => Value::from_object(Opaque),
+        user => Value::from_object(User),
+        point => Value::from_struct_object(Point),
+    };
+    assert_eq!(
+        env.render_str("{{ [user, point]|tojson }}", &ctx).unwrap(),
+        r#"[{"name":"Peter"},{"x":1,"y":2}]"#
+    );
+    assert_eq!(
+        serde_json::to_string(&Value::from_object(User)).unwrap(),
+        r#"{"name":"Peter"}"#
+    );
+
+    let err = env.render_str("{{ opaque|tojson }}", &ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    let source = std::error::Error::source(&err).unwrap().to_string();
+    assert!(source.contains("Opaque"), "{source}");
+    assert!(source.contains("cannot be serialized"), "{source}");
+}
+
+#[test]
+#[cfg(feature = "source")]
+fn test_compiled_cache() {
+    use minijinja::{CachedTemplate, CompiledCache, LruCompiledCache};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct CountingCache {
+        inner: LruCompiledCache,
+        misses: AtomicUsize,
+    }
+
+    impl CompiledCache for CountingCache {
+        fn get(&self, key: u64) -> Option<CachedTemplate> {
+            let rv = self.inner.get(key);
+            if rv.is_none() {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+            }
+            rv
+        }
+
+        fn insert(&self, key: u64, template: CachedTemplate) {
+            self.inner.insert(key, template)
+        }
+    }
+
+    let cache = Arc::new(CountingCache {
+        inner: LruCompiledCache::new(2),
+        misses: AtomicUsize::new(0),
+    });
+    for idx in 0..3 {
+        let mut env = Environment::new();
+        env.set_compiled_cache(cache.clone());
+        assert_eq!(
+            env.render_str("{{ x }}!", context!(x => idx)).unwrap(),
+            format!("{idx}!")
+        );
+        let tmpl = env.template_from_named_str("a", "{{ x }}?").unwrap();
+        assert_eq!(tmpl.name(), "a");
+        assert_eq!(tmpl.render(context!(x => idx)).unwrap(), format!("{idx}?"));
+    }
+    assert_eq!(cache.misses.load(Ordering::Relaxed), 2);
+    assert_eq!(cache.inner.len(), 2);
+
+    // the syntax settings are part of the key
+    let mut env = Environment::new();
+    env.set_compiled_cache(cache.clone());
+    env.set_keep_trailing_newline(true);
+    assert_eq!(
+        env.render_str("{{ x }}!\n", context!(x => 1)).unwrap(),
+        "1!\n"
+    );
+    assert_eq!(cache.misses.load(Ordering::Relaxed), 3);
+
+    // the least recently used template was evicted
+    assert_eq!(cache.inner.len(), 2);
+    env.set_keep_trailing_newline(false);
+    assert_eq!(env.render_str("{{ x }}!", context!(x => 1)).unwrap(), "1!");
+    assert_eq!(cache.misses.load(Ordering::Relaxed), 4);
+
+    // errors are not cached
+    let err = env.render_str("{{ x", ()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::SyntaxError);
+    assert_eq!(cache.inner.len(), 2);
+}
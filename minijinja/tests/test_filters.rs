@@ -48,3 +48,99 @@ fn test_indent_with_all_indented() {
         String::from("  test\n  test1\n  \n  test2")
     );
 }
+
+#[test]
+fn test_pprint_cycle() {
+    use std::fmt;
+    use std::sync::{Arc, Mutex, Weak};
+
+    use minijinja::value::{Object, ObjectKind, StructObject, Value};
+
+    #[derive(Debug)]
+    struct Node(Mutex<Weak<Node>>);
+
+    impl fmt::Display for Node {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "node")
+        }
+    }
+
+    impl Object for Node {
+        fn kind(&self) -> ObjectKind<'_> {
+            ObjectKind::Struct(self)
+        }
+    }
+
+    impl StructObject for Node {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            match name {
+                "me" => self.0.lock().unwrap().upgrade().map(Value::from),
+                _ => None,
+            }
+        }
+
+        fn static_fields(&self) -> Option<&'static [&'static str]> {
+            Some(&["me"][..])
+        }
+    }
+
+    let node = Arc::new(Node(Mutex::new(Weak::new())));
+    *node.0.lock().unwrap() = Arc::downgrade(&node);
+    let value = Value::from(node.clone());
+    let rv = minijinja::filters::pprint(&value, None).unwrap();
+    assert_eq!(rv, "{\n    \"me\": <cycle>,\n}");
+
+    // the same object appearing twice is not a cycle
+    let value = Value::from(vec![Value::from(node.clone()), Value::from(node)]);
+    let env = minijinja::Environment::new();
+    let rv = env
+        .render_str(
+            "{{ value|pprint(max_depth=2) }}",
+            minijinja::context!(value),
+        )
+        .unwrap();
+    assert_eq!(
+        rv,
+        "[\n    {\n        \"me\": ...,\n    },\n    {\n        \"me\": ...,\n    },\n]"
+    );
+}
+
+#[test]
+fn test_flatten_is_lazy() {
+    use minijinja::filters::flatten;
+    use minijinja::value::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let produced = Arc::new(AtomicUsize::new(0));
+    let counter = produced.clone();
+    let inner = Value::from_iterator((0..1000).map(move |x| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        x
+    }));
+    let rv = flatten(Value::from(vec![Value::from(vec![inner])]), None, None).unwrap();
+    assert_eq!(rv.as_seq().unwrap().get_item(0), Some(Value::from(0)));
+    assert_eq!(produced.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_sort_nan() {
+    use minijinja::{context, Environment};
+
+    let env = Environment::new();
+    let ctx = context!(values => vec![3.0, f64::NAN, 1.0, f64::NAN, 2.0]);
+    assert_eq!(
+        env.render_str("{{ values|sort|join(',') }}", &ctx).unwrap(),
+        "1.0,2.0,3.0,NaN,NaN"
+    );
+    assert_eq!(
+        env.render_str("{{ values|sort(reverse=true)|join(',') }}", &ctx)
+            .unwrap(),
+        "NaN,NaN,3.0,2.0,1.0"
+    );
+    assert_eq!(
+        env.render_str("{{ values|min }}|{{ values|max }}", &ctx)
+            .unwrap(),
+        "1.0|NaN"
+    );
+}
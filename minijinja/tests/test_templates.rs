@@ -90,6 +90,30 @@ fn test_custom_filter() {
     assert_eq!(rv, "[42]");
 }
 
+#[test]
+fn test_state_aware_filter() {
+    let mut env = Environment::new();
+    env.add_filter("url", |state: &State, path: String| -> String {
+        let base = state
+            .lookup("base_url")
+            .map(|x| x.to_string())
+            .unwrap_or_default();
+        format!(
+            "{}/{}?from={}&escape={:?}",
+            base,
+            path.trim_start_matches('/'),
+            state.name(),
+            state.auto_escape()
+        )
+    });
+    env.add_template("page.txt", "{{ '/about'|url }}").unwrap();
+    let tmpl = env.get_template("page.txt").unwrap();
+    let rv = tmpl
+        .render(context!(base_url => "https://example.com"))
+        .unwrap();
+    assert_eq!(rv, "https://example.com/about?from=page.txt&escape=None");
+}
+
 #[test]
 fn test_items_and_dictsort_with_structs() {
     struct MyStruct;
@@ -142,6 +166,28 @@ fn test_urlencode_with_struct() {
     );
 }
 
+#[test]
+#[cfg(feature = "toml")]
+fn test_tomlencode() {
+    insta::assert_snapshot!(
+        minijinja::render!(
+            "{{ x|tomlencode }}",
+            x => context! { a => context! { b => 1 }, c => [1, 2], d => "x\"y" }
+        ),
+        @r###"
+    c = [1, 2]
+    d = "x\"y"
+
+    [a]
+    b = 1
+    "###
+    );
+    let err = Environment::new()
+        .render_str("{{ none|tomlencode }}", ())
+        .unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::BadSerialization);
+}
+
 #[test]
 fn test_single() {
     let mut env = Environment::new();
@@ -216,7 +262,7 @@ fn test_auto_escaping() {
         let rv = tmpl
             .render(context!(var => [Value::from(true), Value::from("<foo>"), Value::from(())]))
             .unwrap();
-        insta::assert_snapshot!(rv, @r###"[true,"<foo>",null]"###);
+        insta::assert_snapshot!(rv, @r###"[true,"\u003cfoo\u003e",null]"###);
     }
 
     // Text
@@ -225,6 +271,26 @@ fn test_auto_escaping() {
     insta::assert_snapshot!(rv, @r###"foo"bar'baz"###);
 }
 
+#[test]
+fn test_string_filters_preserve_safe() {
+    let env = Environment::new();
+    let ctx = context! {
+        markup => minijinja::value::Value::from_safe_string(" <b>Hi</b> ".into()),
+        plain => "<b>hi</b>",
+    };
+    let rv = env
+        .render_named_str(
+            "x.html",
+            "{{ markup|string|upper }}|{{ markup|lower|trim }}|{{ markup|title }}|{{ plain|string|upper }}|{{ 42|string }}",
+            &ctx,
+        )
+        .unwrap();
+    assert_eq!(
+        rv,
+        " <B>HI</B> |<b>hi</b>| <B>Hi</B> |&lt;B&gt;HI&lt;&#x2f;B&gt;|42"
+    );
+}
+
 #[test]
 fn test_loop_changed() {
     let rv = minijinja::render!(
@@ -338,3 +404,298 @@ fn test_current_call_state() {
     "#
     );
 }
+
+#[test]
+fn test_length_filter() {
+    let env = Environment::new();
+    let ctx = context! {
+        map => context! { a => 1, b => 2 },
+        emoji => "👋🌍",
+        combining => "e\u{301}",
+        umlauts => "äöü",
+    };
+    let rv = env
+        .render_str(
+            "{{ map|length }} {{ emoji|length }} {{ combining|count }} {{ umlauts|length }} {{ range(3)|length }}",
+            &ctx,
+        )
+        .unwrap();
+    // strings count unicode chars, not bytes or grapheme clusters
+    assert_eq!(rv, "2 2 2 3 3");
+
+    for source in ["{{ 42|length }}", "{{ true|length }}", "{{ none|length }}"] {
+        let err = env.render_str(source, ()).unwrap_err();
+        assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+        assert!(err.to_string().contains("cannot calculate length"));
+    }
+}
+
+#[test]
+#[cfg(feature = "number_format")]
+fn test_number_format() {
+    let env = Environment::new();
+    let render = |source: &str| env.render_str(source, ()).unwrap();
+
+    assert_eq!(render("{{ 1234567.891|numberformat }}"), "1,234,567.89");
+    assert_eq!(
+        render("{{ 1234567|numberformat(decimals=0) }}"),
+        "1,234,567"
+    );
+    assert_eq!(render("{{ 999|numberformat }}"), "999.00");
+    assert_eq!(
+        render("{{ 1234567.891|numberformat(thousands='.', decimal_point=',') }}"),
+        "1.234.567,89"
+    );
+    assert_eq!(render("{{ -1234.5|numberformat }}"), "-1,234.50");
+    assert_eq!(
+        render("{{ -42|numberformat(negative='parens') }}"),
+        "(42.00)"
+    );
+    assert_eq!(render("{{ -0.001|numberformat }}"), "0.00");
+
+    assert_eq!(render("{{ 1234.5|currencyformat }}"), "$1,234.50");
+    assert_eq!(render("{{ 1234.6|currencyformat(code='jpy') }}"), "¥1,235");
+    assert_eq!(render("{{ 10|currencyformat(code='CHF') }}"), "CHF 10.00");
+    assert_eq!(
+        render("{{ 1234.5|currencyformat(code='EUR', thousands='.', decimal_point=',', symbol_after=true) }}"),
+        "1.234,50 €"
+    );
+    assert_eq!(
+        render("{{ -5|currencyformat(negative='parens') }}"),
+        "($5.00)"
+    );
+
+    let err = env.render_str("{{ 'x'|numberformat }}", ()).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    let err = env.render_str("{{ 1|numberformat(2) }}", ()).unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+}
+
+#[test]
+fn test_debug_function_with_args() {
+    let env = Environment::new();
+    let rv = env
+        .render_str("{{ debug(x) }}|{{ debug(x, 'y') }}", context! { x => 42 })
+        .unwrap();
+    assert_eq!(rv, "42|[\n    42,\n    \"y\",\n]");
+
+    let rv = env
+        .render_str("{% for item in [1] %}{{ debug() }}{% endfor %}", ())
+        .unwrap();
+    assert!(rv.contains("loop"));
+    assert!(rv.contains("filters"));
+    assert!(rv.contains("tests"));
+}
+
+#[test]
+fn test_render_into() {
+    let mut env = Environment::new();
+    env.add_template("hello", "Hello {{ name }}!").unwrap();
+    let tmpl = env.get_template("hello").unwrap();
+
+    let mut buf = String::from(">");
+    tmpl.render_into(context!(name => "John"), &mut buf)
+        .unwrap();
+    tmpl.render_into(context!(name => "Jane"), &mut buf)
+        .unwrap();
+    assert_eq!(buf, ">Hello John!Hello Jane!");
+
+    buf.clear();
+    tmpl.render_into(context!(name => "Peter"), &mut buf)
+        .unwrap();
+    assert_eq!(buf, "Hello Peter!");
+}
+
+#[test]
+fn test_render_or() {
+    let mut env = Environment::new();
+    env.add_template("page", "{{ 42|length }}").unwrap();
+    env.add_template("ok", "Hello {{ name }}!").unwrap();
+    env.add_template("error", "Error: {{ reason }}").unwrap();
+    env.add_template("broken", "{{ missing() }}").unwrap();
+    let fallback = env.get_template("error").unwrap();
+
+    let tmpl = env.get_template("ok").unwrap();
+    let (rv, err) = tmpl
+        .render_or(context!(name => "John"), &fallback, ())
+        .unwrap();
+    assert_eq!(rv, "Hello John!");
+    assert!(err.is_none());
+
+    let tmpl = env.get_template("page").unwrap();
+    let (rv, err) = tmpl
+        .render_or((), &fallback, context!(reason => "oops"))
+        .unwrap();
+    assert_eq!(rv, "Error: oops");
+    assert_eq!(err.unwrap().name(), Some("page"));
+
+    let broken = env.get_template("broken").unwrap();
+    let err = tmpl.render_or((), &broken, ()).unwrap_err();
+    assert_eq!(err.name(), Some("broken"));
+}
+
+#[test]
+#[cfg(feature = "multi_template")]
+fn test_path_join_callback() {
+    let mut env = Environment::new();
+    env.set_path_join_callback(|parent, name| match name.strip_prefix("./") {
+        Some(rest) => match parent.rsplit_once('/') {
+            Some((dir, _)) => format!("{dir}/{rest}"),
+            None => rest.to_string(),
+        },
+        None => name.to_string(),
+    });
+    env.add_template("layout.html", "[{% block body %}{% endblock %}]")
+        .unwrap();
+    env.add_template(
+        "pages/index.html",
+        "{% extends 'layout.html' %}{% block body %}\
+         {% include './item.html' %}|{% from './macros.html' import m %}{{ m() }}\
+         {% endblock %}",
+    )
+    .unwrap();
+    env.add_template("pages/item.html", "item").unwrap();
+    env.add_template("pages/macros.html", "{% macro m() %}macro{% endmacro %}")
+        .unwrap();
+    env.add_template("item.html", "wrong item").unwrap();
+
+    let tmpl = env.get_template("pages/index.html").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "[item|macro]");
+
+    env.add_template(
+        "pages/layout.html",
+        "<{% block title %}{% endblock %}{% block body %}{% endblock %}>",
+    )
+    .unwrap();
+    env.add_template(
+        "pages/about.html",
+        "{% extends './layout.html' %}{% block body %}about{% endblock %}",
+    )
+    .unwrap();
+    let tmpl = env.get_template("pages/about.html").unwrap();
+    assert_eq!(tmpl.render(()).unwrap(), "<about>");
+    assert_eq!(tmpl.blocks().unwrap(), vec!["body", "title"]);
+    assert_eq!(tmpl.render_block("body", ()).unwrap(), "about");
+}
+
+#[test]
+fn test_invalid_map_keys() {
+    let env = Environment::new();
+    for (source, kind) in [
+        ("{{ {[1, 2]: 3} }}", "sequence"),
+        ("{{ {x: 1} }}", "map"),
+        ("{{ {none: 1} }}", "none"),
+        ("{{ {1.5: 1} }}", "number"),
+    ] {
+        let err = env
+            .render_str(source, context! { x => context! { a => 1 } })
+            .unwrap_err();
+        assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+        assert_eq!(
+            err.detail(),
+            Some(format!("cannot use {kind} as map key").as_str())
+        );
+        assert_eq!(err.line(), Some(1));
+    }
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn test_matching_test() {
+    let env = Environment::new();
+    let rv = env
+        .render_str(
+            r#"{{ "/api/v1" is matching "^/api/v\\d+$" }} {{ "/static" is matching "^/api" }} {{ 42 is matching "4" }}"#,
+            (),
+        )
+        .unwrap();
+    assert_eq!(rv, "true false false");
+
+    let err = env
+        .render_str(r#"{{ "x" is matching "(" }}"#, ())
+        .unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(err.detail(), Some("invalid regular expression \"(\""));
+}
+
+#[test]
+#[cfg(feature = "regex")]
+fn test_regex_filters() {
+    let env = Environment::new();
+    let render = |source: &str| env.render_str(source, ()).unwrap();
+
+    assert_eq!(
+        render(r#"{{ "2023-01-15"|regex_replace("(\\d+)-(\\d+)-(\\d+)", "$3.$2.$1") }}"#),
+        "15.01.2023"
+    );
+    assert_eq!(render(r#"{{ "a a a"|regex_replace("a", "b") }}"#), "b b b");
+    assert_eq!(
+        render(r#"{{ "a a a"|regex_replace("a", "b", 2) }}"#),
+        "b b a"
+    );
+    assert_eq!(
+        render(r#"{{ "a a a"|regex_replace("a", "b", count=1) }}"#),
+        "b a a"
+    );
+    assert_eq!(
+        render(r#"{{ "release v1.2.3"|regex_search("v[\\d.]+") }}"#),
+        "v1.2.3"
+    );
+    assert_eq!(
+        render(r#"{{ "release"|regex_search("\\d") is none }}"#),
+        "true"
+    );
+    assert_eq!(
+        render(r#"{{ "a=1, b=2"|regex_findall("\\d") }}"#),
+        r#"["1", "2"]"#
+    );
+    assert_eq!(
+        render(r#"{{ "a=1, b=2"|regex_findall("(\\w)=\\d") }}"#),
+        r#"["a", "b"]"#
+    );
+    assert_eq!(
+        render(r#"{{ "a=1, b=2"|regex_findall("(\\w)=(\\d)") }}"#),
+        r#"[["a", "1"], ["b", "2"]]"#
+    );
+
+    let err = env
+        .render_str(r#"{{ "x"|regex_replace("[", "") }}"#, ())
+        .unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(err.detail(), Some("invalid regular expression \"[\""));
+}
+
+#[test]
+#[cfg(feature = "base64")]
+fn test_base64_filters() {
+    let env = Environment::new();
+    let render = |source: &str| env.render_str(source, ()).unwrap();
+
+    assert_eq!(
+        render(r#"{{ "hello world"|b64encode }}"#),
+        "aGVsbG8gd29ybGQ="
+    );
+    assert_eq!(render(r#"{{ "n?>~"|b64encode }}"#), "bj8+fg==");
+    assert_eq!(
+        render(r#"{{ "n?>~"|b64encode(urlsafe=true) }}"#),
+        "bj8-fg=="
+    );
+    assert_eq!(
+        render(r#"{{ "aGVsbG8gd29ybGQ="|b64decode(string=true) }}"#),
+        "hello world"
+    );
+    assert_eq!(
+        render(r#"{{ "bj8-fg"|b64decode(urlsafe=true, string=true) }}"#),
+        "n?>~"
+    );
+    assert_eq!(
+        render(r#"{{ "aGk="|b64decode is string }}|{{ "aGk="|b64decode|length }}"#),
+        "false|2"
+    );
+
+    let err = env
+        .render_str(r#"{{ "not base64!"|b64decode }}"#, ())
+        .unwrap_err();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert_eq!(err.detail(), Some("invalid base64 data"));
+}
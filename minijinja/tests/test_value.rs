@@ -1,4 +1,5 @@
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt;
 use std::sync::Arc;
 
@@ -76,6 +77,50 @@ fn test_value_by_index() {
     assert!(val.get_item_by_index(4).unwrap().is_undefined());
 }
 
+#[test]
+fn test_value_attr_and_item_lookup() {
+    let val = minijinja::context! {
+        foo => minijinja::context! { bar => 42 },
+        seq => vec![1, 2, 3],
+    };
+    assert_eq!(
+        val.get_attr("foo").unwrap().get_attr("bar").unwrap(),
+        Value::from(42)
+    );
+    assert_eq!(
+        val.get_item(&Value::from("seq"))
+            .unwrap()
+            .get_item(&Value::from(-1))
+            .unwrap(),
+        Value::from(3)
+    );
+    assert!(val.get_attr("missing").unwrap().is_undefined());
+    assert!(val.get_item(&Value::from(0)).unwrap().is_undefined());
+    assert_eq!(
+        Value::UNDEFINED.get_attr("foo").unwrap_err().kind(),
+        minijinja::ErrorKind::UndefinedError
+    );
+    assert_eq!(
+        Value::UNDEFINED.get_item_by_index(0).unwrap_err().kind(),
+        minijinja::ErrorKind::UndefinedError
+    );
+}
+
+#[test]
+fn test_value_get_path() {
+    let val = minijinja::context! {
+        address => minijinja::context! { city => "Vienna" },
+        items => vec![minijinja::context! { name => "a" }],
+    };
+    assert_eq!(val.get_path("address.city").unwrap(), Value::from("Vienna"));
+    assert_eq!(val.get_path("items.0.name").unwrap(), Value::from("a"));
+    assert!(val.get_path("items.1").unwrap().is_undefined());
+    assert_eq!(
+        val.get_path("missing.city").unwrap_err().kind(),
+        minijinja::ErrorKind::UndefinedError
+    );
+}
+
 #[test]
 fn test_map_object_iteration_and_indexing() {
     #[derive(Debug, Clone)]
@@ -232,3 +277,492 @@ fn test_obj_downcast() {
     let thing = value_as_obj.downcast_ref::<Thing>().unwrap();
     assert_eq!(thing.id, 42);
 }
+
+#[test]
+fn test_value_call() {
+    use minijinja::{Environment, Error, ErrorKind, State};
+
+    fn invoke(
+        state: &State,
+        func: Value,
+        args: minijinja::value::Rest<Value>,
+    ) -> Result<Value, Error> {
+        func.call(state, &args)
+    }
+
+    fn invoke_method(state: &State, obj: Value, name: &str) -> Result<Value, Error> {
+        obj.call_method(state, name, &[Value::from(2)])
+    }
+
+    let mut env = Environment::new();
+    env.add_function("invoke", invoke);
+    env.add_function("invoke_method", invoke_method);
+    env.add_global("inc", Value::from_function(|x: i64| x + 1));
+    let ctx = minijinja::context! {
+        obj => minijinja::context! { double => Value::from_function(|x: i64| x * 2) },
+    };
+
+    let rv = env
+        .render_str(
+            "{% macro hello(name) %}Hello {{ name }}!{% endmacro %}\
+             {{ invoke(hello, 'World') }}|{{ invoke(inc, 41) }}|{{ invoke_method(obj, 'double') }}",
+            &ctx,
+        )
+        .unwrap();
+    assert_eq!(rv, "Hello World!|42|4");
+
+    let err = env.render_str("{{ invoke(42) }}", &ctx).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidOperation);
+    assert!(err.to_string().contains("is not callable"));
+
+    let err = env
+        .render_str("{{ invoke_method(obj, 'missing') }}", &ctx)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnknownMethod);
+}
+
+#[test]
+fn test_list_filter_materializes_seq_object() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Default)]
+    struct Lazy(AtomicUsize);
+
+    impl SeqObject for Lazy {
+        fn get_item(&self, index: usize) -> Option<Value> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            if index < 3 {
+                Some(Value::from(index * 10))
+            } else {
+                None
+            }
+        }
+
+        fn item_count(&self) -> usize {
+            3
+        }
+    }
+
+    let lazy = Arc::new(Lazy::default());
+    let rv = minijinja::render!("{{ lazy|list }}", lazy => Value::from_seq_object(lazy.clone()));
+    assert_eq!(rv, "[0, 10, 20]");
+    let materialize_calls = lazy.0.swap(0, Ordering::Relaxed);
+
+    // further access to the list does not go back to the object
+    let rv = minijinja::render!(
+        "{% set items = lazy|list %}{{ items }}|{{ items[1] }}|{{ items|length }}|{{ items[2] }}",
+        lazy => Value::from_seq_object(lazy.clone())
+    );
+    assert_eq!(rv, "[0, 10, 20]|10|3|20");
+    assert_eq!(lazy.0.load(Ordering::Relaxed), materialize_calls);
+    assert_eq!(minijinja::render!("{{ 'abc'|list }}"), "['a', 'b', 'c']");
+}
+
+#[test]
+fn test_safe_string() {
+    let safe = Value::from_safe_string("<em>rendered</em>".into());
+    assert!(safe.is_safe());
+    assert!(!Value::from("<em>").is_safe());
+    assert!(!Value::from(42).is_safe());
+
+    let mut env = minijinja::Environment::new();
+    env.add_template("x.html", "{{ safe }}|{{ plain }}")
+        .unwrap();
+    let rv = env
+        .get_template("x.html")
+        .unwrap()
+        .render(minijinja::context!(safe, plain => "<em>"))
+        .unwrap();
+    assert_eq!(rv, "<em>rendered</em>|&lt;em&gt;");
+}
+
+#[test]
+fn test_number_accessors() {
+    assert_eq!(Value::from(-42).as_i64(), Some(-42));
+    assert_eq!(Value::from(-42).as_u64(), None);
+    assert_eq!(Value::from(42u64).as_u64(), Some(42));
+    assert_eq!(Value::from(u64::MAX).as_u64(), Some(u64::MAX));
+    assert_eq!(Value::from(u64::MAX).as_i64(), None);
+    assert_eq!(Value::from(i128::MAX).as_i64(), None);
+    assert_eq!(Value::from(2.0).as_u64(), Some(2));
+    assert_eq!(Value::from(2.5).as_i64(), None);
+    assert_eq!(Value::from(f64::NAN).as_i64(), None);
+    assert_eq!(Value::from(-3).as_f64(), Some(-3.0));
+    assert_eq!(Value::from(1.5).as_f64(), Some(1.5));
+    assert_eq!(Value::from(true).as_i64(), None);
+    assert_eq!(Value::from(true).as_f64(), None);
+    assert_eq!(Value::from(true).as_bool(), Some(true));
+    assert_eq!(Value::from(1).as_bool(), None);
+    assert_eq!(Value::from("1").as_i64(), None);
+    assert_eq!(Value::UNDEFINED.as_f64(), None);
+}
+
+#[test]
+fn test_from_iterator() {
+    use minijinja::value::ValueKind;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let produced = Arc::new(AtomicUsize::new(0));
+    let counter = produced.clone();
+    let seq = Value::from_iterator((0..5).map(move |x| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        x * 10
+    }));
+    assert_eq!(produced.load(Ordering::Relaxed), 0);
+    assert_eq!(seq.kind(), ValueKind::Seq);
+
+    // indexing only pulls as many items as needed
+    assert_eq!(seq.get_item(&Value::from(1)).unwrap(), Value::from(10));
+    assert_eq!(produced.load(Ordering::Relaxed), 2);
+
+    let env = minijinja::Environment::new();
+    let rv = env
+        .render_str(
+            "{{ seq|length }}: {% for x in seq %}{{ x }} {% endfor %}/ {{ seq|join(',') }}",
+            minijinja::context!(seq),
+        )
+        .unwrap();
+    assert_eq!(rv, "5: 0 10 20 30 40 / 0,10,20,30,40");
+    assert_eq!(produced.load(Ordering::Relaxed), 5);
+}
+
+#[test]
+fn test_scalar_eq() {
+    assert_eq!(Value::from(5), 5);
+    assert_eq!(Value::from(5u64), 5i64);
+    assert_eq!(Value::from(5.0), 5);
+    assert_eq!(5, Value::from(5.0));
+    assert_eq!(Value::from(0.5), 0.5);
+    assert_ne!(Value::from(5.5), 5);
+    assert_eq!(Value::from(i128::from(u64::MAX)), u64::MAX);
+    assert_eq!(Value::from(true), true);
+    assert_eq!(false, Value::from(false));
+    assert_eq!(Value::from("foo"), "foo");
+    assert_eq!("foo", Value::from("foo"));
+    assert_eq!(Value::from("foo"), String::from("foo"));
+    assert_eq!(String::from("foo"), Value::from("foo"));
+    assert_ne!(Value::from("1"), 1);
+    assert_ne!(Value::from(1), "1");
+    assert_ne!(Value::UNDEFINED, false);
+}
+
+#[test]
+fn test_into_iterator() {
+    let seq = Value::from(vec![1, 2]);
+    assert_eq!((&seq).into_iter().collect::<Vec<_>>(), vec![1, 2]);
+
+    let map: Value = [("a", 1), ("b", 2)].into_iter().collect();
+    let keys: Vec<Value> = (&map).into_iter().collect();
+    assert_eq!(keys, vec!["a", "b"]);
+
+    let chars: Vec<String> = (&Value::from("ab"))
+        .into_iter()
+        .map(|x| x.to_string())
+        .collect();
+    assert_eq!(chars, vec!["a", "b"]);
+
+    assert_eq!((&Value::from(42)).into_iter().count(), 0);
+    assert_eq!((&Value::from(true)).into_iter().count(), 0);
+    assert_eq!((&Value::UNDEFINED).into_iter().count(), 0);
+    assert!(Value::from(42).try_iter().is_err());
+}
+
+#[test]
+fn test_try_iter_pairs() {
+    let value = Value::from_serializable(&minijinja::context! {
+        user => minijinja::context! { name => "Peter", age => 42 },
+        active => true,
+    });
+    let mut pairs = value.try_iter_pairs().unwrap().collect::<Vec<_>>();
+    // the order depends on the preserve_order feature
+    pairs.sort_by_key(|x| x.0.to_string());
+    assert_eq!(pairs.len(), 2);
+    assert_eq!(pairs[0].0, "active");
+    assert_eq!(pairs[0].1, true);
+    assert_eq!(pairs[1].0, "user");
+
+    let mut nested = pairs[1]
+        .1
+        .try_iter_pairs()
+        .unwrap()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>();
+    nested.sort();
+    assert_eq!(nested, vec!["age=42", "name=Peter"]);
+
+    let err = Value::from(vec![1, 2]).try_iter_pairs().err().unwrap();
+    assert_eq!(err.kind(), minijinja::ErrorKind::InvalidOperation);
+    assert!(Value::from(42).try_iter_pairs().is_err());
+}
+
+#[test]
+fn test_is_iterable() {
+    assert!(Value::from(vec![1]).is_iterable());
+    assert!(Value::from("abc").is_iterable());
+    assert!(Value::from_iterator(0..3).is_iterable());
+    assert!(!Value::from(42).is_iterable());
+    assert!(!Value::from(()).is_iterable());
+    assert!(!Value::UNDEFINED.is_iterable());
+
+    // sequences from iterators can be iterated over repeatedly
+    let seq = Value::from_iterator(0..3);
+    assert_eq!(seq.try_iter().unwrap().count(), 3);
+    assert_eq!(seq.try_iter().unwrap().count(), 3);
+}
+
+#[test]
+fn test_merge() {
+    let a: Value = [("x", 1), ("y", 2)].into_iter().collect();
+    let b: Value = [("y", 3), ("z", 4)].into_iter().collect();
+    let merged = a.merge(&b).unwrap();
+    assert_eq!(merged.get_attr("x").unwrap(), 1);
+    assert_eq!(merged.get_attr("y").unwrap(), 3);
+    assert_eq!(merged.get_attr("z").unwrap(), 4);
+    // the inputs are not modified
+    assert_eq!(a.get_attr("y").unwrap(), 2);
+
+    assert!(a.merge(&Value::from(42)).is_err());
+    assert!(Value::from(vec![1]).merge(&a).is_err());
+}
+
+#[test]
+fn test_as_map() {
+    let value: Value = [("a", 1), ("b", 2)].into_iter().collect();
+    let map = value.as_map().unwrap();
+    assert_eq!(map.len(), 2);
+    assert!(!map.is_empty());
+    assert_eq!(map.get(&Value::from("a")), Some(Value::from(1)));
+    assert_eq!(map.get(&Value::from("missing")), None);
+    let mut keys = map.keys().map(|x| x.to_string()).collect::<Vec<_>>();
+    keys.sort();
+    assert_eq!(keys, ["a", "b"]);
+    let mut pairs = map
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>();
+    pairs.sort();
+    assert_eq!(pairs, ["a=1", "b=2"]);
+
+    #[derive(Debug)]
+    struct Point;
+
+    impl fmt::Display for Point {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "point")
+        }
+    }
+
+    impl Object for Point {
+        fn kind(&self) -> ObjectKind<'_> {
+            ObjectKind::Struct(self)
+        }
+    }
+
+    impl StructObject for Point {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            match name {
+                "x" => Some(Value::from(1)),
+                _ => None,
+            }
+        }
+
+        fn static_fields(&self) -> Option<&'static [&'static str]> {
+            Some(&["x"][..])
+        }
+    }
+
+    let point = Value::from_object(Point);
+    let map = point.as_map().unwrap();
+    assert_eq!(map.len(), 1);
+    assert_eq!(map.get(&Value::from("x")), Some(Value::from(1)));
+
+    assert!(Value::from(vec![1, 2]).as_map().is_none());
+    assert!(Value::from(42).as_map().is_none());
+}
+
+#[test]
+fn test_serialize_non_string_keys() {
+    let map = BTreeMap::from([(1u32, "one"), (2u32, "two")]);
+    let val = Value::from_serializable(&map);
+    assert_eq!(val.get_item(&Value::from(1)).unwrap(), Value::from("one"));
+    assert_eq!(val.get_item(&Value::from(2.0)).unwrap(), Value::from("two"));
+    assert!(val.get_item(&Value::from("1")).unwrap().is_undefined());
+    let rv = minijinja::render!("{{ map[1] }}|{{ map['1'] is undefined }}", map => map);
+    assert_snapshot!(rv, @"one|true");
+
+    let map = BTreeMap::from([(1u128, "big")]);
+    let val = Value::from_serializable(&map);
+    assert_eq!(val.get_item(&Value::from(1)).unwrap(), Value::from("big"));
+
+    #[derive(PartialEq, Eq, PartialOrd, Ord)]
+    enum Color {
+        Red,
+        Green,
+    }
+
+    impl serde::Serialize for Color {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self {
+                Color::Red => serializer.serialize_unit_variant("Color", 0, "Red"),
+                Color::Green => serializer.serialize_unit_variant("Color", 1, "Green"),
+            }
+        }
+    }
+
+    let map = BTreeMap::from([(Color::Red, 1), (Color::Green, 2)]);
+    let val = Value::from_serializable(&map);
+    assert_eq!(val.get_attr("Red").unwrap(), Value::from(1));
+    assert_eq!(val.get_attr("Green").unwrap(), Value::from(2));
+}
+
+#[test]
+fn test_map_ordering() {
+    let map: Value = [("z", 1), ("a", 2), ("m", 3)].into_iter().collect();
+    let rv = minijinja::render!(
+        "{% for k in map %}{{ k }}{% endfor %}|{% for k in {'y': 1, 'b': 2, 'y': 3} %}{{ k }}{% endfor %}",
+        map => map
+    );
+    if cfg!(feature = "preserve_order") {
+        assert_eq!(rv, "zam|yb");
+    } else {
+        assert_eq!(rv, "amz|by");
+    }
+}
+
+#[test]
+fn test_len() {
+    assert_eq!(Value::from("äöü").len(), Some(3));
+    assert_eq!(Value::from(vec![1, 2]).len(), Some(2));
+    assert_eq!(Value::from_iterator(0..5).len(), Some(5));
+    let map: Value = [("a", 1)].into_iter().collect();
+    assert_eq!(map.len(), Some(1));
+    assert_eq!(Value::from(42).len(), None);
+    assert_eq!(Value::from(()).len(), None);
+    assert_eq!(Value::UNDEFINED.len(), None);
+}
+
+#[test]
+fn test_to_pretty_string() {
+    let value = Value::from_serializable(&serde_json::json!({
+        "z": {"b": 2, "a": 1},
+        "y": [],
+        "x": "hello",
+    }));
+    assert_snapshot!(value.to_pretty_string(), @r###"
+    {
+        "x": "hello",
+        "y": [],
+        "z": {
+            "a": 1,
+            "b": 2,
+        },
+    }
+    "###);
+    assert_eq!(Value::from(42).to_pretty_string(), "42");
+    assert_eq!(Value::UNDEFINED.to_pretty_string(), "undefined");
+}
+
+#[test]
+fn test_is_true_matches_engine() {
+    let env = minijinja::Environment::new();
+    let values = vec![
+        Value::UNDEFINED,
+        Value::from(()),
+        Value::from(false),
+        Value::from(true),
+        Value::from(0),
+        Value::from(0u64),
+        Value::from(0.0),
+        Value::from(-1),
+        Value::from(0.5),
+        Value::from(""),
+        Value::from("0"),
+        Value::from(Vec::<Value>::new()),
+        Value::from(vec![0]),
+        Value::from(BTreeMap::<String, Value>::new()),
+        Value::from_iterator(0..0),
+        Value::from_iterator(0..1),
+    ];
+    for value in values {
+        let rendered = env
+            .render_str(
+                "{{ 'yes' if value else 'no' }}",
+                minijinja::context!(value => value.clone()),
+            )
+            .unwrap();
+        assert_eq!(rendered == "yes", value.is_true(), "{value:?}");
+    }
+}
+
+#[test]
+fn test_container_eq() {
+    let seq = Value::from(vec![1, 2, 3]);
+    assert_eq!(seq, Value::from(vec![1.0, 2.0, 3.0]));
+    assert_eq!(seq, Value::from_iterator(1..4));
+    assert_ne!(seq, Value::from(vec![1, 2]));
+    assert_ne!(seq, Value::from(vec![1, 2, 4]));
+
+    let map = Value::from_serializable(&serde_json::json!({"a": [1], "b": null}));
+    assert_eq!(
+        map,
+        Value::from_serializable(&serde_json::json!({"b": null, "a": [1]}))
+    );
+    assert_ne!(
+        map,
+        Value::from_serializable(&serde_json::json!({"a": [1], "c": null}))
+    );
+    assert_ne!(
+        map,
+        Value::from_serializable(&serde_json::json!({"a": [1]}))
+    );
+    assert_ne!(map, seq);
+
+    // deeply nested containers stop comparing at the depth limit
+    let nested = |depth| (0..depth).fold(Value::from(1), |acc, _| Value::from(vec![acc]));
+    assert_eq!(nested(100), nested(100));
+    assert_ne!(nested(1000), nested(1000));
+}
+
+#[test]
+fn test_self_referential_eq() {
+    use std::sync::{Mutex, Weak};
+
+    #[derive(Debug)]
+    struct Node(i32, Mutex<Weak<Node>>);
+
+    impl fmt::Display for Node {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "node")
+        }
+    }
+
+    impl Object for Node {
+        fn kind(&self) -> ObjectKind<'_> {
+            ObjectKind::Struct(self)
+        }
+    }
+
+    impl StructObject for Node {
+        fn get_field(&self, name: &str) -> Option<Value> {
+            match name {
+                "id" => Some(Value::from(self.0)),
+                "me" => self.1.lock().unwrap().upgrade().map(Value::from),
+                _ => None,
+            }
+        }
+
+        fn static_fields(&self) -> Option<&'static [&'static str]> {
+            Some(&["id", "me"][..])
+        }
+    }
+
+    let make_node = |id| {
+        let node = Arc::new(Node(id, Mutex::new(Weak::new())));
+        *node.1.lock().unwrap() = Arc::downgrade(&node);
+        node
+    };
+    let (a, b, c) = (make_node(1), make_node(1), make_node(2));
+    assert_eq!(Value::from(a.clone()), Value::from(a.clone()));
+    assert_eq!(Value::from(a.clone()), Value::from(b));
+    assert_ne!(Value::from(a), Value::from(c));
+}